@@ -7,6 +7,7 @@ use activitypub_federation::{
     actix_web::inbox::receive_activity,
     config::{Data, FederationConfig, FederationMiddleware},
     fetch::webfinger::{build_webfinger_response, extract_webfinger_name},
+    inbox::ReceiveOutcome,
     protocol::context::WithContext,
     traits::Object,
     FEDERATION_CONTENT_TYPE,
@@ -54,7 +55,7 @@ pub async fn http_post_user_inbox(
     request: HttpRequest,
     body: Bytes,
     data: Data<DatabaseHandle>,
-) -> Result<HttpResponse, Error> {
+) -> Result<ReceiveOutcome, Error> {
     receive_activity::<WithContext<PersonAcceptedActivities>, DbUser, DatabaseHandle>(
         request, body, &data,
     )
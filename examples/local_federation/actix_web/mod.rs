@@ -1,6 +1,13 @@
 use crate::error::Error;
-use actix_web::ResponseError;
+use actix_web::{http::StatusCode, ResponseError};
 
 pub(crate) mod http;
 
-impl ResponseError for Error {}
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        self.0
+            .downcast_ref::<activitypub_federation::error::Error>()
+            .map(|e| e.status_code())
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
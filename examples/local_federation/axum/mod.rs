@@ -7,6 +7,11 @@ pub mod http;
 
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
-        (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", self.0)).into_response()
+        let status = self
+            .0
+            .downcast_ref::<activitypub_federation::error::Error>()
+            .map(|e| e.status_code())
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        (status, format!("{}", self.0)).into_response()
     }
 }
@@ -4,6 +4,7 @@
 
 use crate::{
     config::Data,
+    crypto::CryptoBackend,
     error::Error,
     http_signatures::sign_request,
     reqwest_shim::ResponseExt,
@@ -19,15 +20,20 @@ use background_jobs::{
     MaxRetries,
     WorkerConfig,
 };
+use bytes::Bytes;
 use http::{header::HeaderName, HeaderMap, HeaderValue};
 use httpdate::fmt_http_date;
 use itertools::Itertools;
+use lru::LruCache;
 use reqwest_middleware::ClientWithMiddleware;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::{
     fmt::Debug,
     future::Future,
+    num::NonZeroUsize,
     pin::Pin,
+    sync::{Arc, Mutex},
     time::{Duration, SystemTime},
 };
 use tracing::{debug, info, warn};
@@ -52,10 +58,127 @@ where
     Datatype: Clone,
     ActorType: Actor,
 {
-    let config = &data.config;
-    let actor_id = activity.actor();
-    let activity_id = activity.id();
     let activity_serialized = serde_json::to_string_pretty(&activity)?;
+    queue_activity(activity.id(), activity_serialized, actor, inboxes, data).await?;
+    Ok(())
+}
+
+/// Forward the activity which is currently being received to the given inboxes, as described in
+/// [ActivityPub §7.1.2](https://www.w3.org/TR/activitypub/#inbox-forwarding).
+///
+/// Call this from [ActivityHandler::receive] for activities which are addressed to a local
+/// collection, for example a reply which is addressed to the followers of a local user.
+///
+/// - `collection`: The local collection whose members should receive the activity
+/// - `actor`: Local actor who forwards the activity, and signs the HTTP request
+/// - `inboxes`: Inboxes of the collection members
+///
+/// The activity is sent exactly as it was received (see [Data::received_activity]), so that an
+/// embedded Linked Data signature or integrity proof stays valid for the recipients.
+///
+/// Returns `Ok(false)` without sending anything if the activity is not addressed to `collection`
+/// in `to`, `cc` or `audience`, if the collection or activity is not local resp. remote, if none
+/// of `inReplyTo`, `object`, `target` and `tag` references an object owned by this server, or if
+/// the activity was already forwarded before. Embedded objects are searched for local references
+/// up to [MAX_FORWARD_DEPTH] levels deep. Inboxes on the domain of the activity actor are
+/// skipped, as the origin server already has the activity. Together this prevents forwarding
+/// loops between servers.
+///
+/// Activities which were rewritten by an [inbox policy](crate::inbox_policy) are never
+/// forwarded: the rewritten version isn't signed by the original actor, and sending the original
+/// version would bypass the policy.
+pub async fn forward_activity<Datatype, ActorType>(
+    collection: &Url,
+    actor: &ActorType,
+    inboxes: Vec<Url>,
+    data: &Data<Datatype>,
+) -> Result<bool, Error>
+where
+    Datatype: Clone,
+    ActorType: Actor,
+{
+    let config = &data.config;
+    let received = data
+        .received_activity()
+        .ok_or_else(|| Error::other(anyhow!("No activity is being received")))?;
+    if received.is_rewritten()
+        || !config.is_local_url(collection)
+        || config.is_local_url(received.id())
+        || config.is_local_url(received.actor())
+    {
+        return Ok(false);
+    }
+    let json: Value = serde_json::from_slice(received.body()).map_err(Error::other)?;
+    let addressed = ["to", "cc", "audience"].iter().any(|field| {
+        field_values(&json, field)
+            .iter()
+            .any(|v| v.as_str() == Some(collection.as_str()))
+    });
+    if !addressed
+        || !references_local_object(&json, data, 0)
+        || !config.forwarded_activities.insert(received.id().clone())
+    {
+        return Ok(false);
+    }
+
+    let origin = received.actor().domain();
+    let inboxes = inboxes
+        .into_iter()
+        .filter(|i| i.domain() != origin)
+        .collect();
+    let body = String::from_utf8(received.body().to_vec()).map_err(Error::other)?;
+    queue_activity(received.id(), body, actor, inboxes, data).await?;
+    Ok(true)
+}
+
+/// Maximum depth of embedded objects which is searched for local references when forwarding
+pub const MAX_FORWARD_DEPTH: usize = 5;
+
+fn field_values<'a>(json: &'a Value, field: &str) -> Vec<&'a Value> {
+    match json.get(field) {
+        Some(Value::Array(values)) => values.iter().collect(),
+        Some(value) => vec![value],
+        None => vec![],
+    }
+}
+
+/// Checks if `inReplyTo`, `object`, `target` or `tag` of `json` reference an object owned by
+/// this server, either by id or by embedding it. Embedded objects are searched recursively.
+fn references_local_object<T: Clone>(json: &Value, data: &Data<T>, depth: usize) -> bool {
+    let is_local = |value: Option<&Value>| {
+        value
+            .and_then(Value::as_str)
+            .and_then(|id| Url::parse(id).ok())
+            .is_some_and(|id| data.config.is_local_url(&id))
+    };
+    if depth > MAX_FORWARD_DEPTH {
+        return false;
+    }
+    ["inReplyTo", "object", "target", "tag"]
+        .iter()
+        .any(|field| {
+            field_values(json, field).into_iter().any(|value| {
+                is_local(Some(value))
+                    || is_local(value.get("id"))
+                    || is_local(value.get("href"))
+                    || (value.is_object() && references_local_object(value, data, depth + 1))
+            })
+        })
+}
+
+async fn queue_activity<Datatype, ActorType>(
+    activity_id: &Url,
+    activity_serialized: String,
+    actor: &ActorType,
+    inboxes: Vec<Url>,
+    data: &Data<Datatype>,
+) -> Result<(), anyhow::Error>
+where
+    Datatype: Clone,
+    ActorType: Actor,
+{
+    let config = &data.config;
+    let key_id = actor.private_key_id();
     let private_key = actor
         .private_key_pem()
         .expect("Actor for sending activity has private key");
@@ -76,7 +199,7 @@ where
         }
 
         let message = SendActivityTask {
-            actor_id: actor_id.clone(),
+            key_id: key_id.clone(),
             activity_id: activity_id.clone(),
             inbox,
             activity: activity_serialized.clone(),
@@ -84,7 +207,13 @@ where
             http_signature_compat: config.http_signature_compat,
         };
         if config.debug {
-            let res = do_send(message, &config.client, config.request_timeout).await;
+            let res = do_send(
+                message,
+                &config.client,
+                config.request_timeout,
+                config.crypto_backend.clone(),
+            )
+            .await;
             // Don't fail on error, as we intentionally do some invalid actions in tests, to verify that
             // they are rejected on the receiving side. These errors shouldn't bubble up to make the API
             // call fail. This matches the behaviour in production.
@@ -110,9 +239,91 @@ where
     Ok(())
 }
 
+/// Incoming activity whose signature was verified, in the exact form in which it was received.
+/// Available through [Data::received_activity] while the activity is being verified and
+/// received.
+#[derive(Clone, Debug)]
+pub struct RawActivity {
+    id: Url,
+    actor: Url,
+    body: Bytes,
+    /// The activity as it was rewritten by inbox policies, if any
+    rewritten: Option<Bytes>,
+}
+
+impl RawActivity {
+    pub(crate) fn new(id: Url, actor: Url, body: Bytes) -> Self {
+        RawActivity {
+            id,
+            actor,
+            body,
+            rewritten: None,
+        }
+    }
+
+    pub(crate) fn with_rewritten(mut self, json: Bytes) -> Self {
+        self.rewritten = Some(json);
+        self
+    }
+
+    /// Id of the activity
+    pub fn id(&self) -> &Url {
+        &self.id
+    }
+
+    /// Actor of the activity, whose signature was verified
+    pub fn actor(&self) -> &Url {
+        &self.actor
+    }
+
+    /// The unmodified request body
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Whether the activity was rewritten by an [inbox policy](crate::inbox_policy). Handlers
+    /// then receive the rewritten activity, which differs from [RawActivity::body]. Such
+    /// activities are not forwarded, see [forward_activity].
+    pub fn is_rewritten(&self) -> bool {
+        self.rewritten.is_some()
+    }
+
+    /// The activity as it is passed to handlers, after inbox policies were applied
+    pub(crate) fn activity_json(&self) -> &[u8] {
+        self.rewritten.as_ref().unwrap_or(&self.body)
+    }
+}
+
+/// Maximum number of activity ids which are remembered to detect duplicates
+const RECENT_ACTIVITIES_SIZE: usize = 10_000;
+
+/// Ids of activities which were recently forwarded or received. Clones share the same data.
+#[derive(Clone)]
+pub(crate) struct RecentActivities(Arc<Mutex<LruCache<Url, ()>>>);
+
+impl RecentActivities {
+    /// Returns true if the activity was not seen before.
+    pub(crate) fn insert(&self, id: Url) -> bool {
+        let mut ids = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        ids.put(id, ()).is_none()
+    }
+
+    pub(crate) fn contains(&self, id: &Url) -> bool {
+        let ids = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        ids.contains(id)
+    }
+}
+
+impl Default for RecentActivities {
+    fn default() -> Self {
+        let size = NonZeroUsize::new(RECENT_ACTIVITIES_SIZE).unwrap_or(NonZeroUsize::MIN);
+        RecentActivities(Arc::new(Mutex::new(LruCache::new(size))))
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct SendActivityTask {
-    actor_id: Url,
+    key_id: String,
     activity_id: Url,
     activity: String,
     inbox: Url,
@@ -133,7 +344,7 @@ impl ActixJob for SendActivityTask {
     const BACKOFF: Backoff = Backoff::Exponential(60);
 
     fn run(self, state: Self::State) -> Self::Future {
-        Box::pin(async move { do_send(self, &state.client, state.timeout).await })
+        Box::pin(async move { do_send(self, &state.client, state.timeout, state.crypto).await })
     }
 }
 
@@ -141,6 +352,7 @@ async fn do_send(
     task: SendActivityTask,
     client: &ClientWithMiddleware,
     timeout: Duration,
+    crypto: Box<dyn CryptoBackend>,
 ) -> Result<(), anyhow::Error> {
     debug!("Sending {} to {}", task.activity_id, task.inbox);
     let request_builder = client
@@ -149,10 +361,11 @@ async fn do_send(
         .headers(generate_request_headers(&task.inbox));
     let request = sign_request(
         request_builder,
-        task.actor_id,
+        task.key_id,
         task.activity,
         task.private_key,
         task.http_signature_compat,
+        crypto,
     )
     .await?;
     let response = client.execute(request).await;
@@ -220,6 +433,7 @@ pub(crate) fn create_activity_queue(
     client: ClientWithMiddleware,
     worker_count: u64,
     request_timeout: Duration,
+    crypto: Box<dyn CryptoBackend>,
     debug: bool,
 ) -> Manager {
     // queue is not used in debug mod, so dont create any workers to avoid log spam
@@ -229,6 +443,7 @@ pub(crate) fn create_activity_queue(
     WorkerConfig::new_managed(Storage::new(ActixTimer), move |_| QueueState {
         client: client.clone(),
         timeout: request_timeout,
+        crypto: crypto.clone(),
     })
     .register::<SendActivityTask>()
     .set_worker_count("default", worker_count)
@@ -239,4 +454,93 @@ pub(crate) fn create_activity_queue(
 struct QueueState {
     client: ClientWithMiddleware,
     timeout: Duration,
+    crypto: Box<dyn CryptoBackend>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::FederationConfig,
+        traits::tests::{DbConnection, DB_USER},
+    };
+    use serde_json::json;
+
+    #[actix_rt::test]
+    async fn test_forward_activity() {
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(DbConnection)
+            .debug(true)
+            .build()
+            .unwrap();
+        let followers = Url::parse("http://localhost:8002/u/alice/followers").unwrap();
+        let received_reply = |id: &str, to: &Url, in_reply_to: &str| {
+            let body = json!({
+                "id": id,
+                "to": ["https://www.w3.org/ns/activitystreams#Public"],
+                "cc": to,
+                "object": { "type": "Note", "inReplyTo": in_reply_to }
+            });
+            RawActivity::new(
+                Url::parse(id).unwrap(),
+                Url::parse("https://example.com/u/bob").unwrap(),
+                body.to_string().into(),
+            )
+        };
+        let received = |id: &str, to: &Url| received_reply(id, to, "http://localhost:8002/post/1");
+
+        // No activity is being received
+        let data = config.to_request_data();
+        assert!(forward_activity(&followers, &*DB_USER, vec![], &data)
+            .await
+            .is_err());
+
+        let data = data.with_received_activity(received("https://example.com/1", &followers));
+        assert_eq!(
+            forward_activity(&followers, &*DB_USER, vec![], &data).await,
+            Ok(true)
+        );
+        // Each activity is forwarded only once
+        assert_eq!(
+            forward_activity(&followers, &*DB_USER, vec![], &data).await,
+            Ok(false)
+        );
+
+        // Not addressed to the collection
+        let other = Url::parse("http://localhost:8002/u/carol/followers").unwrap();
+        let data = data.with_received_activity(received("https://example.com/2", &other));
+        assert_eq!(
+            forward_activity(&followers, &*DB_USER, vec![], &data).await,
+            Ok(false)
+        );
+
+        // Collection is not local
+        let remote = Url::parse("https://example.com/u/bob/followers").unwrap();
+        let data = data.with_received_activity(received("https://example.com/3", &remote));
+        assert_eq!(
+            forward_activity(&remote, &*DB_USER, vec![], &data).await,
+            Ok(false)
+        );
+
+        // Doesn't reference a local object
+        let data = data.with_received_activity(received_reply(
+            "https://example.com/4",
+            &followers,
+            "https://example.com/post/1",
+        ));
+        assert_eq!(
+            forward_activity(&followers, &*DB_USER, vec![], &data).await,
+            Ok(false)
+        );
+
+        // Rewritten by an inbox policy
+        let raw = received("https://example.com/5", &followers);
+        let rewritten = raw.body().to_vec().into();
+        let data = data.with_received_activity(raw.with_rewritten(rewritten));
+        assert_eq!(
+            forward_activity(&followers, &*DB_USER, vec![], &data).await,
+            Ok(false)
+        );
+    }
 }
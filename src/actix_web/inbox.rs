@@ -3,52 +3,55 @@
 use crate::{
     config::Data,
     error::Error,
-    fetch::object_id::ObjectId,
-    http_signatures::{verify_inbox_hash, verify_signature},
+    inbox::{self, ReceiveOutcome},
     traits::{ActivityHandler, Actor, Object},
 };
-use actix_web::{web::Bytes, HttpRequest, HttpResponse};
+use actix_web::{body::BoxBody, web::Bytes, HttpRequest, HttpResponse, Responder};
 use serde::de::DeserializeOwned;
-use tracing::debug;
+use std::fmt::Display;
 
 /// Handles incoming activities, verifying HTTP signatures and other checks
 ///
 /// After successful validation, activities are passed to respective [trait@ActivityHandler].
+/// If the [incoming queue](crate::incoming_queue) is enabled, this happens in the background
+/// and the outcome is `deferred`. See [inbox::receive_activity] for details.
+///
+/// The returned [ReceiveOutcome] can be used directly as response.
 pub async fn receive_activity<Activity, ActorT, Datatype>(
     request: HttpRequest,
     body: Bytes,
     data: &Data<Datatype>,
-) -> Result<HttpResponse, <Activity as ActivityHandler>::Error>
+) -> Result<ReceiveOutcome, <Activity as ActivityHandler>::Error>
 where
     Activity: ActivityHandler<DataType = Datatype> + DeserializeOwned + Send + 'static,
-    ActorT: Object<DataType = Datatype> + Actor + Send + 'static,
+    ActorT: Object<DataType = Datatype> + Actor + Send + Sync + 'static,
     for<'de2> <ActorT as Object>::Kind: serde::Deserialize<'de2>,
     <Activity as ActivityHandler>::Error: From<anyhow::Error>
         + From<Error>
         + From<<ActorT as Object>::Error>
         + From<serde_json::Error>,
     <ActorT as Object>::Error: From<Error> + From<anyhow::Error>,
-    Datatype: Clone,
+    <Activity as ActivityHandler>::Error: Display + 'static,
+    Datatype: Clone + Send + Sync + 'static,
 {
-    verify_inbox_hash(request.headers().get("Digest"), &body)?;
-
-    let activity: Activity = serde_json::from_slice(&body)?;
-    data.config.verify_url_and_domain(&activity).await?;
-    let actor = ObjectId::<ActorT>::from(activity.actor().clone())
-        .dereference(data)
-        .await?;
-
-    verify_signature(
-        request.headers(),
-        request.method(),
-        request.uri(),
-        actor.public_key_pem(),
-    )?;
-
-    debug!("Receiving activity {}", activity.id().to_string());
-    activity.verify(data).await?;
-    activity.receive(data).await?;
-    Ok(HttpResponse::Ok().finish())
+    let mut builder = http::Request::builder()
+        .method(request.method().clone())
+        .uri(request.uri().clone());
+    for (name, value) in request.headers() {
+        builder = builder.header(name, value);
+    }
+    let request = builder.body(body).map_err(Error::other)?;
+
+    inbox::receive_activity::<Activity, ActorT, Datatype>(request, data).await
+}
+
+/// Responds with [ReceiveOutcome::status] and empty body
+impl Responder for ReceiveOutcome {
+    type Body = BoxBody;
+
+    fn respond_to(self, _req: &HttpRequest) -> HttpResponse<Self::Body> {
+        HttpResponse::build(self.status()).finish()
+    }
 }
 
 #[cfg(test)]
@@ -56,12 +59,17 @@ mod test {
     use super::*;
     use crate::{
         config::FederationConfig,
+        crypto::default_crypto_backend,
+        fetch::object_id::ObjectId,
         http_signatures::sign_request,
-        traits::tests::{DbConnection, DbUser, Follow, DB_USER_KEYPAIR},
+        inbox_policy::{InboxPolicy, PolicyAction, PolicyActor},
+        traits::tests::{DbConnection, DbUser, Follow, DB_USER, DB_USER_KEYPAIR},
+        FEDERATION_CONTENT_TYPE,
     };
-    use actix_web::test::TestRequest;
+    use actix_web::{http::StatusCode, test::TestRequest};
     use reqwest::Client;
     use reqwest_middleware::ClientWithMiddleware;
+    use serde_json::Value;
 
     #[actix_rt::test]
     async fn test_receive_activity() {
@@ -75,6 +83,76 @@ mod test {
         .unwrap();
     }
 
+    #[actix_rt::test]
+    async fn test_receive_activity_queued() {
+        let (body, incoming_request, _) = setup_receive_test().await;
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(DbConnection)
+            .debug(true)
+            .incoming_queue(true)
+            .build()
+            .unwrap();
+        let res = receive_activity::<Follow, DbUser, DbConnection>(
+            incoming_request.to_http_request(),
+            body.into(),
+            &config.to_request_data(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+
+        // Signature is still verified before queueing
+        let (_, incoming_request, _) = setup_receive_test().await;
+        let err = receive_activity::<Follow, DbUser, DbConnection>(
+            incoming_request.to_http_request(),
+            "invalid".into(),
+            &config.to_request_data(),
+        )
+        .await
+        .err()
+        .unwrap();
+        let e = err.root_cause().downcast_ref::<Error>().unwrap();
+        assert_eq!(e, &Error::ActivityBodyDigestInvalid)
+    }
+
+    #[actix_rt::test]
+    async fn test_receive_activity_rejected_by_policy() {
+        #[derive(Clone)]
+        struct RejectFollows;
+
+        #[async_trait::async_trait]
+        impl InboxPolicy for RejectFollows {
+            async fn filter(&self, _: &Value, actor: &PolicyActor, kind: &str) -> PolicyAction {
+                assert_eq!(actor.id(), &DB_USER.id());
+                assert!(actor.downcast_ref::<DbUser>().is_some());
+                match kind {
+                    "Follow" => PolicyAction::Reject("Follows are disabled".to_string()),
+                    _ => PolicyAction::Accept,
+                }
+            }
+        }
+
+        let (body, incoming_request, _) = setup_receive_test().await;
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(DbConnection)
+            .debug(true)
+            .inbox_policies(vec![Box::new(RejectFollows)])
+            .build()
+            .unwrap();
+        let err = receive_activity::<Follow, DbUser, DbConnection>(
+            incoming_request.to_http_request(),
+            body.into(),
+            &config.to_request_data(),
+        )
+        .await
+        .err()
+        .unwrap();
+        let e = err.root_cause().downcast_ref::<Error>().unwrap();
+        assert!(matches!(e, Error::ActivityRejected(m) if m == "Follows are disabled"))
+    }
+
     #[actix_rt::test]
     async fn test_receive_activity_invalid_body_signature() {
         let (_, incoming_request, config) = setup_receive_test().await;
@@ -108,22 +186,117 @@ mod test {
         assert_eq!(e, &Error::ActivitySignatureInvalid)
     }
 
-    async fn setup_receive_test() -> (String, TestRequest, FederationConfig<DbConnection>) {
-        let request_builder =
-            ClientWithMiddleware::from(Client::default()).post("https://example.com/inbox");
-        let activity = Follow {
+    #[cfg(feature = "ld-signatures")]
+    #[actix_rt::test]
+    async fn test_receive_forwarded_activity() {
+        use crate::{http_signatures::generate_actor_keypair, ld_signatures::create_ld_signature};
+        use serde_json::json;
+        use url::Url;
+
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(DbConnection)
+            .debug(true)
+            .ld_signatures(true)
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+        let mut activity = serde_json::to_value(follow()).unwrap();
+        activity["@context"] = json!("https://www.w3.org/ns/activitystreams");
+        let key_id = Url::parse(&DB_USER.private_key_id()).unwrap();
+        let activity = create_ld_signature(&activity, &key_id, &DB_USER_KEYPAIR.private_key, &data)
+            .await
+            .unwrap();
+
+        // HTTP signature is made by a different actor, who forwarded the activity
+        let forwarder_key_id = "https://example.com/forwarder#main-key";
+        let forwarder_keypair = generate_actor_keypair().unwrap();
+        let body = activity.to_string();
+        let incoming_request =
+            signed_request(&body, forwarder_key_id, &forwarder_keypair.private_key).await;
+        receive_activity::<Follow, DbUser, DbConnection>(
+            incoming_request.to_http_request(),
+            body.into(),
+            &config.to_request_data(),
+        )
+        .await
+        .unwrap();
+
+        let mut tampered = activity.clone();
+        tampered["object"] = json!("http://localhost:126");
+        let body = tampered.to_string();
+        let incoming_request =
+            signed_request(&body, forwarder_key_id, &forwarder_keypair.private_key).await;
+        let err = receive_activity::<Follow, DbUser, DbConnection>(
+            incoming_request.to_http_request(),
+            body.into(),
+            &config.to_request_data(),
+        )
+        .await
+        .err()
+        .unwrap();
+        let e = err.root_cause().downcast_ref::<Error>().unwrap();
+        assert_eq!(e, &Error::UrlVerificationError("Domains do not match"))
+    }
+
+    #[cfg(feature = "integrity-proofs")]
+    #[actix_rt::test]
+    async fn test_receive_activity_with_integrity_proof() {
+        use crate::{
+            http_signatures::generate_actor_keypair,
+            integrity_proofs::Signed,
+            protocol::context::WithContext,
+            traits::tests::DB_USER_ED25519_KEYPAIR,
+        };
+        use url::Url;
+
+        let (_, _, config) = setup_receive_test().await;
+        let key_id = Url::parse(&DB_USER.assertion_methods()[0].id).unwrap();
+        let activity = Signed::new(
+            WithContext::new_default(follow()),
+            &key_id,
+            &DB_USER_ED25519_KEYPAIR.private_key,
+        )
+        .unwrap();
+
+        // HTTP signature is made by a different actor, who forwarded the activity
+        let forwarder_keypair = generate_actor_keypair().unwrap();
+        let body = serde_json::to_string(&activity).unwrap();
+        let incoming_request = signed_request(
+            &body,
+            "https://example.com/forwarder#main-key",
+            &forwarder_keypair.private_key,
+        )
+        .await;
+        receive_activity::<WithContext<Follow>, DbUser, DbConnection>(
+            incoming_request.to_http_request(),
+            body.into(),
+            &config.to_request_data(),
+        )
+        .await
+        .unwrap();
+    }
+
+    fn follow() -> Follow {
+        Follow {
             actor: ObjectId::parse("http://localhost:123").unwrap(),
             object: ObjectId::parse("http://localhost:124").unwrap(),
             kind: Default::default(),
             id: "http://localhost:123/1".try_into().unwrap(),
-        };
-        let body = serde_json::to_string(&activity).unwrap();
+        }
+    }
+
+    async fn signed_request(body: &str, key_id: &str, private_key: &str) -> TestRequest {
+        let request_builder = ClientWithMiddleware::from(Client::default())
+            .post("https://example.com/inbox")
+            .header("content-type", FEDERATION_CONTENT_TYPE);
         let outgoing_request = sign_request(
             request_builder,
-            activity.actor.into_inner(),
+            key_id.to_string(),
             body.to_string(),
-            DB_USER_KEYPAIR.private_key.clone(),
+            private_key.to_string(),
             false,
+            default_crypto_backend(),
         )
         .await
         .unwrap();
@@ -131,7 +304,17 @@ mod test {
         for h in outgoing_request.headers() {
             incoming_request = incoming_request.append_header(h);
         }
+        incoming_request
+    }
 
+    async fn setup_receive_test() -> (String, TestRequest, FederationConfig<DbConnection>) {
+        let body = serde_json::to_string(&follow()).unwrap();
+        let incoming_request = signed_request(
+            &body,
+            &DB_USER.private_key_id(),
+            &DB_USER_KEYPAIR.private_key,
+        )
+        .await;
         let config = FederationConfig::builder()
             .domain("localhost:8002")
             .app_data(DbConnection)
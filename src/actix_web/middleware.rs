@@ -1,12 +1,24 @@
 use crate::config::{Data, FederationConfig, FederationMiddleware};
 use actix_web::{
-    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    dev::{
+        forward_ready,
+        Extensions,
+        Payload,
+        Service,
+        ServiceRequest,
+        ServiceResponse,
+        Transform,
+    },
+    web::PayloadConfig,
     Error,
     FromRequest,
     HttpMessage,
     HttpRequest,
 };
-use std::future::{ready, Ready};
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+};
 
 impl<S, B, T> Transform<S, ServiceRequest> for FederationMiddleware<T>
 where
@@ -22,9 +34,13 @@ where
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
+        // Allow inbox bodies up to the configured size in the `Bytes` extractor
+        let mut app_data = Extensions::new();
+        app_data.insert(PayloadConfig::new(self.0.max_inbox_body_size));
         ready(Ok(FederationService {
             service,
             config: self.0.clone(),
+            app_data: Rc::new(app_data),
         }))
     }
 }
@@ -39,6 +55,7 @@ where
 {
     service: S,
     config: FederationConfig<T>,
+    app_data: Rc<Extensions>,
 }
 
 impl<S, B, T> Service<ServiceRequest> for FederationService<S, T>
@@ -54,8 +71,9 @@ where
 
     forward_ready!(service);
 
-    fn call(&self, req: ServiceRequest) -> Self::Future {
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
         req.extensions_mut().insert(self.config.clone());
+        req.add_data_container(self.app_data.clone());
 
         self.service.call(req)
     }
@@ -74,3 +92,38 @@ impl<T: Clone + 'static> FromRequest for Data<T> {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::tests::DbConnection;
+    use actix_web::{http::StatusCode, test, web, web::Bytes, App};
+
+    #[actix_rt::test]
+    async fn test_payload_limit() {
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(DbConnection)
+            .debug(true)
+            .max_inbox_body_size(512 * 1024)
+            .build()
+            .unwrap();
+        let app = test::init_service(App::new().wrap(FederationMiddleware::new(config)).route(
+            "/inbox",
+            web::post().to(|body: Bytes| async move { body.len().to_string() }),
+        ))
+        .await;
+        let request = |size: usize| {
+            test::TestRequest::post()
+                .uri("/inbox")
+                .set_payload(vec![b'a'; size])
+                .to_request()
+        };
+
+        // Larger than the default limit of actix-web
+        let res = test::call_service(&app, request(300 * 1024)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = test::call_service(&app, request(600 * 1024)).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -5,8 +5,7 @@
 use crate::{
     config::Data,
     error::Error,
-    fetch::object_id::ObjectId,
-    http_signatures::{verify_inbox_hash, verify_signature},
+    inbox::{self, ReceiveOutcome},
     traits::{ActivityHandler, Actor, Object},
 };
 use axum::{
@@ -16,54 +15,63 @@ use axum::{
     http::{Request, StatusCode},
     response::{IntoResponse, Response},
 };
-use http::{HeaderMap, Method, Uri};
+use bytes::{Buf, BufMut, BytesMut};
+use http::{header::CONTENT_LENGTH, HeaderMap, Method, Uri};
 use serde::de::DeserializeOwned;
-use tracing::debug;
+use std::fmt::Display;
 
 /// Handles incoming activities, verifying HTTP signatures and other checks
+///
+/// The returned [ReceiveOutcome] describes what happened to the activity, and can be used
+/// directly as response. See [inbox::receive_activity] for details.
 pub async fn receive_activity<Activity, ActorT, Datatype>(
     activity_data: ActivityData,
     data: &Data<Datatype>,
-) -> Result<(), <Activity as ActivityHandler>::Error>
+) -> Result<ReceiveOutcome, <Activity as ActivityHandler>::Error>
 where
     Activity: ActivityHandler<DataType = Datatype> + DeserializeOwned + Send + 'static,
-    ActorT: Object<DataType = Datatype> + Actor + Send + 'static,
+    ActorT: Object<DataType = Datatype> + Actor + Send + Sync + 'static,
     for<'de2> <ActorT as Object>::Kind: serde::Deserialize<'de2>,
     <Activity as ActivityHandler>::Error: From<anyhow::Error>
         + From<Error>
         + From<<ActorT as Object>::Error>
         + From<serde_json::Error>,
     <ActorT as Object>::Error: From<Error> + From<anyhow::Error>,
-    Datatype: Clone,
+    <Activity as ActivityHandler>::Error: Display + 'static,
+    Datatype: Clone + Send + Sync + 'static,
 {
-    verify_inbox_hash(activity_data.headers.get("Digest"), &activity_data.body)?;
-
-    let activity: Activity = serde_json::from_slice(&activity_data.body)?;
-    data.config.verify_url_and_domain(&activity).await?;
-    let actor = ObjectId::<ActorT>::from(activity.actor().clone())
-        .dereference(data)
-        .await?;
-
-    verify_signature(
-        &activity_data.headers,
-        &activity_data.method,
-        &activity_data.uri,
-        actor.public_key_pem(),
-    )?;
-
-    debug!("Receiving activity {}", activity.id().to_string());
-    activity.verify(data).await?;
-    activity.receive(data).await?;
-    Ok(())
+    let mut request = Request::new(activity_data.body);
+    *request.method_mut() = activity_data.method;
+    *request.uri_mut() = activity_data.uri;
+    *request.headers_mut() = activity_data.headers;
+
+    inbox::receive_activity::<Activity, ActorT, Datatype>(request, data).await
+}
+
+/// Responds with [ReceiveOutcome::status] and empty body
+impl IntoResponse for ReceiveOutcome {
+    fn into_response(self) -> Response {
+        self.status().into_response()
+    }
 }
 
+/// Fallback if [FederationMiddleware](crate::config::FederationMiddleware) is not registered,
+/// identical to the default of
+/// [max_inbox_body_size](crate::config::FederationConfigBuilder::max_inbox_body_size)
+const DEFAULT_MAX_INBOX_BODY_SIZE: usize = 1024 * 1024;
+
+/// Inserted into request extensions by the middleware, as [ActivityData] can't access the
+/// generic config
+#[derive(Clone, Copy)]
+pub(crate) struct MaxInboxBodySize(pub(crate) usize);
+
 /// Contains all data that is necessary to receive an activity from an HTTP request
 #[derive(Debug)]
 pub struct ActivityData {
     headers: HeaderMap,
     method: Method,
     uri: Uri,
-    body: Vec<u8>,
+    body: Bytes,
 }
 
 #[async_trait]
@@ -79,19 +87,194 @@ where
 
     async fn from_request(req: Request<B>, _state: &S) -> Result<Self, Self::Rejection> {
         let (parts, body) = req.into_parts();
+        let max_size = parts
+            .extensions
+            .get::<MaxInboxBodySize>()
+            .map(|m| m.0)
+            .unwrap_or(DEFAULT_MAX_INBOX_BODY_SIZE);
+        let too_large = || Error::ActivityBodyTooLarge.into_response();
+        let content_length = parts
+            .headers
+            .get(CONTENT_LENGTH)
+            .and_then(|l| l.to_str().ok()?.parse::<usize>().ok());
+        if content_length.unwrap_or_default() > max_size {
+            return Err(too_large());
+        }
 
-        // this wont work if the body is an long running stream
-        let bytes = hyper::body::to_bytes(body)
-            .await
-            .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response())?;
+        // Read the body in chunks, to abort as soon as it gets too large
+        let mut body = Box::pin(body);
+        let mut bytes = BytesMut::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.map_err(|err| {
+                (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+            })?;
+            if bytes.len() + chunk.remaining() > max_size {
+                return Err(too_large());
+            }
+            bytes.put(chunk);
+        }
 
         Ok(Self {
             headers: parts.headers,
             method: parts.method,
             uri: parts.uri,
-            body: bytes.to_vec(),
+            body: bytes.freeze(),
         })
     }
 }
 
-// TODO: copy tests from actix-web inbox and implement for axum as well
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        config::FederationConfig,
+        crypto::default_crypto_backend,
+        fetch::object_id::ObjectId,
+        http_signatures::sign_request,
+        traits::tests::{DbConnection, DbUser, Follow, DB_USER, DB_USER_KEYPAIR},
+        FEDERATION_CONTENT_TYPE,
+    };
+    use axum::body::Body;
+    use reqwest::Client;
+    use reqwest_middleware::ClientWithMiddleware;
+
+    #[actix_rt::test]
+    async fn test_receive_activity() {
+        let (body, config) = setup_receive_test(false);
+        let activity_data = signed_request(&body, "/inbox").await;
+        let data = config.to_request_data();
+        let outcome = receive_activity::<Follow, DbUser, DbConnection>(activity_data, &data)
+            .await
+            .unwrap();
+        let mut expected = ReceiveOutcome {
+            id: "http://localhost:123/1".parse().unwrap(),
+            kind: "Follow".to_string(),
+            actor: "http://localhost:123".parse().unwrap(),
+            duplicate: false,
+            actor_deleted: false,
+            deferred: false,
+            request_count: 0,
+        };
+        assert_eq!(outcome, expected);
+        assert_eq!(outcome.into_response().status(), StatusCode::OK);
+
+        // Same activity is handled again, and reported as duplicate
+        let activity_data = signed_request(&body, "/inbox").await;
+        let outcome = receive_activity::<Follow, DbUser, DbConnection>(activity_data, &data)
+            .await
+            .unwrap();
+        expected.duplicate = true;
+        assert_eq!(outcome, expected);
+    }
+
+    #[actix_rt::test]
+    async fn test_receive_activity_queued() {
+        let (body, config) = setup_receive_test(true);
+        let activity_data = signed_request(&body, "/inbox").await;
+        let outcome = receive_activity::<Follow, DbUser, DbConnection>(
+            activity_data,
+            &config.to_request_data(),
+        )
+        .await
+        .unwrap();
+        assert!(outcome.deferred);
+        assert_eq!(outcome.into_response().status(), StatusCode::ACCEPTED);
+    }
+
+    #[actix_rt::test]
+    async fn test_receive_activity_invalid_body_signature() {
+        let (body, config) = setup_receive_test(false);
+        let mut activity_data = signed_request(&body, "/inbox").await;
+        activity_data.body = "invalid".into();
+        let err = receive_activity::<Follow, DbUser, DbConnection>(
+            activity_data,
+            &config.to_request_data(),
+        )
+        .await
+        .err()
+        .unwrap();
+        let e = err.root_cause().downcast_ref::<Error>().unwrap();
+        assert_eq!(e, &Error::ActivityBodyDigestInvalid)
+    }
+
+    #[actix_rt::test]
+    async fn test_receive_activity_invalid_path() {
+        let (body, config) = setup_receive_test(false);
+        let mut activity_data = signed_request(&body, "/inbox").await;
+        activity_data.uri = Uri::from_static("/wrong");
+        let err = receive_activity::<Follow, DbUser, DbConnection>(
+            activity_data,
+            &config.to_request_data(),
+        )
+        .await
+        .err()
+        .unwrap();
+        let e = err.root_cause().downcast_ref::<Error>().unwrap();
+        assert_eq!(e, &Error::ActivitySignatureInvalid)
+    }
+
+    #[actix_rt::test]
+    async fn test_receive_activity_body_too_large() {
+        let request = Request::post("/inbox")
+            .extension(MaxInboxBodySize(10))
+            .body(Body::from("x".repeat(11)))
+            .unwrap();
+        let rejection = ActivityData::from_request(request, &()).await.unwrap_err();
+        assert_eq!(rejection.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[actix_rt::test]
+    async fn test_receive_activity_invalid_content_type() {
+        let (body, config) = setup_receive_test(false);
+        let mut activity_data = signed_request(&body, "/inbox").await;
+        activity_data.headers.remove("content-type");
+        let err = receive_activity::<Follow, DbUser, DbConnection>(
+            activity_data,
+            &config.to_request_data(),
+        )
+        .await
+        .err()
+        .unwrap();
+        let e = err.root_cause().downcast_ref::<Error>().unwrap();
+        assert_eq!(e, &Error::ActivityContentTypeInvalid(String::new()))
+    }
+
+    async fn signed_request(body: &str, path: &str) -> ActivityData {
+        let request_builder = ClientWithMiddleware::from(Client::default())
+            .post(format!("https://example.com{path}"))
+            .header("content-type", FEDERATION_CONTENT_TYPE);
+        let outgoing_request = sign_request(
+            request_builder,
+            DB_USER.private_key_id(),
+            body.to_string(),
+            DB_USER_KEYPAIR.private_key.clone(),
+            false,
+            default_crypto_backend(),
+        )
+        .await
+        .unwrap();
+        let mut request = Request::post(path);
+        for (name, value) in outgoing_request.headers() {
+            request = request.header(name, value);
+        }
+        let request = request.body(Body::from(body.to_string())).unwrap();
+        ActivityData::from_request(request, &()).await.unwrap()
+    }
+
+    fn setup_receive_test(incoming_queue: bool) -> (String, FederationConfig<DbConnection>) {
+        let follow = Follow {
+            actor: ObjectId::parse("http://localhost:123").unwrap(),
+            object: ObjectId::parse("http://localhost:124").unwrap(),
+            kind: Default::default(),
+            id: "http://localhost:123/1".try_into().unwrap(),
+        };
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(DbConnection)
+            .debug(true)
+            .incoming_queue(incoming_queue)
+            .build()
+            .unwrap();
+        (serde_json::to_string(&follow).unwrap(), config)
+    }
+}
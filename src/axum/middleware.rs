@@ -1,4 +1,7 @@
-use crate::config::{Data, FederationConfig, FederationMiddleware};
+use crate::{
+    axum::inbox::MaxInboxBodySize,
+    config::{Data, FederationConfig, FederationMiddleware},
+};
 use axum::{async_trait, body::Body, extract::FromRequestParts, http::Request, response::Response};
 use http::{request::Parts, StatusCode};
 use std::task::{Context, Poll};
@@ -39,6 +42,9 @@ where
 
     fn call(&mut self, mut request: Request<Body>) -> Self::Future {
         request.extensions_mut().insert(self.config.clone());
+        request
+            .extensions_mut()
+            .insert(MaxInboxBodySize(self.config.max_inbox_body_size));
         self.inner.call(request)
     }
 }
@@ -15,8 +15,12 @@
 //! ```
 
 use crate::{
-    activity_queue::create_activity_queue,
+    activity_queue::{create_activity_queue, RawActivity, RecentActivities},
+    crypto::{default_crypto_backend, CryptoBackend},
     error::Error,
+    fetch::cache::ObjectCache,
+    inbox_policy::InboxPolicy,
+    incoming_queue::{default_failure_handler, IncomingFailureHandler, IncomingQueue},
     protocol::verification::verify_domains_match,
     traits::ActivityHandler,
 };
@@ -24,9 +28,21 @@ use async_trait::async_trait;
 use background_jobs::Manager;
 use derive_builder::Builder;
 use dyn_clone::{clone_trait_object, DynClone};
+use hyper::client::connect::dns::Name;
+use ipnet::IpNet;
+use reqwest::{
+    dns::{Addrs, Resolve, Resolving},
+    redirect::Policy,
+};
 use reqwest_middleware::ClientWithMiddleware;
 use serde::de::DeserializeOwned;
+#[cfg(feature = "ld-signatures")]
+use serde_json::Value;
+#[cfg(feature = "ld-signatures")]
+use std::{collections::HashMap, sync::RwLock};
 use std::{
+    io,
+    net::{IpAddr, SocketAddr},
     ops::Deref,
     sync::{
         atomic::{AtomicU32, Ordering},
@@ -34,7 +50,8 @@ use std::{
     },
     time::Duration,
 };
-use url::Url;
+use tokio::net::lookup_host;
+use url::{Host, Url};
 
 /// Configuration for this library, with various federation related settings
 #[derive(Builder, Clone)]
@@ -50,9 +67,24 @@ pub struct FederationConfig<T: Clone> {
     /// [crate::fetch::object_id::ObjectId] for more details.
     #[builder(default = "20")]
     pub(crate) http_fetch_limit: u32,
-    #[builder(default = "reqwest::Client::default().into()")]
+    /// Maximum number of objects which
+    /// [ObjectId::dereference_many](crate::fetch::object_id::ObjectId::dereference_many) fetches
+    /// from the same host at the same time
+    #[builder(default = "2")]
+    pub(crate) max_concurrent_fetches_per_host: usize,
+    #[builder(default = "default_client(
+        self.debug.unwrap_or(false),
+        self.private_network_allowlist.clone().unwrap_or_default(),
+    )")]
     /// HTTP client used for all outgoing requests. Middleware can be used to add functionality
     /// like log tracing or retry of failed requests.
+    ///
+    /// The default client uses [PublicAddressResolver], so that it doesn't connect to private
+    /// addresses outside of debug mode. Custom clients should use it as well.
+    ///
+    /// Redirects of fetch requests are followed and verified by this library, so custom clients
+    /// must be built with `redirect(reqwest::redirect::Policy::none())`. Fetches which were
+    /// redirected by the client itself are rejected.
     pub(crate) client: ClientWithMiddleware,
     /// Number of worker threads for sending outgoing activities
     #[builder(default = "64")]
@@ -66,6 +98,37 @@ pub struct FederationConfig<T: Clone> {
     /// use the same as timeout when sending
     #[builder(default = "Duration::from_secs(10)")]
     pub(crate) request_timeout: Duration,
+    /// Maximum size of incoming activities in bytes. Larger requests are rejected with
+    /// `413 Payload Too Large`.
+    #[builder(default = "1024 * 1024")]
+    pub(crate) max_inbox_body_size: usize,
+    /// Moderation policies which are applied to incoming activities in the given order, see
+    /// [crate::inbox_policy].
+    #[builder(default = "vec![]")]
+    pub(crate) inbox_policies: Vec<Box<dyn InboxPolicy>>,
+    /// Check that the `id` of fetched objects matches the url they were served from, after
+    /// following redirects. Objects whose id is on the same domain are fetched again from their
+    /// id, while ids on other domains are rejected with [Error::FetchedIdMismatch] unless the
+    /// object has an [integrity proof](crate::integrity_proofs) from the domain of its id.
+    /// Objects without a valid id are rejected with [Error::FetchedIdMissing]. Only disable this
+    /// for testing.
+    #[builder(default = "true")]
+    pub(crate) verify_fetched_id: bool,
+    /// Private networks which remote urls may resolve to. By default fetching from loopback,
+    /// private and link-local addresses is rejected, to prevent server-side request forgery
+    /// against internal services. These checks are skipped in debug mode.
+    #[builder(default = "vec![]")]
+    pub(crate) private_network_allowlist: Vec<IpNet>,
+    /// Time after which remote objects are refetched when they are dereferenced, see
+    /// [Object::last_refreshed_at](crate::traits::Object::last_refreshed_at). Can be overridden
+    /// per object type with [Object::refetch_interval](crate::traits::Object::refetch_interval).
+    #[builder(default = "Duration::from_secs(24 * 60 * 60)")]
+    pub(crate) refetch_interval: Duration,
+    /// Cache for objects which are dereferenced with
+    /// [ObjectId::dereference](crate::fetch::object_id::ObjectId::dereference), see
+    /// [Object::cache_copy](crate::traits::Object::cache_copy). Disabled by default.
+    #[builder(default = "None", setter(strip_option))]
+    pub(crate) object_cache: Option<ObjectCache>,
     /// Function used to verify that urls are valid, See [UrlVerifier] for details.
     #[builder(default = "Box::new(DefaultUrlVerifier())")]
     pub(crate) url_verifier: Box<dyn UrlVerifier + Sync>,
@@ -75,6 +138,48 @@ pub struct FederationConfig<T: Clone> {
     /// <https://git.pleroma.social/pleroma/pleroma/-/issues/2939>
     #[builder(default = "false")]
     pub(crate) http_signature_compat: bool,
+    /// Backend used for signing outgoing and verifying incoming HTTP signatures. Defaults to
+    /// the one selected by cargo features, see [crate::crypto].
+    #[builder(default = "default_crypto_backend()")]
+    pub(crate) crypto_backend: Box<dyn CryptoBackend>,
+    /// Verify Linked Data Signatures on incoming activities which are not signed by their actor
+    /// over HTTP. This is necessary to receive activities which were forwarded by another server,
+    /// see [crate::ld_signatures].
+    #[cfg(feature = "ld-signatures")]
+    #[builder(default = "false")]
+    pub(crate) ld_signatures: bool,
+    /// Additional JSON-LD contexts for verifying Linked Data Signatures, keyed by url
+    #[cfg(feature = "ld-signatures")]
+    #[builder(setter(skip))]
+    pub(crate) ld_contexts: Arc<RwLock<HashMap<String, Value>>>,
+    /// Process incoming activities in a background queue, see [crate::incoming_queue]. Inbox
+    /// handlers only verify the signature and respond with `202 Accepted`.
+    #[builder(default = "false")]
+    pub(crate) incoming_queue: bool,
+    /// Number of workers for processing incoming activities, if the incoming queue is enabled
+    #[builder(default = "16")]
+    pub(crate) incoming_worker_count: u64,
+    /// Number of retries for incoming activities which failed to process in the background
+    #[builder(default = "3")]
+    pub(crate) incoming_retry_count: u32,
+    /// Maximum number of activities waiting in the incoming queue. When it is full, further
+    /// activities are rejected with `503 Service Unavailable`.
+    #[builder(default = "1000")]
+    pub(crate) incoming_queue_size: usize,
+    /// Called for incoming activities which failed to process in the background even after
+    /// retrying, see [IncomingFailureHandler] for details.
+    #[builder(default = "default_failure_handler()")]
+    pub(crate) incoming_failure_handler: Box<dyn IncomingFailureHandler + Sync>,
+    /// Queue for processing incoming activities in the background
+    #[builder(setter(skip))]
+    pub(crate) incoming_activity_queue: IncomingQueue,
+    /// Ids of activities which were forwarded recently, see
+    /// [forward_activity](crate::activity_queue::forward_activity)
+    #[builder(setter(skip))]
+    pub(crate) forwarded_activities: RecentActivities,
+    /// Ids of activities which were received recently, to report duplicates
+    #[builder(setter(skip))]
+    pub(crate) received_activities: RecentActivities,
     /// Queue for sending outgoing activities. Only optional to make builder work, its always
     /// present once constructed.
     #[builder(setter(skip))]
@@ -110,6 +215,7 @@ impl<T: Clone> FederationConfig<T> {
         Data {
             config: self.clone(),
             request_counter: Default::default(),
+            received_activity: None,
         }
     }
 
@@ -153,10 +259,40 @@ impl<T: Clone> FederationConfig<T> {
         Ok(())
     }
 
+    /// Resolves the host of a remote url, and rejects it if any of its addresses is not public.
+    /// This is checked for every request when fetching objects, including redirects.
+    ///
+    /// The client resolves the host again when connecting, so this alone doesn't protect against
+    /// DNS servers which return different addresses for each query. The default client also
+    /// checks the addresses it connects to, see [PublicAddressResolver].
+    pub(crate) async fn verify_url_address(&self, url: &Url) -> Result<(), Error> {
+        if self.debug || (url.domain().is_some() && self.is_local_url(url)) {
+            return Ok(());
+        }
+        let port = url.port_or_known_default().unwrap_or(443);
+        let addresses: Vec<IpAddr> = match url.host() {
+            Some(Host::Ipv4(ip)) => vec![ip.into()],
+            Some(Host::Ipv6(ip)) => vec![ip.into()],
+            Some(Host::Domain(domain)) => lookup_host((domain, port))
+                .await
+                .map_err(Error::other)?
+                .map(|addr| addr.ip())
+                .collect(),
+            None => return Err(Error::UrlVerificationError("Url must have a domain")),
+        };
+        let allowed = |ip: &IpAddr| is_allowed_ip(ip, &self.private_network_allowlist);
+        if !addresses.iter().all(allowed) {
+            return Err(Error::UrlVerificationError(
+                "Url resolves to a private ip address",
+            ));
+        }
+        Ok(())
+    }
+
     /// Returns true if the url refers to this instance. Handles hostnames like `localhost:8540` for
     /// local debugging.
     pub(crate) fn is_local_url(&self, url: &Url) -> bool {
-        let mut domain = url.domain().expect("id has domain").to_string();
+        let mut domain = url.host_str().unwrap_or_default().to_string();
         if let Some(port) = url.port() {
             domain = format!("{}:{}", domain, port);
         }
@@ -167,6 +303,133 @@ impl<T: Clone> FederationConfig<T> {
     pub fn domain(&self) -> &str {
         &self.domain
     }
+
+    /// Adds a JSON-LD context document for verifying Linked Data Signatures. Remote contexts are
+    /// never fetched, so activities which reference a context other than the built-in ones are
+    /// only accepted if it was added here. The document must contain a `@context` field.
+    #[cfg(feature = "ld-signatures")]
+    pub fn add_ld_context(&self, url: &str, document: Value) {
+        self.ld_contexts
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(url.to_string(), document);
+    }
+
+    #[cfg(feature = "ld-signatures")]
+    pub(crate) fn ld_context(&self, url: &str) -> Option<Value> {
+        self.ld_contexts
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(url)
+            .cloned()
+    }
+}
+
+/// Default HTTP client, which doesn't follow redirects so that each one can be verified. Outside
+/// of debug mode it only connects to public addresses, and those in `allowlist`.
+fn default_client(debug: bool, allowlist: Vec<IpNet>) -> ClientWithMiddleware {
+    let mut builder = reqwest::Client::builder().redirect(Policy::none());
+    if !debug {
+        builder = builder.dns_resolver(Arc::new(PublicAddressResolver::new(allowlist)));
+    }
+    builder
+        .build()
+        .expect("Failed to build default HTTP client")
+        .into()
+}
+
+/// DNS resolver for [reqwest::ClientBuilder::dns_resolver] which rejects hosts that resolve to
+/// an address which is not public.
+///
+/// Addresses are checked when the client connects, so unlike the check before each fetch this
+/// can't be bypassed by a DNS server which returns a different address for each query.
+///
+/// ```
+/// # use activitypub_federation::config::PublicAddressResolver;
+/// # use std::sync::Arc;
+/// let client = reqwest::Client::builder()
+///     .redirect(reqwest::redirect::Policy::none())
+///     .dns_resolver(Arc::new(PublicAddressResolver::new(vec![])))
+///     .build()?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct PublicAddressResolver {
+    allowlist: Arc<Vec<IpNet>>,
+}
+
+impl PublicAddressResolver {
+    /// Create a new resolver which also allows private addresses in the given networks, see
+    /// [FederationConfigBuilder::private_network_allowlist].
+    pub fn new(allowlist: Vec<IpNet>) -> Self {
+        PublicAddressResolver {
+            allowlist: Arc::new(allowlist),
+        }
+    }
+}
+
+impl Resolve for PublicAddressResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let allowlist = self.allowlist.clone();
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> = lookup_host((name.as_str(), 0)).await?.collect();
+            if !addresses
+                .iter()
+                .all(|addr| is_allowed_ip(&addr.ip(), &allowlist))
+            {
+                let message = format!("{} resolves to a private ip address", name.as_str());
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, message).into());
+            }
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Returns true if the address is public, or in one of the allowed private networks
+fn is_allowed_ip(ip: &IpAddr, allowlist: &[IpNet]) -> bool {
+    is_public_ip(ip) || allowlist.iter().any(|n| n.contains(ip))
+}
+
+/// Returns false for addresses which are loopback, private, link-local or otherwise not
+/// reachable on the public internet
+pub(crate) fn is_public_ip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // "this network", shared address space, benchmarking and reserved
+                || a == 0
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 198 && (18..20).contains(&b))
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ipv4) = ip.to_ipv4_mapped() {
+                return is_public_ip(&ipv4.into());
+            }
+            let first = ip.segments()[0];
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                // unique local
+                || (first & 0xfe00) == 0xfc00
+                // link-local and deprecated site-local
+                || (first & 0xffc0) == 0xfe80
+                || (first & 0xffc0) == 0xfec0
+                // documentation
+                || (first == 0x2001 && ip.segments()[1] == 0x0db8)
+                // NAT64 and 6to4, which embed IPv4 addresses
+                || ip.segments()[..6] == [0x64, 0xff9b, 0, 0, 0, 0]
+                || (first == 0x64 && ip.segments()[1] == 0xff9b && ip.segments()[2] == 1)
+                || first == 0x2002)
+        }
+    }
 }
 
 impl<T: Clone> FederationConfigBuilder<T> {
@@ -180,6 +443,7 @@ impl<T: Clone> FederationConfigBuilder<T> {
             config.client.clone(),
             config.worker_count,
             config.request_timeout,
+            config.crypto_backend.clone(),
             config.debug,
         );
         config.activity_queue = Some(Arc::new(queue));
@@ -258,7 +522,8 @@ clone_trait_object!(UrlVerifier);
 /// <https://www.w3.org/TR/activitypub/#security-recursive-objects>
 pub struct Data<T: Clone> {
     pub(crate) config: FederationConfig<T>,
-    pub(crate) request_counter: AtomicU32,
+    pub(crate) request_counter: Arc<AtomicU32>,
+    pub(crate) received_activity: Option<RawActivity>,
 }
 
 impl<T: Clone> Data<T> {
@@ -277,12 +542,29 @@ impl<T: Clone> Data<T> {
         Data {
             config: self.config.clone(),
             request_counter: Default::default(),
+            received_activity: self.received_activity.clone(),
         }
     }
     /// Total number of outgoing HTTP requests made with this data.
     pub fn request_count(&self) -> u32 {
         self.request_counter.load(Ordering::Relaxed)
     }
+
+    /// The incoming activity which is currently being received, in its original form. This can
+    /// be used to [forward](crate::activity_queue::forward_activity) it to other servers.
+    pub fn received_activity(&self) -> Option<&RawActivity> {
+        self.received_activity.as_ref()
+    }
+
+    /// Returns a new instance of `Data` for handling `activity`. The request counter is shared,
+    /// so that requests made with either instance are counted in both.
+    pub(crate) fn with_received_activity(&self, activity: RawActivity) -> Self {
+        Data {
+            config: self.config.clone(),
+            request_counter: self.request_counter.clone(),
+            received_activity: Some(activity),
+        }
+    }
 }
 
 impl<T: Clone> Deref for Data<T> {
@@ -303,3 +585,104 @@ impl<T: Clone> FederationMiddleware<T> {
         FederationMiddleware(config)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_is_public_ip() {
+        let public = ["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"];
+        for ip in public {
+            assert!(is_public_ip(&ip.parse().unwrap()), "{ip}");
+        }
+        let private = [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::7f00:1",
+            "64:ff9b:1::a00:1",
+            "2002:7f00:1::",
+        ];
+        for ip in private {
+            assert!(!is_public_ip(&ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_with_received_activity() {
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(())
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+        data.request_counter.fetch_add(1, Ordering::SeqCst);
+        let url = Url::parse("https://example.com/1").unwrap();
+        let received =
+            data.with_received_activity(RawActivity::new(url.clone(), url, Default::default()));
+        received.request_counter.fetch_add(1, Ordering::SeqCst);
+        assert_eq!((data.request_count(), received.request_count()), (2, 2));
+    }
+
+    #[actix_rt::test]
+    async fn test_verify_url_address() {
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(())
+            .build()
+            .unwrap();
+        let url = |u: &str| Url::parse(u).unwrap();
+        assert!(config
+            .verify_url_address(&url("https://169.254.169.254/latest/meta-data"))
+            .await
+            .is_err());
+        assert!(config
+            .verify_url_address(&url("https://[::1]/"))
+            .await
+            .is_err());
+        assert!(config
+            .verify_url_address(&url("https://localhost/"))
+            .await
+            .is_err());
+
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(())
+            .private_network_allowlist(vec!["127.0.0.0/8".parse().unwrap()])
+            .build()
+            .unwrap();
+        assert!(config
+            .verify_url_address(&url("https://127.0.0.1/"))
+            .await
+            .is_ok());
+        assert!(config
+            .verify_url_address(&url("https://10.0.0.1/"))
+            .await
+            .is_err());
+    }
+
+    #[actix_rt::test]
+    async fn test_public_address_resolver() {
+        let name = || Name::from_str("localhost").unwrap();
+        assert!(PublicAddressResolver::default()
+            .resolve(name())
+            .await
+            .is_err());
+        let allowlist = vec!["127.0.0.0/8".parse().unwrap(), "::1/128".parse().unwrap()];
+        let mut addresses = PublicAddressResolver::new(allowlist)
+            .resolve(name())
+            .await
+            .unwrap();
+        assert!(addresses.all(|addr| addr.ip().is_loopback()));
+    }
+}
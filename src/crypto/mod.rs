@@ -0,0 +1,172 @@
+//! Pluggable backends for generating keypairs, signing and verifying signatures
+//!
+//! All cryptographic operations which are needed for HTTP signatures go through the
+//! [CryptoBackend] trait. The library ships with two implementations which can be selected with
+//! cargo features:
+//!
+//! - `openssl` (enabled by default): [OpensslBackend], uses the system OpenSSL library. HTTP
+//!   requests use native TLS.
+//! - `rustcrypto`: [RustCryptoBackend], pure Rust implementation based on the `rsa` crate. HTTP
+//!   requests use rustls, so that together with `default-features = false` nothing links to
+//!   OpenSSL. This is useful for static musl builds or cross compilation, where system OpenSSL is
+//!   unavailable.
+//!
+//! If both features are enabled, OpenSSL is used by default. A different backend can be set with
+//! [FederationConfigBuilder::crypto_backend](crate::config::FederationConfigBuilder::crypto_backend),
+//! for example to keep private keys in an external signer.
+//!
+//! Parsing PEM keys is relatively expensive, so both included backends keep a bounded cache of
+//! parsed keys. Entries are keyed by a hash of the PEM string, which means that a changed key is
+//! never confused with the previous one. Outdated keys are evicted once the cache is full.
+
+use crate::{error::Error, http_signatures::Keypair};
+use dyn_clone::{clone_trait_object, DynClone};
+use lru::LruCache;
+use sha2::{Digest, Sha256};
+use std::{
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+};
+
+#[cfg(feature = "openssl")]
+mod openssl;
+#[cfg(feature = "rustcrypto")]
+mod rustcrypto;
+
+#[cfg(feature = "openssl")]
+pub use self::openssl::OpensslBackend;
+#[cfg(feature = "rustcrypto")]
+pub use self::rustcrypto::RustCryptoBackend;
+
+#[cfg(not(any(feature = "openssl", feature = "rustcrypto")))]
+compile_error!(
+    "Either feature `openssl` or `rustcrypto` must be enabled for activitypub_federation"
+);
+
+/// Cryptographic operations used for HTTP signatures.
+///
+/// Keys are passed in PEM format, exactly as they are stored by [Actor](crate::traits::Actor)
+/// implementations. All signatures use RSASSA-PKCS1-v1_5 with SHA-256, which is the algorithm
+/// used by all major fediverse platforms.
+pub trait CryptoBackend: DynClone + Send + Sync {
+    /// Generate a new random keypair.
+    fn generate_keypair(&self) -> Result<Keypair, Error>;
+
+    /// Sign `message` with the given private key, returning the raw signature bytes.
+    fn sign(&self, private_key_pem: &str, message: &[u8]) -> Result<Vec<u8>, Error>;
+
+    /// Returns `Ok(true)` if `signature` is a valid signature of `message` by the given public key.
+    fn verify(&self, public_key_pem: &str, message: &[u8], signature: &[u8])
+        -> Result<bool, Error>;
+}
+
+clone_trait_object!(CryptoBackend);
+
+/// Returns the backend which is selected by cargo features.
+pub fn default_crypto_backend() -> Box<dyn CryptoBackend> {
+    #[cfg(feature = "openssl")]
+    return Box::<OpensslBackend>::default();
+    #[cfg(all(feature = "rustcrypto", not(feature = "openssl")))]
+    return Box::<RustCryptoBackend>::default();
+}
+
+/// Default number of parsed keys which are kept in memory by each backend.
+pub const DEFAULT_KEY_CACHE_SIZE: usize = 1000;
+
+/// Bounded cache of parsed keys, keyed by hash of the PEM string. Clones share the same cache.
+pub(crate) struct KeyCache<K> {
+    keys: Arc<Mutex<LruCache<[u8; 32], Arc<K>>>>,
+}
+
+impl<K> KeyCache<K> {
+    pub(crate) fn new(size: usize) -> Self {
+        let size = NonZeroUsize::new(size).unwrap_or(NonZeroUsize::MIN);
+        KeyCache {
+            keys: Arc::new(Mutex::new(LruCache::new(size))),
+        }
+    }
+
+    /// Returns the parsed key from cache, or calls `parse` and stores the result.
+    pub(crate) fn get_or_parse<F>(&self, pem: &str, parse: F) -> Result<Arc<K>, Error>
+    where
+        F: FnOnce(&str) -> Result<K, Error>,
+    {
+        let hash: [u8; 32] = Sha256::digest(pem.as_bytes()).into();
+        if let Some(key) = self.lock().get(&hash) {
+            return Ok(key.clone());
+        }
+        // Parse without holding the lock, so that other threads are not blocked
+        let key = Arc::new(parse(pem)?);
+        self.lock().put(hash, key.clone());
+        Ok(key)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, LruCache<[u8; 32], Arc<K>>> {
+        // The cache can't be left in an inconsistent state, so it is fine to ignore poisoning
+        self.keys.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.lock().len()
+    }
+}
+
+impl<K> Clone for KeyCache<K> {
+    fn clone(&self) -> Self {
+        KeyCache {
+            keys: self.keys.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sign_and_verify(signer: &dyn CryptoBackend, verifier: &dyn CryptoBackend) {
+        let keypair = signer.generate_keypair().unwrap();
+        let message = b"(request-target): post /inbox";
+        let signature = signer.sign(&keypair.private_key, message).unwrap();
+        assert!(verifier
+            .verify(&keypair.public_key, message, &signature)
+            .unwrap());
+        assert!(!verifier
+            .verify(&keypair.public_key, b"something else", &signature)
+            .unwrap());
+    }
+
+    #[test]
+    fn test_default_backend() {
+        let backend = default_crypto_backend();
+        sign_and_verify(backend.as_ref(), backend.as_ref());
+    }
+
+    #[cfg(all(feature = "openssl", feature = "rustcrypto"))]
+    #[test]
+    fn test_backends_compatible() {
+        sign_and_verify(&OpensslBackend::default(), &RustCryptoBackend::default());
+        sign_and_verify(&RustCryptoBackend::default(), &OpensslBackend::default());
+    }
+
+    #[test]
+    fn test_key_cache() {
+        let cache = KeyCache::new(2);
+        let mut parsed = 0;
+        for pem in ["a", "b", "a", "c", "a", "b"] {
+            cache
+                .get_or_parse(pem, |p| {
+                    parsed += 1;
+                    Ok(p.to_string())
+                })
+                .unwrap();
+        }
+        // "b" was evicted when "c" was inserted
+        assert_eq!(parsed, 4);
+        assert_eq!(cache.len(), 2);
+
+        let err = cache.get_or_parse("invalid", |_| Err(Error::NotFound));
+        assert!(err.is_err());
+        assert_eq!(cache.len(), 2);
+    }
+}
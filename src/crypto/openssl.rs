@@ -0,0 +1,73 @@
+use crate::{
+    crypto::{CryptoBackend, KeyCache, DEFAULT_KEY_CACHE_SIZE},
+    error::Error,
+    http_signatures::Keypair,
+};
+use openssl::{
+    hash::MessageDigest,
+    pkey::{PKey, Private, Public},
+    rsa::Rsa,
+    sign::{Signer, Verifier},
+};
+
+/// Crypto backend which uses the system OpenSSL library.
+#[derive(Clone)]
+pub struct OpensslBackend {
+    private_keys: KeyCache<PKey<Private>>,
+    public_keys: KeyCache<PKey<Public>>,
+}
+
+impl OpensslBackend {
+    /// Create a new backend which keeps up to `key_cache_size` parsed private and public keys
+    /// in memory.
+    pub fn new(key_cache_size: usize) -> Self {
+        OpensslBackend {
+            private_keys: KeyCache::new(key_cache_size),
+            public_keys: KeyCache::new(key_cache_size),
+        }
+    }
+}
+
+impl Default for OpensslBackend {
+    fn default() -> Self {
+        OpensslBackend::new(DEFAULT_KEY_CACHE_SIZE)
+    }
+}
+
+impl CryptoBackend for OpensslBackend {
+    fn generate_keypair(&self) -> Result<Keypair, Error> {
+        let rsa = Rsa::generate(2048).map_err(Error::other)?;
+        let pkey = PKey::from_rsa(rsa).map_err(Error::other)?;
+        let public_key = pkey.public_key_to_pem().map_err(Error::other)?;
+        let private_key = pkey.private_key_to_pem_pkcs8().map_err(Error::other)?;
+        Ok(Keypair {
+            private_key: String::from_utf8(private_key).map_err(Error::other)?,
+            public_key: String::from_utf8(public_key).map_err(Error::other)?,
+        })
+    }
+
+    fn sign(&self, private_key_pem: &str, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let private_key = self.private_keys.get_or_parse(private_key_pem, |pem| {
+            PKey::private_key_from_pem(pem.as_bytes()).map_err(Error::other)
+        })?;
+        let mut signer =
+            Signer::new(MessageDigest::sha256(), &private_key).map_err(Error::other)?;
+        signer.update(message).map_err(Error::other)?;
+        signer.sign_to_vec().map_err(Error::other)
+    }
+
+    fn verify(
+        &self,
+        public_key_pem: &str,
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<bool, Error> {
+        let public_key = self.public_keys.get_or_parse(public_key_pem, |pem| {
+            PKey::public_key_from_pem(pem.as_bytes()).map_err(Error::other)
+        })?;
+        let mut verifier =
+            Verifier::new(MessageDigest::sha256(), &public_key).map_err(Error::other)?;
+        verifier.update(message).map_err(Error::other)?;
+        verifier.verify(signature).map_err(Error::other)
+    }
+}
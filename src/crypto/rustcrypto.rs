@@ -0,0 +1,85 @@
+use crate::{
+    crypto::{CryptoBackend, KeyCache, DEFAULT_KEY_CACHE_SIZE},
+    error::Error,
+    http_signatures::Keypair,
+};
+use rsa::{
+    pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey},
+    pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey, LineEnding},
+    rand_core::OsRng,
+    sha2::{Digest, Sha256},
+    Pkcs1v15Sign,
+    RsaPrivateKey,
+    RsaPublicKey,
+};
+
+/// Pure Rust crypto backend, which doesn't require system OpenSSL.
+#[derive(Clone)]
+pub struct RustCryptoBackend {
+    private_keys: KeyCache<RsaPrivateKey>,
+    public_keys: KeyCache<RsaPublicKey>,
+}
+
+impl RustCryptoBackend {
+    /// Create a new backend which keeps up to `key_cache_size` parsed private and public keys
+    /// in memory.
+    pub fn new(key_cache_size: usize) -> Self {
+        RustCryptoBackend {
+            private_keys: KeyCache::new(key_cache_size),
+            public_keys: KeyCache::new(key_cache_size),
+        }
+    }
+}
+
+impl Default for RustCryptoBackend {
+    fn default() -> Self {
+        RustCryptoBackend::new(DEFAULT_KEY_CACHE_SIZE)
+    }
+}
+
+impl CryptoBackend for RustCryptoBackend {
+    fn generate_keypair(&self) -> Result<Keypair, Error> {
+        let private_key = RsaPrivateKey::new(&mut OsRng, 2048).map_err(Error::other)?;
+        let public_key = private_key
+            .to_public_key()
+            .to_public_key_pem(LineEnding::LF)
+            .map_err(Error::other)?;
+        let private_key = private_key
+            .to_pkcs8_pem(LineEnding::LF)
+            .map_err(Error::other)?;
+        Ok(Keypair {
+            private_key: private_key.to_string(),
+            public_key,
+        })
+    }
+
+    fn sign(&self, private_key_pem: &str, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let private_key = self.private_keys.get_or_parse(private_key_pem, |pem| {
+            // Accept both PKCS#8 and the older PKCS#1 format, same as OpenSSL
+            RsaPrivateKey::from_pkcs8_pem(pem)
+                .or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem))
+                .map_err(Error::other)
+        })?;
+        let hashed = Sha256::digest(message);
+        private_key
+            .sign(Pkcs1v15Sign::new::<Sha256>(), &hashed)
+            .map_err(Error::other)
+    }
+
+    fn verify(
+        &self,
+        public_key_pem: &str,
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<bool, Error> {
+        let public_key = self.public_keys.get_or_parse(public_key_pem, |pem| {
+            RsaPublicKey::from_public_key_pem(pem)
+                .or_else(|_| RsaPublicKey::from_pkcs1_pem(pem))
+                .map_err(Error::other)
+        })?;
+        let hashed = Sha256::digest(message);
+        Ok(public_key
+            .verify(Pkcs1v15Sign::new::<Sha256>(), &hashed, signature)
+            .is_ok())
+    }
+}
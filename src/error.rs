@@ -1,6 +1,8 @@
 //! Error messages returned by this library
 
 use displaydoc::Display;
+use http::StatusCode;
+use url::Url;
 
 /// Error messages returned by this library
 #[derive(thiserror::Error, Debug, Display)]
@@ -11,14 +13,38 @@ pub enum Error {
     RequestLimit,
     /// Response body limit was reached during fetch
     ResponseBodyLimit,
-    /// Object to be fetched was deleted
+    /// Object to be fetched was deleted, or was not found on the remote server
     ObjectDeleted,
     /// {0}
     UrlVerificationError(&'static str),
+    /// Fetched object has unsupported content type: {0}
+    FetchContentTypeInvalid(String),
+    /// Fetched object has id {0} which doesn't match the url it was fetched from
+    FetchedIdMismatch(Url),
+    /// Fetched object has no valid id
+    FetchedIdMissing,
+    /// Fetch was redirected to {0}, which is on a different domain
+    FetchRedirectInvalid(Url),
+    /// Incoming activity body exceeds the maximum size
+    ActivityBodyTooLarge,
+    /// Incoming activity has unsupported content type: {0}
+    ActivityContentTypeInvalid(String),
     /// Incoming activity has invalid digest for body
     ActivityBodyDigestInvalid,
+    /// Incoming activity uses unsupported digest algorithm: {0}
+    ActivityBodyDigestUnsupported(String),
+    /// Failed to parse incoming activity: {0}
+    ActivityParseError(serde_json::Error),
     /// Incoming activity has invalid signature
     ActivitySignatureInvalid,
+    /// Incoming activity was signed with key {0}, which is not a key of its actor
+    ActivitySignatureKeyUnknown(String),
+    /// Incoming activity was rejected: {0}
+    ActivityRejected(String),
+    /// Incoming activity queue is full
+    IncomingQueueFull,
+    /// Object has invalid integrity proof
+    IntegrityProofInvalid,
     /// Failed to resolve actor via webfinger
     WebfingerResolveFailed,
     /// Other errors which are not explicitly handled
@@ -27,6 +53,36 @@ pub enum Error {
 }
 
 impl Error {
+    /// HTTP status code which should be returned when this error occurs while handling a request.
+    ///
+    /// Errors caused by the sender such as an invalid signature or a blocked domain result in a
+    /// `4xx` status, so that the remote server doesn't retry delivery. Errors of type
+    /// [Error::Other] are treated as internal server errors, unless they were caused by invalid
+    /// JSON.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Error::NotFound | Error::WebfingerResolveFailed => StatusCode::NOT_FOUND,
+            Error::RequestLimit => StatusCode::TOO_MANY_REQUESTS,
+            Error::ResponseBodyLimit | Error::IncomingQueueFull => StatusCode::SERVICE_UNAVAILABLE,
+            Error::ObjectDeleted => StatusCode::GONE,
+            Error::UrlVerificationError(_) | Error::ActivityRejected(_) => StatusCode::FORBIDDEN,
+            Error::ActivityBodyDigestInvalid
+            | Error::ActivityBodyDigestUnsupported(_)
+            | Error::ActivitySignatureInvalid
+            | Error::ActivitySignatureKeyUnknown(_)
+            | Error::IntegrityProofInvalid => StatusCode::UNAUTHORIZED,
+            Error::ActivityParseError(_) => StatusCode::BAD_REQUEST,
+            Error::ActivityBodyTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::ActivityContentTypeInvalid(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Error::FetchContentTypeInvalid(_)
+            | Error::FetchedIdMismatch(_)
+            | Error::FetchedIdMissing
+            | Error::FetchRedirectInvalid(_) => StatusCode::BAD_GATEWAY,
+            Error::Other(e) if e.is::<serde_json::Error>() => StatusCode::BAD_REQUEST,
+            Error::Other(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
     pub(crate) fn other<T>(error: T) -> Self
     where
         T: Into<anyhow::Error>,
@@ -40,3 +96,54 @@ impl PartialEq for Error {
         std::mem::discriminant(self) == std::mem::discriminant(other)
     }
 }
+
+#[cfg(feature = "actix-web")]
+impl actix_web::ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        Error::status_code(self)
+    }
+}
+
+#[cfg(feature = "axum")]
+impl axum::response::IntoResponse for Error {
+    fn into_response(self) -> axum::response::Response {
+        (self.status_code(), self.to_string()).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_status_code() {
+        assert_eq!(
+            Error::ActivitySignatureInvalid.status_code(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            Error::UrlVerificationError("Domain is blocked").status_code(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(Error::ObjectDeleted.status_code(), StatusCode::GONE);
+        assert_eq!(
+            Error::IncomingQueueFull.status_code(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        let parse_error = serde_json::from_str::<u8>("invalid").unwrap_err();
+        assert_eq!(
+            Error::ActivityParseError(parse_error).status_code(),
+            StatusCode::BAD_REQUEST
+        );
+        let parse_error = serde_json::from_str::<u8>("invalid").unwrap_err();
+        assert_eq!(
+            Error::other(parse_error).status_code(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            Error::other(anyhow!("database unavailable")).status_code(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}
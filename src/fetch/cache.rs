@@ -0,0 +1,317 @@
+use lru::LruCache;
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    future::Future,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+use url::Url;
+
+/// Cached objects keyed by url, with one object of each type stored under that url. Values have
+/// the type `(Instant, T)`.
+type Entries = LruCache<Url, HashMap<TypeId, Box<dyn Any + Send>>>;
+
+/// Url and type of an object which is being fetched
+type FetchKey = (Url, TypeId);
+
+/// Locks which are held while an object is fetched, so that concurrent fetches of the same
+/// object wait for the first one
+type InFlight = HashMap<FetchKey, Weak<tokio::sync::Mutex<()>>>;
+
+/// Maximum time to wait for a fetch of the same object in another task. Fetches in different
+/// tasks may wait for each other if objects refer to each other, so after this time the object
+/// is fetched independently.
+const MAX_WAIT: Duration = Duration::from_secs(10);
+
+tokio::task_local! {
+    /// Objects which are being fetched by the current task. Waiting for one of these would never
+    /// finish, for example if a reply refers back to itself.
+    static FETCHING: Vec<FetchKey>;
+}
+
+/// Values which can be stored in the [ObjectCache]
+pub(crate) trait CacheValue: Sized + Send + 'static {
+    /// Returns a copy for the cache, or `None` if the value shouldn't be cached
+    fn cache_copy(&self) -> Option<Self>;
+}
+
+/// In-memory cache for dereferenced objects, used by
+/// [ObjectId::dereference](crate::fetch::object_id::ObjectId::dereference) if configured.
+///
+/// Only objects which return a copy from [Object::cache_copy](crate::traits::Object::cache_copy)
+/// are cached. Objects are keyed by url and type, and expire after the given time to live. If the
+/// cache is full, the least recently used url is removed. Concurrent dereferences of the same
+/// object wait for the first one, so that the database and remote server are only queried once.
+///
+/// Changes to cached objects only become visible after they expire, unless
+/// [ObjectCache::invalidate] is called.
+///
+/// ```
+/// # use activitypub_federation::config::FederationConfig;
+/// # use activitypub_federation::fetch::cache::ObjectCache;
+/// # use std::time::Duration;
+/// # let _ = actix_rt::System::new();
+/// let config = FederationConfig::builder()
+///     .domain("example.com")
+///     .app_data(())
+///     .object_cache(ObjectCache::new(Duration::from_secs(60), 10_000))
+///     .build()?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Clone)]
+pub struct ObjectCache {
+    entries: Arc<Mutex<Entries>>,
+    in_flight: Arc<Mutex<InFlight>>,
+    ttl: Duration,
+}
+
+impl ObjectCache {
+    /// Create a new cache which holds objects of up to `max_size` urls for `ttl` each
+    pub fn new(ttl: Duration, max_size: usize) -> Self {
+        let size = NonZeroUsize::new(max_size).unwrap_or(NonZeroUsize::MIN);
+        ObjectCache {
+            entries: Arc::new(Mutex::new(LruCache::new(size))),
+            in_flight: Default::default(),
+            ttl,
+        }
+    }
+
+    /// Remove all objects with the given url from the cache. Call this after changing an object
+    /// in the database.
+    pub fn invalidate(&self, url: &Url) {
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop(url);
+    }
+
+    /// Remove all objects from the cache
+    pub fn clear(&self) {
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+    }
+
+    /// Returns a copy of the cached object of type `T` with the given url, or calls `fetch` to
+    /// retrieve it. If another task is already fetching the same object, waits for it and
+    /// returns its result from the cache instead.
+    ///
+    /// The fetched object is only stored if [CacheValue::cache_copy] returns a copy of it.
+    /// Errors are not cached, so waiting tasks call their own `fetch` afterwards.
+    pub(crate) async fn get_or_fetch<T, E, F>(&self, url: &Url, fetch: F) -> Result<T, E>
+    where
+        T: CacheValue,
+        F: Future<Output = Result<T, E>>,
+    {
+        if let Some(object) = self.get(url) {
+            return Ok(object);
+        }
+        let key = (url.clone(), TypeId::of::<T>());
+        let mut fetching = FETCHING.try_with(Clone::clone).unwrap_or_default();
+        if fetching.contains(&key) {
+            return fetch.await;
+        }
+
+        let lock = self.in_flight_lock(&key);
+        let guard = tokio::time::timeout(MAX_WAIT, lock.lock()).await.ok();
+        if let Some(object) = self.get(url) {
+            drop(guard);
+            self.finish_fetch(&key, lock);
+            return Ok(object);
+        }
+        fetching.push(key.clone());
+        let res = FETCHING.scope(fetching, fetch).await;
+        if let Ok(object) = &res {
+            self.insert(url, object);
+        }
+        drop(guard);
+        self.finish_fetch(&key, lock);
+        res
+    }
+
+    /// Returns a copy of the cached object, if it is not expired
+    pub(crate) fn get<T>(&self, url: &Url) -> Option<T>
+    where
+        T: CacheValue,
+    {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let (fetched_at, object) = entries
+            .get(url)?
+            .get(&TypeId::of::<T>())?
+            .downcast_ref::<(Instant, T)>()?;
+        if fetched_at.elapsed() < self.ttl {
+            object.cache_copy()
+        } else {
+            None
+        }
+    }
+
+    /// Stores a copy of the object, replacing the cached object of the same type and url
+    pub(crate) fn insert<T>(&self, url: &Url, object: &T)
+    where
+        T: CacheValue,
+    {
+        if let Some(copy) = object.cache_copy() {
+            self.store(url, copy);
+        }
+    }
+
+    /// Stores the object, replacing the cached object of the same type and url
+    pub(crate) fn store<T>(&self, url: &Url, object: T)
+    where
+        T: CacheValue,
+    {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries
+            .get_or_insert_mut(url.clone(), HashMap::new)
+            .insert(TypeId::of::<T>(), Box::new((Instant::now(), object)));
+    }
+
+    /// Returns the lock for fetching the given object, creating it if no fetch is in progress
+    fn in_flight_lock(&self, key: &FetchKey) -> Arc<tokio::sync::Mutex<()>> {
+        let mut in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(lock) = in_flight.get(key).and_then(Weak::upgrade) {
+            return lock;
+        }
+        let lock = Arc::new(tokio::sync::Mutex::new(()));
+        in_flight.insert(key.clone(), Arc::downgrade(&lock));
+        lock
+    }
+
+    /// Releases the lock, and removes it once no other task is waiting for it
+    fn finish_fetch(&self, key: &FetchKey, lock: Arc<tokio::sync::Mutex<()>>) {
+        let mut in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+        drop(lock);
+        if in_flight
+            .get(key)
+            .is_some_and(|lock| lock.strong_count() == 0)
+        {
+            in_flight.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    impl CacheValue for String {
+        fn cache_copy(&self) -> Option<Self> {
+            Some(self.clone())
+        }
+    }
+
+    impl CacheValue for u32 {
+        fn cache_copy(&self) -> Option<Self> {
+            Some(*self)
+        }
+    }
+
+    /// Value which is never cached
+    #[derive(Debug, PartialEq)]
+    struct NoCopy(String);
+
+    impl CacheValue for NoCopy {
+        fn cache_copy(&self) -> Option<Self> {
+            None
+        }
+    }
+
+    async fn fetch(counter: &AtomicU32, value: &'static str) -> Result<String, String> {
+        counter.fetch_add(1, Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        Ok(value.to_string())
+    }
+
+    #[actix_rt::test]
+    async fn test_object_cache() {
+        let cache = ObjectCache::new(Duration::from_secs(60), 10);
+        let url = Url::parse("https://example.com/u/alice").unwrap();
+        let counter = AtomicU32::new(0);
+
+        // Concurrent fetches are combined
+        let (a, b) = tokio::join!(
+            cache.get_or_fetch(&url, fetch(&counter, "alice")),
+            cache.get_or_fetch(&url, fetch(&counter, "alice2"))
+        );
+        assert_eq!((a, b), (Ok("alice".to_string()), Ok("alice".to_string())));
+        assert_eq!(counter.load(Ordering::Relaxed), 1);
+        assert!(cache.in_flight.lock().unwrap().is_empty());
+
+        // Same url with different type is cached separately
+        let other = cache
+            .get_or_fetch(&url, async { Ok::<_, String>(1u32) })
+            .await;
+        assert_eq!(other, Ok(1));
+
+        // Errors are not cached
+        let other_url = Url::parse("https://example.com/u/bob").unwrap();
+        let res = cache
+            .get_or_fetch::<String, _, _>(&other_url, async { Err("not found".to_string()) })
+            .await;
+        assert!(res.is_err());
+        let res = cache.get_or_fetch(&other_url, fetch(&counter, "bob")).await;
+        assert_eq!(res, Ok("bob".to_string()));
+        assert_eq!(counter.load(Ordering::Relaxed), 2);
+
+        cache.invalidate(&url);
+        let res = cache.get_or_fetch(&url, fetch(&counter, "alice3")).await;
+        assert_eq!(res, Ok("alice3".to_string()));
+        assert_eq!(counter.load(Ordering::Relaxed), 3);
+    }
+
+    #[actix_rt::test]
+    async fn test_object_cache_self_reference() {
+        let cache = ObjectCache::new(Duration::from_secs(60), 10);
+        let url = Url::parse("https://example.com/note/1").unwrap();
+        let counter = AtomicU32::new(0);
+
+        // Fetching an object which refers back to itself doesn't wait for its own fetch
+        let fetch_reply = async {
+            let parent = cache.get_or_fetch(&url, fetch(&counter, "parent")).await?;
+            Ok::<_, String>(format!("reply to {parent}"))
+        };
+        let res = tokio::time::timeout(
+            Duration::from_secs(1),
+            cache.get_or_fetch(&url, fetch_reply),
+        )
+        .await;
+        assert_eq!(res, Ok(Ok("reply to parent".to_string())));
+    }
+
+    #[actix_rt::test]
+    async fn test_object_cache_expiry() {
+        let cache = ObjectCache::new(Duration::ZERO, 10);
+        let url = Url::parse("https://example.com/u/alice").unwrap();
+        let counter = AtomicU32::new(0);
+        cache
+            .get_or_fetch(&url, fetch(&counter, "a"))
+            .await
+            .unwrap();
+        let res = cache.get_or_fetch(&url, fetch(&counter, "b")).await;
+        assert_eq!(res, Ok("b".to_string()));
+        assert_eq!(counter.load(Ordering::Relaxed), 2);
+    }
+
+    #[actix_rt::test]
+    async fn test_object_cache_no_copy() {
+        let cache = ObjectCache::new(Duration::from_secs(60), 10);
+        let url = Url::parse("https://example.com/u/alice").unwrap();
+        let counter = &AtomicU32::new(0);
+        let fetch_no_copy = |value| async move { fetch(counter, value).await.map(NoCopy) };
+        cache.get_or_fetch(&url, fetch_no_copy("a")).await.unwrap();
+        let res = cache.get_or_fetch(&url, fetch_no_copy("b")).await;
+        assert_eq!(res, Ok(NoCopy("b".to_string())));
+        assert_eq!(counter.load(Ordering::Relaxed), 2);
+
+        // Inserted objects replace the cached one
+        cache.insert(&url, &"c".to_string());
+        let res = cache.get_or_fetch(&url, fetch(counter, "d")).await;
+        assert_eq!(res, Ok("c".to_string()));
+        assert_eq!(counter.load(Ordering::Relaxed), 2);
+    }
+}
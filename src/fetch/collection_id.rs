@@ -1,6 +1,16 @@
-use crate::{config::Data, error::Error, fetch::fetch_object_http, traits::Collection};
-use serde::{Deserialize, Serialize};
+use crate::{
+    config::Data,
+    error::Error,
+    fetch::fetch_object_http,
+    protocol::verification::verify_domains_match,
+    traits::Collection,
+};
+use futures_core::Stream;
+use futures_util::stream;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
 use std::{
+    collections::{HashSet, VecDeque},
     fmt::{Debug, Display, Formatter},
     marker::PhantomData,
 };
@@ -44,6 +54,140 @@ where
         Kind::verify(&json, &self.0, data).await?;
         Kind::from_json(json, owner, data).await
     }
+
+    /// Fetches the items of a paginated collection over HTTP, such as a remote outbox or list of
+    /// followers.
+    ///
+    /// The collection is fetched first, followed by the page in its `first` field and then each
+    /// `next` page. If the url is a page itself, the following pages are fetched in the same
+    /// way. Items in `orderedItems` or `items` are returned in order, both from the collection
+    /// itself and from its pages. They are usually urls, but may also be embedded objects, so `T`
+    /// should be able to deserialize both (for example `serde_json::Value`).
+    ///
+    /// The stream ends after `max_items` items, or when a page links back to a page which was
+    /// already fetched. Every page counts towards
+    /// [http_fetch_limit](crate::config::FederationConfigBuilder::http_fetch_limit). If a page
+    /// can't be fetched or is on a different domain than the collection, the error is returned
+    /// as last item of the stream. Items which can't be deserialized are returned as errors,
+    /// without ending the stream.
+    ///
+    /// ```
+    /// # use activitypub_federation::fetch::collection_id::CollectionId;
+    /// # use activitypub_federation::config::Data;
+    /// # use activitypub_federation::traits::Collection;
+    /// # use futures_util::StreamExt;
+    /// # use url::Url;
+    /// async fn read_outbox<Outbox: Collection>(
+    ///     outbox: &CollectionId<Outbox>,
+    ///     data: &Data<Outbox::DataType>,
+    /// ) -> Vec<Url> {
+    ///     outbox
+    ///         .items::<Url>(data, 100)
+    ///         .filter_map(|item| async { item.ok() })
+    ///         .collect()
+    ///         .await
+    /// }
+    /// ```
+    pub fn items<'a, T>(
+        &self,
+        data: &'a Data<<Kind as Collection>::DataType>,
+        max_items: usize,
+    ) -> impl Stream<Item = Result<T, Error>> + Send + 'a
+    where
+        T: DeserializeOwned + 'a,
+    {
+        let pages = CollectionPages {
+            collection: *self.0.clone(),
+            next: Some(Ok(*self.0.clone())),
+            is_collection: true,
+            items: VecDeque::new(),
+            visited: HashSet::new(),
+            remaining: max_items,
+        };
+        stream::unfold(pages, move |mut pages| async move {
+            loop {
+                if pages.remaining == 0 {
+                    return None;
+                }
+                if let Some(item) = pages.items.pop_front() {
+                    pages.remaining -= 1;
+                    let item = serde_json::from_value(item).map_err(Error::other);
+                    return Some((item, pages));
+                }
+                let url = match pages.next.take()? {
+                    Ok(url) => url,
+                    Err(e) => return Some((Err(e), pages)),
+                };
+                if !pages.visited.insert(url.clone()) {
+                    return None;
+                }
+                match fetch_object_http(&url, data).await {
+                    Ok(page) => pages.read_page(page),
+                    Err(e) => return Some((Err(e), pages)),
+                }
+            }
+        })
+    }
+}
+
+/// State of [CollectionId::items] while walking through the collection pages
+struct CollectionPages {
+    /// Url of the collection, all pages must be on the same domain
+    collection: Url,
+    /// Url of the next page to fetch, or the error if the link is invalid
+    next: Option<Result<Url, Error>>,
+    /// If the next page is the collection itself, whose first page is in `first` instead of `next`
+    is_collection: bool,
+    /// Items which were fetched but not yet returned
+    items: VecDeque<Value>,
+    /// Urls of all pages which were fetched, to detect loops
+    visited: HashSet<Url>,
+    /// Number of items which may still be returned
+    remaining: usize,
+}
+
+impl CollectionPages {
+    /// Takes the items of `page` and the link to the following page. If the following page is
+    /// embedded, it is read as well.
+    fn read_page(&mut self, mut page: Value) {
+        loop {
+            let items = page
+                .get_mut("orderedItems")
+                .map(Value::take)
+                .or_else(|| page.get_mut("items").map(Value::take));
+            match items {
+                Some(Value::Array(items)) => self.items.extend(items),
+                Some(Value::Null) | None => {}
+                Some(item) => self.items.push_back(item),
+            }
+
+            // The collection url may also point to a page, which has no `first` link
+            let link = if std::mem::take(&mut self.is_collection) && page.get("first").is_some() {
+                "first"
+            } else {
+                "next"
+            };
+            match page.get_mut(link).map(Value::take) {
+                Some(Value::String(url)) => {
+                    self.next = Url::parse(&url).ok().map(|url| {
+                        verify_domains_match(&url, &self.collection)?;
+                        Ok(url)
+                    });
+                    return;
+                }
+                Some(embedded @ Value::Object(_)) => {
+                    let id = embedded.get("id").and_then(Value::as_str);
+                    if let Some(id) = id.and_then(|id| Url::parse(id).ok()) {
+                        if !self.visited.insert(id) {
+                            return;
+                        }
+                    }
+                    page = embedded;
+                }
+                _ => return,
+            }
+        }
+    }
 }
 
 /// Need to implement clone manually, to avoid requiring Kind to be Clone
@@ -95,3 +239,170 @@ where
         CollectionId(Box::new(url), PhantomData::<Kind>)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::FederationConfig,
+        traits::tests::{start_test_server, DbConnection},
+        FEDERATION_CONTENT_TYPE,
+    };
+    use async_trait::async_trait;
+    use axum::{extract::Path, http::header::CONTENT_TYPE, routing::get, Router};
+    use futures_util::StreamExt;
+    use serde_json::json;
+
+    struct Outbox;
+
+    #[async_trait]
+    impl Collection for Outbox {
+        type Owner = ();
+        type DataType = DbConnection;
+        type Kind = Value;
+        type Error = Error;
+
+        async fn read_local(_: &(), _: &Data<Self::DataType>) -> Result<Value, Error> {
+            Ok(Value::Null)
+        }
+
+        async fn verify(_: &Value, _: &Url, _: &Data<Self::DataType>) -> Result<(), Error> {
+            Ok(())
+        }
+
+        async fn from_json(_: Value, _: &(), _: &Data<Self::DataType>) -> Result<Self, Error> {
+            Ok(Outbox)
+        }
+    }
+
+    /// Starts a server with an outbox whose last page links back to the first page, and returns
+    /// its port
+    fn start_server() -> u16 {
+        start_test_server(|port| {
+            let base = format!("http://localhost:{port}");
+            Router::new().route(
+                "/outbox/:page",
+                get(move |Path(page): Path<String>| async move {
+                    let json = match page.as_str() {
+                        "all" => json!({
+                            "id": format!("{base}/outbox/all"),
+                            "type": "OrderedCollection",
+                            "first": format!("{base}/outbox/1"),
+                        }),
+                        "1" => json!({
+                            "id": format!("{base}/outbox/1"),
+                            "type": "OrderedCollectionPage",
+                            "orderedItems": ["https://example.com/1", "https://example.com/2"],
+                            "next": format!("{base}/outbox/2"),
+                        }),
+                        "2" => json!({
+                            "id": format!("{base}/outbox/2"),
+                            "type": "CollectionPage",
+                            "items": "https://example.com/3",
+                            "next": format!("{base}/outbox/1"),
+                        }),
+                        // Page linking to a page on another domain
+                        "foreign" => json!({
+                            "id": format!("{base}/outbox/foreign"),
+                            "type": "OrderedCollection",
+                            "orderedItems": ["https://example.com/4"],
+                            "next": format!("http://127.0.0.1:{port}/outbox/1"),
+                        }),
+                        // Collection with inline items and embedded first page
+                        _ => json!({
+                            "id": format!("{base}/outbox/inline"),
+                            "type": "Collection",
+                            "items": ["https://example.com/0"],
+                            "first": {
+                                "type": "CollectionPage",
+                                "items": ["https://example.com/1"],
+                                "next": format!("{base}/outbox/2"),
+                            },
+                        }),
+                    };
+                    ([(CONTENT_TYPE, FEDERATION_CONTENT_TYPE)], json.to_string())
+                }),
+            )
+        })
+    }
+
+    async fn items(
+        id: &CollectionId<Outbox>,
+        data: &Data<DbConnection>,
+        max_items: usize,
+    ) -> Vec<String> {
+        id.items(data, max_items)
+            .map(|item| item.unwrap())
+            .collect()
+            .await
+    }
+
+    #[actix_rt::test]
+    async fn test_collection_items() {
+        let port = start_server();
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(DbConnection)
+            .debug(true)
+            .build()
+            .unwrap();
+        let urls = |ids: &[u32]| -> Vec<String> {
+            ids.iter()
+                .map(|i| format!("https://example.com/{i}"))
+                .collect()
+        };
+
+        // Pages are walked until the loop back to the first page is detected
+        let id =
+            CollectionId::<Outbox>::parse(format!("http://localhost:{port}/outbox/all").as_str())
+                .unwrap();
+        let data = config.to_request_data();
+        assert_eq!(items(&id, &data, 100).await, urls(&[1, 2, 3]));
+        assert_eq!(data.request_count(), 3);
+
+        // Stops fetching pages once enough items were returned
+        let data = config.to_request_data();
+        assert_eq!(items(&id, &data, 2).await, urls(&[1, 2]));
+        assert_eq!(data.request_count(), 2);
+
+        // Walking starts from the given page if it is not the collection itself
+        let page =
+            CollectionId::<Outbox>::parse(format!("http://localhost:{port}/outbox/2").as_str())
+                .unwrap();
+        let data = config.to_request_data();
+        assert_eq!(items(&page, &data, 100).await, urls(&[3, 1, 2]));
+
+        // Pages on a different domain than the collection are rejected without fetching them
+        let foreign = CollectionId::<Outbox>::parse(
+            format!("http://localhost:{port}/outbox/foreign").as_str(),
+        )
+        .unwrap();
+        let data = config.to_request_data();
+        let res: Vec<Result<String, _>> = foreign.items(&data, 100).collect().await;
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].as_ref().unwrap(), "https://example.com/4");
+        assert!(matches!(res[1], Err(Error::UrlVerificationError(_))));
+        assert_eq!(data.request_count(), 1);
+
+        // Inline items and embedded pages
+        let id = CollectionId::<Outbox>::parse(
+            format!("http://localhost:{port}/outbox/inline").as_str(),
+        )
+        .unwrap();
+        let data = config.to_request_data();
+        assert_eq!(items(&id, &data, 100).await, urls(&[0, 1, 3, 1, 2]));
+
+        // Fetch limit ends the stream with an error
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(DbConnection)
+            .debug(true)
+            .http_fetch_limit(1)
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+        let res: Vec<Result<String, _>> = id.items(&data, 100).collect().await;
+        assert_eq!(res.len(), 4);
+        assert!(matches!(res[3], Err(Error::RequestLimit)));
+    }
+}
@@ -2,13 +2,38 @@
 //!
 #![doc = include_str!("../../docs/07_fetching_data.md")]
 
-use crate::{config::Data, error::Error, reqwest_shim::ResponseExt, FEDERATION_CONTENT_TYPE};
-use http::StatusCode;
-use serde::de::DeserializeOwned;
+#[cfg(feature = "integrity-proofs")]
+use crate::integrity_proofs::verify_object_proof;
+use crate::{
+    config::Data,
+    error::Error,
+    inbox::is_activity_content_type,
+    object_enum::has_type,
+    reqwest_shim::ResponseExt,
+};
+use anyhow::anyhow;
+use http::{
+    header::{
+        ACCEPT,
+        CONTENT_TYPE,
+        ETAG,
+        IF_MODIFIED_SINCE,
+        IF_NONE_MATCH,
+        LAST_MODIFIED,
+        LOCATION,
+    },
+    HeaderValue,
+    StatusCode,
+};
+use reqwest::Response;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
 use std::sync::atomic::Ordering;
-use tracing::info;
+use tracing::{debug, info};
 use url::Url;
 
+/// In-memory cache for dereferenced objects
+pub mod cache;
 /// Typed wrapper for collection IDs
 pub mod collection_id;
 /// Typed wrapper for Activitypub Object ID which helps with dereferencing and caching
@@ -16,8 +41,19 @@ pub mod object_id;
 /// Resolves identifiers of the form `name@example.com`
 pub mod webfinger;
 
+/// Maximum number of redirects which are followed for a single fetch
+const MAX_REDIRECTS: usize = 10;
+
+/// Accept header for fetching Activitypub objects, with both content types allowed by
+/// <https://www.w3.org/TR/activitypub/#retrieving-objects>
+const ACTIVITY_ACCEPT: &str = r#"application/activity+json, application/ld+json; profile="https://www.w3.org/ns/activitystreams""#;
+
 /// Fetch a remote object over HTTP and convert to `Kind`.
 ///
+/// The response must have one of the Activitypub content types. If the server returns an HTML
+/// page instead, the object is fetched from its `<link rel="alternate">` with Activitypub type.
+/// Redirects and alternate links to other domains are rejected with [Error::FetchRedirectInvalid].
+///
 /// [crate::fetch::object_id::ObjectId::dereference] wraps this function to add caching and
 /// conversion to database type. Only use this function directly in exceptional cases where that
 /// behaviour is undesired.
@@ -30,10 +66,154 @@ pub async fn fetch_object_http<T: Clone, Kind: DeserializeOwned>(
     url: &Url,
     data: &Data<T>,
 ) -> Result<Kind, Error> {
+    match fetch_object_http_conditional(url, data, None).await? {
+        FetchResult::Modified(object, _) => Ok(object),
+        FetchResult::NotModified => Err(Error::other(anyhow!(
+            "Unexpected 304 Not Modified response from {}",
+            url
+        ))),
+    }
+}
+
+/// Values of the `ETag` and `Last-Modified` headers from the response when an object was fetched.
+///
+/// These can be stored together with the object, see
+/// [Object::cache_validators](crate::traits::Object::cache_validators). When the object is
+/// refetched, they are sent in `If-None-Match` and `If-Modified-Since` headers so that the remote
+/// server can respond with `304 Not Modified` if the object is unchanged.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CacheValidators {
+    /// Value of the `ETag` header
+    pub etag: Option<String>,
+    /// Value of the `Last-Modified` header
+    pub last_modified: Option<String>,
+}
+
+impl CacheValidators {
+    /// Returns true if neither header was present
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// Result of [fetch_object_http_conditional]
+pub(crate) enum FetchResult<Kind> {
+    /// The object with validators from the response
+    Modified(Kind, CacheValidators),
+    /// The remote server responded with `304 Not Modified`
+    NotModified,
+}
+
+/// Same as [fetch_object_http], but makes a conditional request if `validators` are given.
+pub(crate) async fn fetch_object_http_conditional<T: Clone, Kind: DeserializeOwned>(
+    url: &Url,
+    data: &Data<T>,
+    validators: Option<&CacheValidators>,
+) -> Result<FetchResult<Kind>, Error> {
+    let mut res = send_fetch_request(url, data, ACTIVITY_ACCEPT, validators).await?;
+    if res.status() == StatusCode::NOT_MODIFIED && validators.is_some() {
+        return Ok(FetchResult::NotModified);
+    }
+
+    // Profile urls which are pasted by users often point to an HTML page. Follow its alternate
+    // link to the Activitypub representation.
+    if res.status().is_success() && is_html(&content_type(&res)) {
+        let html = res.text_limited().await?;
+        let Some(alternate) = find_alternate_link(&html).and_then(|href| url.join(&href).ok())
+        else {
+            return Err(Error::FetchContentTypeInvalid("text/html".to_string()));
+        };
+        debug!("Following alternate link from {} to {}", url, alternate);
+        res = send_fetch_request(&alternate, data, ACTIVITY_ACCEPT, None).await?;
+    }
+
+    let served_url = res.url().clone();
+    // Redirects and alternate links must not lead to a different domain, otherwise that domain
+    // could serve objects for urls it doesn't control
+    if !same_origin(url, &served_url) {
+        return Err(Error::FetchRedirectInvalid(served_url));
+    }
+    let (mut json, mut validators) = parse_object_response(url, res).await?;
+    if data.config.verify_fetched_id {
+        let id = fetched_id(&json).ok_or(Error::FetchedIdMissing)?;
+        if !same_origin(&id, &served_url) {
+            if !is_proven_by_origin(&json, &id, data).await {
+                return Err(Error::FetchedIdMismatch(id));
+            }
+        } else if id != served_url {
+            // The object may be served from an alias url, such as a profile url. In this case
+            // fetch it again from its canonical id on the same domain.
+            debug!("Refetching {} from its id {}", url, id);
+            let res = send_fetch_request(&id, data, ACTIVITY_ACCEPT, None).await?;
+            let served_url = res.url().clone();
+            (json, validators) = parse_object_response(&id, res).await?;
+            if fetched_id(&json).as_ref() != Some(&served_url) {
+                return Err(Error::FetchedIdMismatch(served_url));
+            }
+        }
+    }
+    // Some servers replace deleted objects with a tombstone instead of responding with an error
+    if has_type(&json, &["Tombstone"]) {
+        return Err(Error::ObjectDeleted);
+    }
+    let object = serde_json::from_value(json).map_err(Error::other)?;
+    Ok(FetchResult::Modified(object, validators))
+}
+
+/// Objects from other domains, for example from a relay, are only accepted if they have a valid
+/// integrity proof by an actor from the domain of their id
+#[cfg(feature = "integrity-proofs")]
+async fn is_proven_by_origin<T: Clone>(json: &Value, id: &Url, data: &Data<T>) -> bool {
+    // Boxed because fetching the key of the proof calls this function again
+    Box::pin(verify_object_proof(json, data))
+        .await
+        .is_some_and(|controller| same_origin(&controller, id))
+}
+
+#[cfg(not(feature = "integrity-proofs"))]
+async fn is_proven_by_origin<T: Clone>(_: &Value, _: &Url, _: &Data<T>) -> bool {
+    false
+}
+
+/// Returns the `id` of a fetched object
+pub(crate) fn fetched_id(json: &Value) -> Option<Url> {
+    json.get("id")?.as_str()?.parse().ok()
+}
+
+/// Returns true if both urls have the same scheme, host and port
+pub(crate) fn same_origin(a: &Url, b: &Url) -> bool {
+    a.origin() == b.origin()
+}
+
+/// Fetch JSON data which is not an Activitypub object, such as a webfinger response. This
+/// counts towards the request limit, but doesn't validate the response content type.
+pub(crate) async fn fetch_json_http<T: Clone, Kind: DeserializeOwned>(
+    url: &Url,
+    data: &Data<T>,
+    accept: &str,
+) -> Result<Kind, Error> {
+    let res = send_fetch_request(url, data, accept, None).await?;
+    if !res.status().is_success() {
+        return Err(Error::other(anyhow!(
+            "Fetching {} failed with status {}",
+            url,
+            res.status()
+        )));
+    }
+    res.json_limited().await
+}
+
+/// Checks the request limit and sends a GET request. Redirects are followed here instead of in
+/// the client, so that the target of each redirect is verified.
+async fn send_fetch_request<T: Clone>(
+    url: &Url,
+    data: &Data<T>,
+    accept: &str,
+    validators: Option<&CacheValidators>,
+) -> Result<Response, Error> {
     let config = &data.config;
     // dont fetch local objects this way
     debug_assert!(url.domain() != Some(&config.domain));
-    config.verify_url_valid(url).await?;
     info!("Fetching remote object {}", url.to_string());
 
     let counter = data.request_counter.fetch_add(1, Ordering::SeqCst);
@@ -41,18 +221,394 @@ pub async fn fetch_object_http<T: Clone, Kind: DeserializeOwned>(
         return Err(Error::RequestLimit);
     }
 
-    let res = config
-        .client
-        .get(url.as_str())
-        .header("Accept", FEDERATION_CONTENT_TYPE)
-        .timeout(config.request_timeout)
-        .send()
-        .await
-        .map_err(Error::other)?;
+    let mut url = url.clone();
+    for _ in 0..=MAX_REDIRECTS {
+        config.verify_url_valid(&url).await?;
+        config.verify_url_address(&url).await?;
+
+        let mut req = config
+            .client
+            .get(url.as_str())
+            .header(ACCEPT, accept)
+            .timeout(config.request_timeout);
+        if let Some(validators) = validators {
+            if let Some(etag) = &validators.etag {
+                req = req.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &validators.last_modified {
+                req = req.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+        let res = req.send().await.map_err(Error::other)?;
+        // Custom clients which follow redirects themselves would bypass the checks above
+        if res.url() != &url {
+            return Err(Error::UrlVerificationError(
+                "HTTP client must not follow redirects",
+            ));
+        }
+
+        let location = res
+            .headers()
+            .get(LOCATION)
+            .and_then(|l| l.to_str().ok())
+            .and_then(|l| url.join(l).ok());
+        match location {
+            Some(location) if is_redirect(res.status()) => {
+                debug!("Following redirect from {} to {}", url, location);
+                url = location;
+            }
+            _ => return Ok(res),
+        }
+    }
+    Err(Error::other(anyhow!(
+        "Too many redirects when fetching {}",
+        url
+    )))
+}
+
+fn is_redirect(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::MOVED_PERMANENTLY
+            | StatusCode::FOUND
+            | StatusCode::SEE_OTHER
+            | StatusCode::TEMPORARY_REDIRECT
+            | StatusCode::PERMANENT_REDIRECT
+    )
+}
 
-    if res.status() == StatusCode::GONE {
+/// Checks status and content type of the response, and parses the object json
+async fn parse_object_response(
+    url: &Url,
+    res: Response,
+) -> Result<(Value, CacheValidators), Error> {
+    if res.status() == StatusCode::GONE || res.status() == StatusCode::NOT_FOUND {
         return Err(Error::ObjectDeleted);
     }
+    if !res.status().is_success() {
+        return Err(Error::other(anyhow!(
+            "Fetching {} failed with status {}",
+            url,
+            res.status()
+        )));
+    }
+    let content_type = content_type(&res);
+    if !is_object_content_type(&content_type) {
+        return Err(Error::FetchContentTypeInvalid(content_type));
+    }
 
-    res.json_limited().await
+    let header = |name| {
+        res.headers()
+            .get(name)
+            .and_then(|v: &HeaderValue| v.to_str().ok())
+            .map(str::to_string)
+    };
+    let validators = CacheValidators {
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+    };
+    Ok((res.json_limited().await?, validators))
+}
+
+fn content_type(res: &Response) -> String {
+    res.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Content types which are accepted for fetched objects. In addition to the activity content
+/// types, plain `application/ld+json` without profile is accepted, as some servers omit it.
+fn is_object_content_type(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+    mime.eq_ignore_ascii_case("application/ld+json") || is_activity_content_type(content_type)
+}
+
+fn is_html(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+    mime.eq_ignore_ascii_case("text/html")
+}
+
+/// Returns the `href` of the first `<link rel="alternate">` element with an Activitypub content
+/// type in the given HTML document.
+fn find_alternate_link(html: &str) -> Option<String> {
+    // Lowercasing ASCII keeps byte offsets the same
+    let lowercase = html.to_ascii_lowercase();
+    let mut pos = 0;
+    while let Some(start) = lowercase[pos..]
+        .find("<link")
+        .map(|i| pos + i + "<link".len())
+    {
+        let end = lowercase[start..].find('>').map(|i| start + i)?;
+        pos = end;
+        let attributes = parse_attributes(&html[start..end]);
+        let attribute = |name: &str| {
+            attributes
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.as_str())
+        };
+        let is_alternate = attribute("rel").is_some_and(|rel| {
+            rel.split_whitespace()
+                .any(|r| r.eq_ignore_ascii_case("alternate"))
+        });
+        let is_activity = attribute("type").is_some_and(is_activity_content_type);
+        if is_alternate && is_activity {
+            if let Some(href) = attribute("href") {
+                return Some(href.to_string());
+            }
+        }
+    }
+    None
+}
+
+/// Parses the attributes of an HTML tag into pairs of lowercase name and value
+fn parse_attributes(tag: &str) -> Vec<(String, String)> {
+    let mut attributes = vec![];
+    let mut chars = tag.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace() || *c == '/').is_some() {}
+        let name: String =
+            std::iter::from_fn(|| chars.next_if(|c| !c.is_whitespace() && *c != '=' && *c != '/'))
+                .collect();
+        if name.is_empty() {
+            return attributes;
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut value = String::new();
+        if chars.next_if_eq(&'=').is_some() {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            match chars.next_if(|c| *c == '"' || *c == '\'') {
+                Some(quote) => value.extend(chars.by_ref().take_while(|c| *c != quote)),
+                None => value.extend(std::iter::from_fn(|| chars.next_if(|c| !c.is_whitespace()))),
+            }
+        }
+        let value = value.replace("&quot;", "\"").replace("&amp;", "&");
+        attributes.push((name.to_ascii_lowercase(), value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::FederationConfig,
+        traits::tests::start_test_server,
+        FEDERATION_CONTENT_TYPE,
+    };
+    use axum::{
+        http::{HeaderMap, Uri},
+        response::{IntoResponse, Redirect},
+        routing::get,
+        Router,
+    };
+    use serde_json::json;
+
+    /// Starts a server with an object which is also linked from an HTML page, and returns its port
+    fn start_server() -> u16 {
+        start_test_server(|port| {
+            let object = json!({"id": format!("http://localhost:{port}/object")}).to_string();
+            let json = object.clone();
+            let alias = object.clone();
+            let ld = json!({"id": format!("http://localhost:{port}/ld")}).to_string();
+            let no_id = json!({"id": "object", "type": "Note"}).to_string();
+            let spoofed = json!({"id": format!("http://127.0.0.1:{port}/object")}).to_string();
+            let tombstone = json!({
+                "id": format!("http://localhost:{port}/tombstone"),
+                "type": "Tombstone",
+            })
+            .to_string();
+            Router::new()
+                .route(
+                    "/object",
+                    get(|headers: HeaderMap| async move {
+                        let accept = headers.get(ACCEPT).unwrap().to_str().unwrap();
+                        assert_eq!(accept, ACTIVITY_ACCEPT);
+                        ([(CONTENT_TYPE, FEDERATION_CONTENT_TYPE)], object)
+                    }),
+                )
+                .route(
+                    "/@alice",
+                    get(|| async {
+                        let html =
+                            r#"<link rel="alternate" type="application/activity+json" href="/object">"#;
+                        ([(CONTENT_TYPE, "text/html; charset=utf-8")], html).into_response()
+                    }),
+                )
+                .route(
+                    "/ld",
+                    get(|| async { ([(CONTENT_TYPE, "application/ld+json")], ld) }),
+                )
+                .route(
+                    "/upload.json",
+                    get(|| async { ([(CONTENT_TYPE, "application/json")], json) }),
+                )
+                .route("/redirect", get(|| async { Redirect::to("/object") }))
+                .route(
+                    "/redirect-to",
+                    get(|uri: Uri| async move { Redirect::to(uri.query().unwrap_or_default()) }),
+                )
+                .route(
+                    "/no-id",
+                    get(|| async { ([(CONTENT_TYPE, FEDERATION_CONTENT_TYPE)], no_id) }),
+                )
+                .route(
+                    "/redirect-file",
+                    get(|| async { Redirect::to("file:///etc/passwd") }),
+                )
+                .route(
+                    "/alias",
+                    get(|| async { ([(CONTENT_TYPE, FEDERATION_CONTENT_TYPE)], alias) }),
+                )
+                .route(
+                    "/spoofed",
+                    get(|| async { ([(CONTENT_TYPE, FEDERATION_CONTENT_TYPE)], spoofed) }),
+                )
+                .route("/gone", get(|| async { StatusCode::GONE }))
+                .route(
+                    "/tombstone",
+                    get(|| async { ([(CONTENT_TYPE, FEDERATION_CONTENT_TYPE)], tombstone) }),
+                )
+        })
+    }
+
+    #[actix_rt::test]
+    async fn test_fetch_content_negotiation() {
+        let port = start_server();
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(())
+            .debug(true)
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+        let url = |path: &str| Url::parse(&format!("http://localhost:{port}{path}")).unwrap();
+
+        let object: Value = fetch_object_http(&url("/object"), &data).await.unwrap();
+        assert_eq!(object["id"], url("/object").as_str());
+
+        // Alternate link of HTML page is followed
+        let object: Value = fetch_object_http(&url("/@alice"), &data).await.unwrap();
+        assert_eq!(object["id"], url("/object").as_str());
+        assert_eq!(data.request_count(), 3);
+
+        // JSON-LD without profile is accepted
+        let object: Value = fetch_object_http(&url("/ld"), &data).await.unwrap();
+        assert_eq!(object["id"], url("/ld").as_str());
+
+        let res = fetch_object_http::<_, Value>(&url("/upload.json"), &data).await;
+        assert_eq!(
+            res,
+            Err(Error::FetchContentTypeInvalid(
+                "application/json".to_string()
+            ))
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_fetch_verify_id() {
+        let port = start_server();
+        let url = |path: &str| Url::parse(&format!("http://localhost:{port}{path}")).unwrap();
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(())
+            .debug(true)
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+
+        let object: Value = fetch_object_http(&url("/redirect"), &data).await.unwrap();
+        assert_eq!(object["id"], url("/object").as_str());
+        assert_eq!(data.request_count(), 1);
+
+        // Target of each redirect is verified
+        let res = fetch_object_http::<_, Value>(&url("/redirect-file"), &data).await;
+        assert_eq!(res, Err(Error::UrlVerificationError("Invalid url scheme")));
+
+        // Redirects to other domains are rejected
+        let other_url = Url::parse(&format!("http://localhost:{}/object", start_server())).unwrap();
+        let redirect_url = url(&format!("/redirect-to?{other_url}"));
+        let res = fetch_object_http::<_, Value>(&redirect_url, &data).await;
+        assert_eq!(res, Err(Error::FetchRedirectInvalid(other_url)));
+
+        // Object is refetched from its canonical id
+        let object: Value = fetch_object_http(&url("/alias"), &data).await.unwrap();
+        assert_eq!(object["id"], url("/object").as_str());
+        assert_eq!(data.request_count(), 5);
+
+        let res = fetch_object_http::<_, Value>(&url("/spoofed"), &data).await;
+        let spoofed_id = Url::parse(&format!("http://127.0.0.1:{port}/object")).unwrap();
+        assert_eq!(res, Err(Error::FetchedIdMismatch(spoofed_id)));
+
+        let res = fetch_object_http::<_, Value>(&url("/no-id"), &data).await;
+        assert_eq!(res, Err(Error::FetchedIdMissing));
+
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(())
+            .debug(true)
+            .verify_fetched_id(false)
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+        let res = fetch_object_http::<_, Value>(&url("/spoofed"), &data).await;
+        assert!(res.is_ok());
+        let res = fetch_object_http::<_, Value>(&url("/no-id"), &data).await;
+        assert!(res.is_ok());
+
+        // Custom client which follows redirects
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(())
+            .debug(true)
+            .client(reqwest::Client::new().into())
+            .build()
+            .unwrap();
+        let res = fetch_object_http::<_, Value>(&url("/redirect"), &config.to_request_data()).await;
+        assert_eq!(
+            res,
+            Err(Error::UrlVerificationError(
+                "HTTP client must not follow redirects"
+            ))
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_fetch_deleted() {
+        let port = start_server();
+        let url = |path: &str| Url::parse(&format!("http://localhost:{port}{path}")).unwrap();
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(())
+            .debug(true)
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+
+        for path in ["/gone", "/missing", "/tombstone"] {
+            let res = fetch_object_http::<_, Value>(&url(path), &data).await;
+            assert_eq!(res, Err(Error::ObjectDeleted), "{path}");
+        }
+    }
+
+    #[test]
+    fn test_find_alternate_link() {
+        let html = r#"<html><head>
+            <link rel="stylesheet" href="/style.css">
+            <LINK href='https://example.com/users/alice' rel="alternate" type="text/html" />
+            <link rel=alternate type="application/activity+json" href="https://example.com/users/alice">
+            </head></html>"#;
+        assert_eq!(
+            find_alternate_link(html),
+            Some("https://example.com/users/alice".to_string())
+        );
+
+        let html = r#"<link rel="alternate" href="/u/bob"
+            type="application/ld+json; profile=&quot;https://www.w3.org/ns/activitystreams&quot;">"#;
+        assert_eq!(find_alternate_link(html), Some("/u/bob".to_string()));
+
+        assert_eq!(find_alternate_link("<html><link rel=alternate"), None);
+        assert_eq!(find_alternate_link("<p>no links</p>"), None);
+    }
 }
@@ -1,12 +1,31 @@
-use crate::{config::Data, error::Error, fetch::fetch_object_http, traits::Object};
+use crate::{
+    config::Data,
+    error::Error,
+    fetch::{
+        cache::CacheValue,
+        fetch_object_http_conditional,
+        fetched_id,
+        same_origin,
+        FetchResult,
+    },
+    traits::Object,
+};
+#[cfg(feature = "integrity-proofs")]
+use crate::{integrity_proofs::verify_object_proof, protocol::verification::verify_domains_match};
 use anyhow::anyhow;
 use chrono::{Duration as ChronoDuration, NaiveDateTime, Utc};
+use futures_util::{stream, StreamExt};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::{
+    collections::{HashMap, HashSet},
     fmt::{Debug, Display, Formatter},
     marker::PhantomData,
     str::FromStr,
+    time::Duration,
 };
+use tokio::sync::Semaphore;
+use tracing::debug;
 use url::Url;
 
 impl<T> FromStr for ObjectId<T>
@@ -86,10 +105,240 @@ where
     }
 
     /// Fetches an activitypub object, either from local database (if possible), or over http.
+    ///
+    /// If the remote server responds with `410 Gone`, `404 Not Found` or a `Tombstone`, the local
+    /// copy of the object is removed with [Object::delete] and [Error::ObjectDeleted] is
+    /// returned.
+    ///
+    /// If an [ObjectCache](crate::fetch::cache::ObjectCache) is configured, objects which
+    /// implement [Object::cache_copy] are returned from the cache before reading the database.
     pub async fn dereference(
         &self,
         data: &Data<<Kind as Object>::DataType>,
     ) -> Result<Kind, <Kind as Object>::Error>
+    where
+        <Kind as Object>::Error: From<Error> + From<anyhow::Error>,
+    {
+        let res = self.dereference_or_deleted(data).await?;
+        self.delete_if_deleted(res, data).await
+    }
+
+    /// Fetches an activitypub object over http, even if it is present in the local database and
+    /// was refreshed recently. Local objects are still read from the database.
+    ///
+    /// Use this when the remote object is known to have changed, for example when a user
+    /// explicitly requests to refresh a profile.
+    pub async fn dereference_forced(
+        &self,
+        data: &Data<<Kind as Object>::DataType>,
+    ) -> Result<Kind, <Kind as Object>::Error>
+    where
+        <Kind as Object>::Error: From<Error> + From<anyhow::Error>,
+    {
+        if data.config.is_local_url(&self.0) {
+            return self.dereference_local(data).await;
+        }
+        if let Some(cache) = &data.config.object_cache {
+            cache.invalidate(&self.0);
+        }
+        let db_object = self.dereference_from_db(data).await?;
+        let res = self.dereference_from_http(data, db_object, false).await?;
+        let object = self.delete_if_deleted(res, data).await?;
+        self.cache_object(&object, data);
+        Ok(object)
+    }
+
+    /// Stores a copy of the object in the cache, if one is configured
+    fn cache_object(&self, object: &Kind, data: &Data<<Kind as Object>::DataType>) {
+        if let (Some(cache), Some(copy)) = (&data.config.object_cache, object.cache_copy()) {
+            cache.store(&self.0, Dereferenced::Object(copy));
+        }
+    }
+
+    /// Calls [Object::delete] on the local copy if the remote object was deleted
+    async fn delete_if_deleted(
+        &self,
+        res: Dereferenced<Kind>,
+        data: &Data<<Kind as Object>::DataType>,
+    ) -> Result<Kind, <Kind as Object>::Error>
+    where
+        <Kind as Object>::Error: From<Error>,
+    {
+        match res {
+            Dereferenced::Object(object) => Ok(object),
+            Dereferenced::Deleted(db_object) => {
+                debug!("Fetched remote object {} which was deleted", self);
+                if let Some(cache) = &data.config.object_cache {
+                    cache.invalidate(&self.0);
+                }
+                if let Some(db_object) = db_object {
+                    db_object.delete(data).await?;
+                }
+                Err(Error::ObjectDeleted.into())
+            }
+        }
+    }
+
+    /// Dereferences many objects concurrently, for example all replies in a thread.
+    ///
+    /// Duplicate ids are only dereferenced once. Returns each distinct id with its result, in the
+    /// order in which the ids were first given.
+    ///
+    /// All objects are first read from the cache and local database, up to `max_concurrent` at a
+    /// time. The remaining objects are then fetched over http, again up to `max_concurrent` at a
+    /// time, but at most
+    /// [max_concurrent_fetches_per_host](crate::config::FederationConfigBuilder::max_concurrent_fetches_per_host)
+    /// from the same host (scheme, domain and port) to avoid hitting rate limits. All fetches
+    /// count towards the same
+    /// [http_fetch_limit](crate::config::FederationConfigBuilder::http_fetch_limit), so once it
+    /// is reached the remaining remote objects fail with [Error::RequestLimit].
+    pub async fn dereference_many<I>(
+        ids: I,
+        data: &Data<<Kind as Object>::DataType>,
+        max_concurrent: usize,
+    ) -> Vec<(ObjectId<Kind>, Result<Kind, <Kind as Object>::Error>)>
+    where
+        I: IntoIterator<Item = ObjectId<Kind>>,
+        <Kind as Object>::Error: From<Error> + From<anyhow::Error>,
+    {
+        let max_concurrent = max_concurrent.max(1);
+        let mut seen = HashSet::new();
+        let unique = ids.into_iter().filter(|id| seen.insert(id.0.clone()));
+        let stored: Vec<_> = stream::iter(unique.enumerate())
+            .map(|(index, id)| async move {
+                let res = id.dereference_stored(data).await;
+                (index, id, res)
+            })
+            .buffer_unordered(max_concurrent)
+            .collect()
+            .await;
+
+        let mut results = vec![];
+        let mut hosts = HashMap::new();
+        let mut groups: Vec<Vec<_>> = vec![];
+        for (index, id, res) in stored {
+            match res {
+                Ok(Stored::Outdated(db_object)) => {
+                    let group = *hosts.entry(id.0.origin()).or_insert_with(|| {
+                        groups.push(vec![]);
+                        groups.len() - 1
+                    });
+                    groups[group].push((index, id, db_object));
+                }
+                Ok(Stored::Current(object)) => results.push((index, id, Ok(object))),
+                Err(e) => results.push((index, id, Err(e))),
+            }
+        }
+
+        // Alternate between hosts, so that waiting for one host doesn't block the others
+        let per_host = data.config.max_concurrent_fetches_per_host.max(1);
+        let limits: Vec<_> = groups.iter().map(|_| Semaphore::new(per_host)).collect();
+        let mut pending = vec![];
+        let mut groups: Vec<_> = groups.into_iter().map(Vec::into_iter).collect();
+        loop {
+            let before = pending.len();
+            for (group, ids) in groups.iter_mut().enumerate() {
+                pending.extend(
+                    ids.next()
+                        .map(|(index, id, db_object)| (group, index, id, db_object)),
+                );
+            }
+            if pending.len() == before {
+                break;
+            }
+        }
+        let fetched: Vec<_> = stream::iter(pending)
+            .map(|(group, index, id, db_object)| {
+                let limit = &limits[group];
+                async move {
+                    // The semaphore is never closed, so this always returns a permit
+                    let _permit = limit.acquire().await;
+                    let res = id.dereference_outdated(db_object, data).await;
+                    (index, id, res)
+                }
+            })
+            .buffer_unordered(max_concurrent)
+            .collect()
+            .await;
+        results.extend(fetched);
+        results.sort_by_key(|(index, _, _)| *index);
+        results.into_iter().map(|(_, id, res)| (id, res)).collect()
+    }
+
+    /// Returns the object from the cache or local database if it is current, or otherwise the
+    /// local copy of the object which needs to be fetched over http.
+    async fn dereference_stored(
+        &self,
+        data: &Data<<Kind as Object>::DataType>,
+    ) -> Result<Stored<Kind>, <Kind as Object>::Error>
+    where
+        <Kind as Object>::Error: From<Error> + From<anyhow::Error>,
+    {
+        if let Some(Dereferenced::Object(object)) = data
+            .config
+            .object_cache
+            .as_ref()
+            .and_then(|cache| cache.get(&self.0))
+        {
+            return Ok(Stored::Current(object));
+        }
+        let stored = self.dereference_from_db_or_outdated(data).await?;
+        if let Stored::Current(object) = &stored {
+            self.cache_object(object, data);
+        }
+        Ok(stored)
+    }
+
+    /// Fetches an object which is missing or outdated in the local database over http, and
+    /// caches the result. If the object is already being fetched, waits for that fetch instead.
+    async fn dereference_outdated(
+        &self,
+        db_object: Option<Kind>,
+        data: &Data<<Kind as Object>::DataType>,
+    ) -> Result<Kind, <Kind as Object>::Error>
+    where
+        <Kind as Object>::Error: From<Error> + From<anyhow::Error>,
+    {
+        let fetch = self.dereference_from_http(data, db_object, true);
+        let res = match &data.config.object_cache {
+            Some(cache) => cache.get_or_fetch(&self.0, fetch).await?,
+            None => fetch.await?,
+        };
+        self.delete_if_deleted(res, data).await
+    }
+
+    /// Same as [ObjectId::dereference], but if the remote object was deleted this returns the
+    /// version from the local database, without calling [Object::delete].
+    ///
+    /// If an [ObjectCache](crate::fetch::cache::ObjectCache) is configured, the object is read
+    /// from and stored in the cache, and concurrent calls for the same object wait for the first
+    /// one.
+    pub(crate) async fn dereference_or_deleted(
+        &self,
+        data: &Data<<Kind as Object>::DataType>,
+    ) -> Result<Dereferenced<Kind>, <Kind as Object>::Error>
+    where
+        <Kind as Object>::Error: From<Error> + From<anyhow::Error>,
+    {
+        let dereference = async {
+            match self.dereference_from_db_or_outdated(data).await? {
+                Stored::Current(object) => Ok(Dereferenced::Object(object)),
+                Stored::Outdated(db_object) => {
+                    self.dereference_from_http(data, db_object, true).await
+                }
+            }
+        };
+        match &data.config.object_cache {
+            Some(cache) => cache.get_or_fetch(&self.0, dereference).await,
+            None => dereference.await,
+        }
+    }
+
+    /// Reads the object from the local database, and checks if it needs to be fetched over http
+    async fn dereference_from_db_or_outdated(
+        &self,
+        data: &Data<<Kind as Object>::DataType>,
+    ) -> Result<Stored<Kind>, <Kind as Object>::Error>
     where
         <Kind as Object>::Error: From<Error> + From<anyhow::Error>,
     {
@@ -99,7 +348,7 @@ where
         if data.config.is_local_url(&self.0) {
             return match db_object {
                 None => Err(Error::NotFound.into()),
-                Some(o) => Ok(o),
+                Some(o) => Ok(Stored::Current(o)),
             };
         }
 
@@ -107,15 +356,18 @@ where
         if let Some(object) = db_object {
             // object is old and should be refetched
             if let Some(last_refreshed_at) = object.last_refreshed_at() {
-                if should_refetch_object(last_refreshed_at) {
-                    return self.dereference_from_http(data, Some(object)).await;
+                let interval = object
+                    .refetch_interval()
+                    .unwrap_or(data.config.refetch_interval);
+                if should_refetch_object(last_refreshed_at, interval) {
+                    return Ok(Stored::Outdated(Some(object)));
                 }
             }
-            Ok(object)
+            Ok(Stored::Current(object))
         }
         // object not found, need to fetch over http
         else {
-            self.dereference_from_http(data, None).await
+            Ok(Stored::Outdated(None))
         }
     }
 
@@ -141,27 +393,92 @@ where
         Object::read_from_id(*id, data).await
     }
 
+    /// Fetch the object over http. With `conditional`, the request includes the cache validators
+    /// of `db_object`, if any.
     async fn dereference_from_http(
         &self,
         data: &Data<<Kind as Object>::DataType>,
         db_object: Option<Kind>,
-    ) -> Result<Kind, <Kind as Object>::Error>
+        conditional: bool,
+    ) -> Result<Dereferenced<Kind>, <Kind as Object>::Error>
     where
         <Kind as Object>::Error: From<Error> + From<anyhow::Error>,
     {
-        let res = fetch_object_http(&self.0, data).await;
+        let validators = db_object
+            .as_ref()
+            .filter(|_| conditional)
+            .and_then(Object::cache_validators)
+            .filter(|validators| !validators.is_empty());
+        let res = fetch_object_http_conditional(&self.0, data, validators.as_ref()).await;
 
         if let Err(Error::ObjectDeleted) = &res {
-            if let Some(db_object) = db_object {
-                db_object.delete(data).await?;
-            }
-            return Err(anyhow!("Fetched remote object {} which was deleted", self).into());
+            return Ok(Dereferenced::Deleted(db_object));
         }
 
-        let res2 = res?;
+        let (json, validators): (Value, _) = match res? {
+            FetchResult::Modified(json, validators) => (json, validators),
+            FetchResult::NotModified => {
+                // Conditional requests are only sent for objects from the database
+                let Some(db_object) = db_object else {
+                    return Err(anyhow!("Unexpected 304 Not Modified for {}", self).into());
+                };
+                return db_object.not_modified(data).await.map(Dereferenced::Object);
+            }
+        };
+        // Objects from another domain were relayed, and are only returned by the fetch if they
+        // have a valid integrity proof by an actor from the domain of their id. They are verified
+        // against their id instead of the url they were fetched from.
+        let expected_domain = match fetched_id(&json) {
+            Some(id) if data.config.verify_fetched_id && !same_origin(&id, self.inner()) => id,
+            _ => {
+                // Objects with a valid integrity proof must be created by an actor from the same
+                // domain. Unsupported or invalid proofs are ignored, the object is still checked
+                // by `verify`.
+                #[cfg(feature = "integrity-proofs")]
+                if json.get("proof").is_some() {
+                    if let Some(controller) = verify_object_proof(&json, data).await {
+                        verify_domains_match(&controller, self.inner())?;
+                    }
+                }
+                self.inner().clone()
+            }
+        };
+        let res2 = serde_json::from_value(json).map_err(Error::other)?;
+
+        Kind::verify(&res2, &expected_domain, data).await?;
+        let object = Kind::from_json(res2, data).await?;
+        // Also called without validators, to replace the ones from an earlier response
+        let object = object.set_cache_validators(validators, data).await?;
+        Ok(Dereferenced::Object(object))
+    }
+}
+
+/// Result of reading an object from the cache or local database
+enum Stored<Kind> {
+    /// The object is current and doesn't need to be fetched
+    Current(Kind),
+    /// The object needs to be fetched over http. Contains the outdated local copy, if any.
+    Outdated(Option<Kind>),
+}
 
-        Kind::verify(&res2, self.inner(), data).await?;
-        Kind::from_json(res2, data).await
+/// Result of [ObjectId::dereference_or_deleted]
+pub(crate) enum Dereferenced<Kind> {
+    /// The object was dereferenced successfully
+    Object(Kind),
+    /// The remote server reported the object as deleted. Contains the local copy, if any.
+    Deleted(Option<Kind>),
+}
+
+/// Only objects are cached, deletions are always fetched again
+impl<Kind> CacheValue for Dereferenced<Kind>
+where
+    Kind: Object + Send + 'static,
+{
+    fn cache_copy(&self) -> Option<Self> {
+        match self {
+            Dereferenced::Object(object) => object.cache_copy().map(Dereferenced::Object),
+            Dereferenced::Deleted(_) => None,
+        }
     }
 }
 
@@ -176,20 +493,15 @@ where
     }
 }
 
-static ACTOR_REFETCH_INTERVAL_SECONDS: i64 = 24 * 60 * 60;
-static ACTOR_REFETCH_INTERVAL_SECONDS_DEBUG: i64 = 20;
-
-/// Determines when a remote actor should be refetched from its instance. In release builds, this is
-/// `ACTOR_REFETCH_INTERVAL_SECONDS` after the last refetch, in debug builds
-/// `ACTOR_REFETCH_INTERVAL_SECONDS_DEBUG`.
-fn should_refetch_object(last_refreshed: NaiveDateTime) -> bool {
-    let update_interval = if cfg!(debug_assertions) {
-        // avoid infinite loop when fetching community outbox
-        ChronoDuration::seconds(ACTOR_REFETCH_INTERVAL_SECONDS_DEBUG)
-    } else {
-        ChronoDuration::seconds(ACTOR_REFETCH_INTERVAL_SECONDS)
-    };
-    let refresh_limit = Utc::now().naive_utc() - update_interval;
+/// Determines when a remote object should be refetched from its instance, which is `interval`
+/// after the last refetch.
+fn should_refetch_object(last_refreshed: NaiveDateTime, interval: Duration) -> bool {
+    let interval =
+        ChronoDuration::from_std(interval).unwrap_or_else(|_| ChronoDuration::max_value());
+    let refresh_limit = Utc::now()
+        .naive_utc()
+        .checked_sub_signed(interval)
+        .unwrap_or(NaiveDateTime::MIN);
     last_refreshed.lt(&refresh_limit)
 }
 
@@ -246,7 +558,25 @@ where
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use crate::{fetch::object_id::should_refetch_object, traits::tests::DbUser};
+    use crate::{
+        config::FederationConfig,
+        fetch::{cache::ObjectCache, object_id::should_refetch_object, CacheValidators},
+        protocol::verification::verify_domains_match,
+        traits::tests::{start_test_server, DbUser},
+        FEDERATION_CONTENT_TYPE,
+    };
+    use activitystreams_kinds::object::NoteType;
+    use async_trait::async_trait;
+    use axum::{
+        http::{header::IF_NONE_MATCH, HeaderMap, StatusCode, Uri},
+        response::IntoResponse,
+    };
+    use serde_json::json;
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+        Mutex,
+    };
 
     #[test]
     fn test_deserialize() {
@@ -261,10 +591,480 @@ pub mod tests {
 
     #[test]
     fn test_should_refetch_object() {
+        let one_day = Duration::from_secs(24 * 60 * 60);
         let one_second_ago = Utc::now().naive_utc() - ChronoDuration::seconds(1);
-        assert!(!should_refetch_object(one_second_ago));
+        assert!(!should_refetch_object(one_second_ago, one_day));
+        assert!(should_refetch_object(one_second_ago, Duration::ZERO));
 
         let two_days_ago = Utc::now().naive_utc() - ChronoDuration::days(2);
-        assert!(should_refetch_object(two_days_ago));
+        assert!(should_refetch_object(two_days_ago, one_day));
+        assert!(!should_refetch_object(two_days_ago, Duration::MAX));
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    struct Note {
+        id: Url,
+        #[serde(rename = "type")]
+        kind: NoteType,
+        content: String,
+    }
+
+    #[derive(Clone)]
+    struct DbNote {
+        id: Url,
+        content: String,
+        validators: Option<CacheValidators>,
+        last_refreshed_at: NaiveDateTime,
+    }
+
+    impl DbNote {
+        fn new(id: &Url, content: &str) -> Self {
+            DbNote {
+                id: id.clone(),
+                content: content.to_string(),
+                validators: None,
+                last_refreshed_at: Utc::now().naive_utc(),
+            }
+        }
+    }
+
+    /// Local database with notes keyed by id, and the number of calls to `from_json`
+    #[derive(Clone, Default)]
+    struct NoteDb(Arc<Mutex<(HashMap<Url, DbNote>, u32)>>);
+
+    impl NoteDb {
+        fn insert(&self, note: DbNote) {
+            self.0.lock().unwrap().0.insert(note.id.clone(), note);
+        }
+
+        fn get(&self, id: &Url) -> Option<DbNote> {
+            self.0.lock().unwrap().0.get(id).cloned()
+        }
+
+        fn parsed_count(&self) -> u32 {
+            self.0.lock().unwrap().1
+        }
+    }
+
+    #[async_trait]
+    impl Object for DbNote {
+        type DataType = NoteDb;
+        type Kind = Note;
+        type Error = anyhow::Error;
+
+        fn last_refreshed_at(&self) -> Option<NaiveDateTime> {
+            Some(self.last_refreshed_at)
+        }
+
+        fn cache_validators(&self) -> Option<CacheValidators> {
+            self.validators.clone()
+        }
+
+        async fn set_cache_validators(
+            mut self,
+            validators: CacheValidators,
+            data: &Data<NoteDb>,
+        ) -> Result<Self, Self::Error> {
+            self.validators = Some(validators).filter(|v| !v.is_empty());
+            data.insert(self.clone());
+            Ok(self)
+        }
+
+        async fn not_modified(mut self, data: &Data<NoteDb>) -> Result<Self, Self::Error> {
+            self.last_refreshed_at = Utc::now().naive_utc();
+            data.insert(self.clone());
+            Ok(self)
+        }
+
+        fn cache_copy(&self) -> Option<Self> {
+            Some(self.clone())
+        }
+
+        async fn read_from_id(id: Url, data: &Data<NoteDb>) -> Result<Option<Self>, Self::Error> {
+            Ok(data.get(&id))
+        }
+
+        async fn delete(self, data: &Data<NoteDb>) -> Result<(), Self::Error> {
+            data.0.lock().unwrap().0.remove(&self.id);
+            Ok(())
+        }
+
+        async fn into_json(self, _: &Data<NoteDb>) -> Result<Note, Self::Error> {
+            Err(anyhow!("not needed"))
+        }
+
+        async fn verify(
+            json: &Note,
+            expected_domain: &Url,
+            _: &Data<NoteDb>,
+        ) -> Result<(), Self::Error> {
+            verify_domains_match(&json.id, expected_domain)?;
+            Ok(())
+        }
+
+        async fn from_json(json: Note, data: &Data<NoteDb>) -> Result<Self, Self::Error> {
+            let note = DbNote::new(&json.id, &json.content);
+            data.insert(note.clone());
+            data.0.lock().unwrap().1 += 1;
+            Ok(note)
+        }
+    }
+
+    /// Starts a server which serves a note with ETag, except at `/plain`, and returns its port
+    fn start_note_server() -> u16 {
+        start_test_server(|port| {
+            axum::Router::new().fallback(move |uri: Uri, headers: HeaderMap| async move {
+                let note = json!({
+                    "id": format!("http://localhost:{port}{}", uri.path()),
+                    "type": "Note",
+                    "content": "Hello",
+                });
+                if uri.path() == "/plain" {
+                    let headers = [("content-type", FEDERATION_CONTENT_TYPE)];
+                    return (headers, note.to_string()).into_response();
+                }
+                if headers.get(IF_NONE_MATCH).is_some_and(|e| e == "\"v1\"") {
+                    return StatusCode::NOT_MODIFIED.into_response();
+                }
+                let headers = [
+                    ("etag", "\"v1\""),
+                    ("content-type", FEDERATION_CONTENT_TYPE),
+                ];
+                (headers, note.to_string()).into_response()
+            })
+        })
+    }
+
+    #[actix_rt::test]
+    async fn test_conditional_refetch() {
+        let port = start_note_server();
+        let db = NoteDb::default();
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(db.clone())
+            .debug(true)
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+        let id =
+            ObjectId::<DbNote>::parse(format!("http://localhost:{port}/note/1").as_str()).unwrap();
+
+        let note = id.dereference(&data).await.unwrap();
+        assert_eq!(note.content, "Hello");
+        assert_eq!(
+            note.validators,
+            Some(CacheValidators {
+                etag: Some("\"v1\"".to_string()),
+                last_modified: None
+            })
+        );
+        assert_eq!(db.parsed_count(), 1);
+
+        // Outdated note is refetched, but not parsed again
+        let two_days_ago = Utc::now().naive_utc() - ChronoDuration::days(2);
+        db.insert(DbNote {
+            last_refreshed_at: two_days_ago,
+            ..note
+        });
+        let note = id.dereference(&data).await.unwrap();
+        assert!(note.last_refreshed_at > two_days_ago);
+        assert_eq!(db.parsed_count(), 1);
+
+        // Forced fetch is unconditional
+        id.dereference_forced(&data).await.unwrap();
+        assert_eq!(db.parsed_count(), 2);
+
+        // Validators are removed if the response doesn't contain any
+        let id =
+            ObjectId::<DbNote>::parse(format!("http://localhost:{port}/plain").as_str()).unwrap();
+        db.insert(DbNote {
+            validators: note.validators,
+            ..DbNote::new(id.inner(), "Hello")
+        });
+        let note = id.dereference_forced(&data).await.unwrap();
+        assert_eq!(note.validators, None);
+        assert_eq!(db.get(id.inner()).unwrap().validators, None);
+    }
+
+    /// Number of requests which are currently handled, and the maximum which was reached
+    #[derive(Clone, Default)]
+    struct InFlight(Arc<(AtomicU32, AtomicU32)>);
+
+    impl InFlight {
+        async fn track(&self) {
+            let current = self.0 .0.fetch_add(1, Ordering::SeqCst) + 1;
+            self.0 .1.fetch_max(current, Ordering::SeqCst);
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            self.0 .0.fetch_sub(1, Ordering::SeqCst);
+        }
+
+        fn max(&self) -> u32 {
+            self.0 .1.load(Ordering::SeqCst)
+        }
+    }
+
+    /// Starts a server which serves a slow note for every path except `/missing` and
+    /// `/tombstone`, and returns its port. The note at `/proof` has proofs which can't be
+    /// verified.
+    fn start_slow_server(server: InFlight, total: InFlight) -> u16 {
+        start_test_server(|port| {
+            axum::Router::new().fallback(move |uri: Uri| async move {
+                tokio::join!(server.track(), total.track());
+                if uri.path() == "/missing" {
+                    return StatusCode::NOT_FOUND.into_response();
+                }
+                let kind = match uri.path() {
+                    "/tombstone" => "Tombstone",
+                    _ => "Note",
+                };
+                let mut note = json!({
+                    "id": format!("http://localhost:{port}{}", uri.path()),
+                    "type": kind,
+                    "content": uri.path(),
+                });
+                if uri.path() == "/proof" {
+                    note["proof"] = json!([
+                        { "type": "RsaSignature2017", "signatureValue": "abc" },
+                        {
+                            "type": "DataIntegrityProof",
+                            "cryptosuite": "eddsa-jcs-2022",
+                            "verificationMethod": format!("http://localhost:{port}/missing#key"),
+                            "proofPurpose": "assertionMethod",
+                            "proofValue": "z123"
+                        }
+                    ]);
+                }
+                let headers = [("content-type", FEDERATION_CONTENT_TYPE)];
+                (headers, note.to_string()).into_response()
+            })
+        })
+    }
+
+    #[actix_rt::test]
+    async fn test_dereference_many() {
+        let (a, b, total) = (
+            InFlight::default(),
+            InFlight::default(),
+            InFlight::default(),
+        );
+        let port_a = start_slow_server(a.clone(), total.clone());
+        let port_b = start_slow_server(b.clone(), total.clone());
+        let db = NoteDb::default();
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(db.clone())
+            .debug(true)
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+        let id = |port: u16, path: &str| {
+            ObjectId::<DbNote>::parse(format!("http://localhost:{port}{path}").as_str()).unwrap()
+        };
+        let ids = vec![
+            id(port_a, "/1"),
+            id(port_b, "/1"),
+            id(port_a, "/2"),
+            id(port_a, "/1"),
+            id(port_b, "/missing"),
+            id(port_a, "/3"),
+        ];
+
+        let results = ObjectId::dereference_many(ids.clone(), &data, 3).await;
+        let results: Vec<_> = results
+            .into_iter()
+            .map(|(id, res)| (id, res.map(|note| note.content).ok()))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                (ids[0].clone(), Some("/1".to_string())),
+                (ids[1].clone(), Some("/1".to_string())),
+                (ids[2].clone(), Some("/2".to_string())),
+                (ids[4].clone(), None),
+                (ids[5].clone(), Some("/3".to_string())),
+            ]
+        );
+        assert_eq!(data.request_count(), 5);
+        // Hosts are fetched in parallel, but only two objects of the same host at a time
+        assert_eq!((a.max(), total.max()), (2, 3));
+
+        // Stored objects are read from the database
+        let results = ObjectId::dereference_many(ids, &data, 3).await;
+        assert_eq!(results.len(), 5);
+        assert_eq!(data.request_count(), 6);
+    }
+
+    #[actix_rt::test]
+    async fn test_dereference_deleted() {
+        let port = start_slow_server(InFlight::default(), InFlight::default());
+        let db = NoteDb::default();
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(db.clone())
+            .debug(true)
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+
+        for path in ["/missing", "/tombstone"] {
+            let id = ObjectId::<DbNote>::parse(format!("http://localhost:{port}{path}").as_str())
+                .unwrap();
+            db.insert(DbNote::new(id.inner(), "old"));
+
+            // Local copy is deleted, and the error can be matched by callers
+            let res = id.dereference_forced(&data).await;
+            let err = res.err().and_then(|e| e.downcast::<Error>().ok());
+            assert_eq!(err, Some(Error::ObjectDeleted), "{path}");
+            assert!(db.get(id.inner()).is_none(), "{path}");
+
+            let res = id.dereference(&data).await;
+            let err = res.err().and_then(|e| e.downcast::<Error>().ok());
+            assert_eq!(err, Some(Error::ObjectDeleted), "{path}");
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_dereference_unverifiable_proof() {
+        let port = start_slow_server(InFlight::default(), InFlight::default());
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(NoteDb::default())
+            .debug(true)
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+        let id =
+            ObjectId::<DbNote>::parse(format!("http://localhost:{port}/proof").as_str()).unwrap();
+
+        // Proofs are ignored, the object is accepted by `verify`
+        let note = id.dereference(&data).await.unwrap();
+        assert_eq!(note.content, "/proof");
+    }
+
+    #[cfg(feature = "integrity-proofs")]
+    #[actix_rt::test]
+    async fn test_dereference_relayed() {
+        use crate::{
+            integrity_proofs::{create_proof, generate_ed25519_keypair, Ed25519Keypair},
+            protocol::multikey::Multikey,
+        };
+
+        /// Serves the actor with the given key at `/actor`, and the other paths from `objects`
+        fn start_server(keypair: Ed25519Keypair, objects: impl FnOnce(u16) -> Value) -> u16 {
+            start_test_server(|port| {
+                let actor = Url::parse(&format!("http://localhost:{port}/actor")).unwrap();
+                let key = Multikey::new(actor.clone(), keypair.public_key);
+                let actor = json!({ "id": actor, "type": "Person", "assertionMethod": [key] });
+                let objects = objects(port);
+                axum::Router::new().fallback(move |uri: Uri| async move {
+                    let json = match uri.path() {
+                        "/actor" => &actor,
+                        path => &objects[path],
+                    };
+                    let headers = [("content-type", FEDERATION_CONTENT_TYPE)];
+                    (headers, json.to_string()).into_response()
+                })
+            })
+        }
+        let origin_keypair = generate_ed25519_keypair();
+        let origin = start_server(origin_keypair.clone(), |_| json!({}));
+        let note_id = Url::parse(&format!("http://localhost:{origin}/note")).unwrap();
+        let note = json!({ "id": note_id, "type": "Note", "content": "Hello" });
+        let origin_key = Url::parse(&format!("http://localhost:{origin}/actor#ed25519-key"));
+        let relayed = create_proof(&note, &origin_key.unwrap(), &origin_keypair.private_key);
+        let relay_keypair = generate_ed25519_keypair();
+        let relay_private_key = relay_keypair.private_key.clone();
+        let relay = start_server(relay_keypair, |port| {
+            let relay_key = Url::parse(&format!("http://localhost:{port}/actor#ed25519-key"));
+            json!({
+                "/relayed": relayed.unwrap(),
+                "/forged": create_proof(&note, &relay_key.unwrap(), &relay_private_key).unwrap(),
+                "/unsigned": note,
+            })
+        });
+
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(NoteDb::default())
+            .debug(true)
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+        let id = |path: &str| {
+            ObjectId::<DbNote>::parse(format!("http://localhost:{relay}{path}").as_str()).unwrap()
+        };
+
+        // Relayed objects are accepted if they have a proof by an actor from their origin
+        let note = id("/relayed").dereference(&data).await.unwrap();
+        assert_eq!(note.id, note_id);
+        for path in ["/forged", "/unsigned"] {
+            let res = id(path).dereference(&data).await;
+            let err = res.err().unwrap();
+            assert_eq!(
+                err.downcast_ref::<Error>(),
+                Some(&Error::FetchedIdMismatch(note_id.clone()))
+            );
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_dereference_object_cache() {
+        let port = start_slow_server(InFlight::default(), InFlight::default());
+        let db = NoteDb::default();
+        let cache = ObjectCache::new(std::time::Duration::from_secs(60), 10);
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(db.clone())
+            .object_cache(cache.clone())
+            .debug(true)
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+        let id = ObjectId::<DbNote>::parse(format!("http://localhost:{port}/1").as_str()).unwrap();
+
+        id.dereference(&data).await.unwrap();
+        assert_eq!(data.request_count(), 1);
+
+        // Cached object is returned without reading the database
+        db.insert(DbNote::new(id.inner(), "changed"));
+        let note = id.dereference(&data).await.unwrap();
+        assert_eq!(note.content, "/1");
+        assert_eq!(data.request_count(), 1);
+
+        cache.invalidate(id.inner());
+        let note = id.dereference(&data).await.unwrap();
+        assert_eq!(note.content, "changed");
+
+        // Forced fetch replaces the cached object
+        id.dereference_forced(&data).await.unwrap();
+        assert_eq!(data.request_count(), 2);
+        db.0.lock().unwrap().0.clear();
+        let note = id.dereference(&data).await.unwrap();
+        assert_eq!(note.content, "/1");
+        assert_eq!(data.request_count(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn test_dereference_object_cache_concurrent() {
+        let port = start_slow_server(InFlight::default(), InFlight::default());
+        let db = NoteDb::default();
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(db.clone())
+            .object_cache(ObjectCache::new(std::time::Duration::from_secs(60), 10))
+            .debug(true)
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+        let id = ObjectId::<DbNote>::parse(format!("http://localhost:{port}/1").as_str()).unwrap();
+
+        // Concurrent dereferences of the same object only fetch it once
+        let (notes, many) = tokio::join!(
+            futures_util::future::join_all((0..5).map(|_| id.dereference(&data))),
+            ObjectId::dereference_many([id.clone()], &data, 2)
+        );
+        assert!(notes.iter().chain([&many[0].1]).all(|note| note.is_ok()));
+        assert_eq!(data.request_count(), 1);
+        assert_eq!(db.parsed_count(), 1);
     }
 }
@@ -1,7 +1,7 @@
 use crate::{
     config::Data,
     error::{Error, Error::WebfingerResolveFailed},
-    fetch::{fetch_object_http, object_id::ObjectId},
+    fetch::{fetch_json_http, object_id::ObjectId},
     traits::{Actor, Object},
     FEDERATION_CONTENT_TYPE,
 };
@@ -13,6 +13,9 @@ use std::collections::HashMap;
 use tracing::debug;
 use url::Url;
 
+/// Accept header for webfinger requests, see <https://www.rfc-editor.org/rfc/rfc7033#section-10.2>
+const WEBFINGER_ACCEPT: &str = "application/jrd+json, application/json";
+
 /// Takes an identifier of the form `name@example.com`, and returns an object of `Kind`.
 ///
 /// For this the identifier is first resolved via webfinger protocol to an Activitypub ID. This ID
@@ -36,7 +39,7 @@ where
         format!("{protocol}://{domain}/.well-known/webfinger?resource=acct:{identifier}");
     debug!("Fetching webfinger url: {}", &fetch_url);
 
-    let res: Webfinger = fetch_object_http(&Url::parse(&fetch_url)?, data).await?;
+    let res: Webfinger = fetch_json_http(&Url::parse(&fetch_url)?, data, WEBFINGER_ACCEPT).await?;
 
     debug_assert_eq!(res.subject, format!("acct:{identifier}"));
     let links: Vec<Url> = res
@@ -5,22 +5,30 @@
 //! [receive_activity (actix-web)](crate::actix_web::inbox::receive_activity) /
 //! [receive_activity (axum)](crate::axum::inbox::receive_activity).
 
+#[cfg(feature = "integrity-proofs")]
+use crate::integrity_proofs::{verify_multikey_signature, verify_proof};
+#[cfg(feature = "ld-signatures")]
+use crate::ld_signatures::verify_ld_signature;
 use crate::{
+    config::Data,
+    crypto::{default_crypto_backend, CryptoBackend},
     error::{Error, Error::ActivitySignatureInvalid},
-    protocol::public_key::main_key_id,
+    fetch::fetch_object_http,
+    protocol::{
+        public_key::{KeyDocument, VerificationKey},
+        verification::verify_domains_match,
+    },
+    traits::Actor,
 };
 use http::{header::HeaderName, uri::PathAndQuery, HeaderValue, Method, Uri};
+use http_signature_normalization::verify::Unverified;
 use http_signature_normalization_reqwest::prelude::{Config, SignExt};
+use itertools::Itertools;
 use once_cell::sync::{Lazy, OnceCell};
-use openssl::{
-    hash::MessageDigest,
-    pkey::PKey,
-    rsa::Rsa,
-    sign::{Signer, Verifier},
-};
 use reqwest::Request;
 use reqwest_middleware::RequestBuilder;
-use sha2::{Digest, Sha256};
+use serde_json::Value;
+use sha2::{Digest, Sha256, Sha512};
 use std::{collections::BTreeMap, fmt::Debug, io::ErrorKind};
 use tracing::debug;
 use url::Url;
@@ -37,34 +45,25 @@ pub struct Keypair {
 }
 
 /// Generate a random asymmetric keypair for ActivityPub HTTP signatures.
+///
+/// Uses the [CryptoBackend] which is selected by cargo features, see [crate::crypto].
 pub fn generate_actor_keypair() -> Result<Keypair, std::io::Error> {
-    let rsa = Rsa::generate(2048)?;
-    let pkey = PKey::from_rsa(rsa)?;
-    let public_key = pkey.public_key_to_pem()?;
-    let private_key = pkey.private_key_to_pem_pkcs8()?;
-    let key_to_string = |key| match String::from_utf8(key) {
-        Ok(s) => Ok(s),
-        Err(e) => Err(std::io::Error::new(
-            ErrorKind::Other,
-            format!("Failed converting key to string: {}", e),
-        )),
-    };
-    Ok(Keypair {
-        private_key: key_to_string(private_key)?,
-        public_key: key_to_string(public_key)?,
-    })
+    default_crypto_backend()
+        .generate_keypair()
+        .map_err(|e| std::io::Error::new(ErrorKind::Other, e))
 }
 
 /// Creates an HTTP post request to `inbox_url`, with the given `client` and `headers`, and
-/// `activity` as request body. The request is signed with `private_key` and then sent.
+/// `activity` as request body. The request is signed with `private_key`, which is identified by
+/// `key_id` in the signature header.
 pub(crate) async fn sign_request(
     request_builder: RequestBuilder,
-    actor_id: Url,
+    key_id: String,
     activity: String,
     private_key: String,
     http_signature_compat: bool,
+    crypto: Box<dyn CryptoBackend>,
 ) -> Result<Request, anyhow::Error> {
-    let key_id = main_key_id(&actor_id);
     let sig_conf = HTTP_SIG_CONFIG.get_or_init(|| {
         let c = Config::new();
         if http_signature_compat {
@@ -80,11 +79,8 @@ pub(crate) async fn sign_request(
             Sha256::new(),
             activity,
             move |signing_string| {
-                let private_key = PKey::private_key_from_pem(private_key.as_bytes())?;
-                let mut signer = Signer::new(MessageDigest::sha256(), &private_key)?;
-                signer.update(signing_string.as_bytes())?;
-
-                Ok(base64::encode(signer.sign_to_vec()?)) as Result<_, anyhow::Error>
+                let signature = crypto.sign(&private_key, signing_string.as_bytes())?;
+                Ok(base64::encode(signature)) as Result<_, anyhow::Error>
             },
         )
         .await
@@ -93,13 +89,157 @@ pub(crate) async fn sign_request(
 static CONFIG2: Lazy<http_signature_normalization::Config> =
     Lazy::new(http_signature_normalization::Config::new);
 
-/// Verifies the HTTP signature on an incoming inbox request.
-pub(crate) fn verify_signature<'a, H>(
+/// Verifies that an incoming inbox request was made by the given actor.
+///
+/// Normally this checks the HTTP signature. If that fails, the activity is also accepted if it
+/// contains a valid [integrity proof](crate::integrity_proofs) by the actor, or if
+/// [FederationConfigBuilder::ld_signatures](crate::config::FederationConfigBuilder::ld_signatures)
+/// is enabled, a Linked Data Signature by the actor. This is the case for activities which are
+/// forwarded by another server.
+///
+/// Returns the signed part of the activity if it was verified with a Linked Data Signature,
+/// which must be used instead of the body, see
+/// [verify_ld_signature](crate::ld_signatures::verify_ld_signature).
+#[cfg_attr(
+    not(any(feature = "integrity-proofs", feature = "ld-signatures")),
+    allow(unused_variables)
+)]
+pub(crate) async fn verify_activity_signature<'a, H, A, T>(
+    headers: H,
+    method: &Method,
+    uri: &Uri,
+    body: &[u8],
+    actor: &A,
+    data: &Data<T>,
+) -> Result<Option<Value>, Error>
+where
+    H: IntoIterator<Item = (&'a HeaderName, &'a HeaderValue)>,
+    A: Actor,
+    T: Clone,
+{
+    let http_signature = verify_signature_for_actor(headers, method, uri, actor, data).await;
+    let Err(e) = http_signature else {
+        return Ok(None);
+    };
+    let json: Value = serde_json::from_slice(body).map_err(Error::other)?;
+    #[cfg(feature = "integrity-proofs")]
+    if json.get("proof").is_some() {
+        match verify_proof(&json, actor, data).await {
+            Ok(()) => return Ok(None),
+            Err(proof_error) => debug!("Invalid integrity proof: {}", proof_error),
+        }
+    }
+    #[cfg(feature = "ld-signatures")]
+    if data.config.ld_signatures && json.get("signature").is_some() {
+        match verify_ld_signature(&json, actor, data).await {
+            Ok(signed) => return Ok(Some(signed)),
+            Err(ld_error) => debug!("Invalid linked data signature: {}", ld_error),
+        }
+    }
+    Err(e)
+}
+
+/// Verifies the HTTP signature on an incoming inbox request, made by the given actor.
+pub(crate) async fn verify_signature_for_actor<'a, H, A, T>(
     headers: H,
     method: &Method,
     uri: &Uri,
-    public_key: &str,
+    actor: &A,
+    data: &Data<T>,
 ) -> Result<(), Error>
+where
+    H: IntoIterator<Item = (&'a HeaderName, &'a HeaderValue)>,
+    A: Actor,
+    T: Clone,
+{
+    let unverified = begin_verify(headers, method, uri)?;
+    let key = actor_key(unverified.key_id(), actor, data).await?;
+    verify_unverified(&unverified, &key, data.config.crypto_backend.as_ref())
+}
+
+/// Returns the key identified by `key_id`, which must belong to `actor`.
+///
+/// - Keys in [Actor::public_keys] and [Actor::assertion_methods] are found by their id.
+/// - If the key id points to a separate document (not the actor itself), the key is fetched
+///   from there. Its `owner` or `controller` must be the actor.
+///
+/// Otherwise [Error::ActivitySignatureKeyUnknown] is returned.
+pub(crate) async fn actor_key<A, T>(
+    key_id: &str,
+    actor: &A,
+    data: &Data<T>,
+) -> Result<VerificationKey, Error>
+where
+    A: Actor,
+    T: Clone,
+{
+    let key = actor
+        .public_keys()
+        .into_iter()
+        .map(VerificationKey::Pem)
+        .chain(
+            actor
+                .assertion_methods()
+                .into_iter()
+                .map(VerificationKey::Multikey),
+        )
+        .find(|k| k.id() == key_id);
+    if let Some(key) = key {
+        return Ok(key);
+    }
+
+    let unknown = || Error::ActivitySignatureKeyUnknown(key_id.to_string());
+    let key_url = Url::parse(key_id).map_err(|_| unknown())?;
+    let mut key_document = key_url.clone();
+    key_document.set_fragment(None);
+    if key_document == actor.id() {
+        // The key would be part of the actor json, which we already checked
+        return Err(unknown());
+    }
+    fetch_public_key(&key_url, &actor.id(), data).await
+}
+
+/// Fetches a key which is published in a document separate from the actor.
+async fn fetch_public_key<T: Clone>(
+    key_id: &Url,
+    owner: &Url,
+    data: &Data<T>,
+) -> Result<VerificationKey, Error> {
+    verify_domains_match(key_id, owner)?;
+    let document: KeyDocument = fetch_object_http(key_id, data).await?;
+    let key = document
+        .into_keys()
+        .into_iter()
+        .find(|k| k.id() == key_id.as_str())
+        .ok_or_else(|| Error::ActivitySignatureKeyUnknown(key_id.to_string()))?;
+    if key.owner() != owner {
+        debug!("Key {} is not owned by {}", key_id, owner);
+        return Err(ActivitySignatureInvalid);
+    }
+    Ok(key)
+}
+
+/// Returns `Ok(true)` if `signature` is a valid signature of `message` by `key`. Ed25519 keys can
+/// only be used with the `integrity-proofs` feature.
+pub(crate) fn verify_with_key(
+    key: &VerificationKey,
+    message: &[u8],
+    signature: &[u8],
+    crypto: &dyn CryptoBackend,
+) -> Result<bool, Error> {
+    match key {
+        VerificationKey::Pem(key) => crypto.verify(&key.public_key_pem, message, signature),
+        #[cfg(feature = "integrity-proofs")]
+        VerificationKey::Multikey(key) => {
+            Ok(verify_multikey_signature(key, message, signature).is_ok())
+        }
+        #[cfg(not(feature = "integrity-proofs"))]
+        VerificationKey::Multikey(_) => Ok(false),
+    }
+}
+
+/// Parses the signature header of an incoming request.
+fn begin_verify<'a, H>(headers: H, method: &Method, uri: &Uri) -> Result<Unverified, Error>
 where
     H: IntoIterator<Item = (&'a HeaderName, &'a HeaderValue)>,
 {
@@ -111,45 +251,81 @@ where
     }
     let path_and_query = uri.path_and_query().map(PathAndQuery::as_str).unwrap_or("");
 
-    let verified = CONFIG2
+    CONFIG2
         .begin_verify(method.as_str(), path_and_query, header_map)
-        .map_err(Error::other)?
+        .map_err(Error::other)
+}
+
+fn verify_unverified(
+    unverified: &Unverified,
+    key: &VerificationKey,
+    crypto: &dyn CryptoBackend,
+) -> Result<(), Error> {
+    let verified = unverified
         .verify(|signature, signing_string| -> anyhow::Result<bool> {
             debug!(
                 "Verifying with key {}, message {}",
-                &public_key, &signing_string
+                key.id(),
+                &signing_string
             );
-            let public_key = PKey::public_key_from_pem(public_key.as_bytes())?;
-            let mut verifier = Verifier::new(MessageDigest::sha256(), &public_key)?;
-            verifier.update(signing_string.as_bytes())?;
-            Ok(verifier.verify(&base64::decode(signature)?)?)
+            Ok(verify_with_key(
+                key,
+                signing_string.as_bytes(),
+                &base64::decode(signature)?,
+                crypto,
+            )?)
         })
         .map_err(Error::other)?;
 
     if verified {
-        debug!("verified signature for {}", uri);
+        debug!("verified signature with key {}", unverified.key_id());
         Ok(())
     } else {
         Err(ActivitySignatureInvalid)
     }
 }
 
+/// Hash algorithms which are supported in `Digest` and `Content-Digest` headers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DigestAlgorithm {
+    Sha256,
+    Sha512,
+}
+
+impl DigestAlgorithm {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "sha-256" => Some(DigestAlgorithm::Sha256),
+            "sha-512" => Some(DigestAlgorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    fn hash(self, body: &[u8]) -> Vec<u8> {
+        match self {
+            DigestAlgorithm::Sha256 => Sha256::digest(body).to_vec(),
+            DigestAlgorithm::Sha512 => Sha512::digest(body).to_vec(),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 struct DigestPart {
-    /// We assume that SHA256 is used which is the case with all major fediverse platforms
-    #[allow(dead_code)]
+    /// Name of the hash algorithm, eg `SHA-256`
     pub algorithm: String,
-    /// The hashsum
+    /// The base64 encoded hashsum
     pub digest: String,
 }
 
 impl DigestPart {
+    /// Parses a `Digest` header as defined in RFC 3230, eg
+    /// `SHA-256=X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=`
     fn try_from_header(h: &HeaderValue) -> Option<Vec<DigestPart>> {
         let h = h.to_str().ok()?.split(';').next()?;
         let v: Vec<_> = h
             .split(',')
             .filter_map(|p| {
-                let mut iter = p.splitn(2, '=');
+                let mut iter = p.trim().splitn(2, '=');
                 iter.next()
                     .and_then(|alg| iter.next().map(|value| (alg, value)))
             })
@@ -165,24 +341,302 @@ impl DigestPart {
             Some(v)
         }
     }
+
+    /// Parses a `Content-Digest` header as defined in RFC 9530, eg
+    /// `sha-256=:X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=:`
+    fn try_from_content_digest(h: &HeaderValue) -> Option<Vec<DigestPart>> {
+        let v: Vec<_> = h
+            .to_str()
+            .ok()?
+            .split(',')
+            .filter_map(|p| {
+                let (alg, value) = p.trim().split_once('=')?;
+                // Ignore parameters of the dictionary member
+                let value = value.split(';').next()?;
+                let value = value.strip_prefix(':')?.strip_suffix(':')?;
+                Some(DigestPart {
+                    algorithm: alg.to_owned(),
+                    digest: value.to_owned(),
+                })
+            })
+            .collect();
+
+        if v.is_empty() {
+            None
+        } else {
+            Some(v)
+        }
+    }
 }
 
-/// Verify body of an inbox request against the hash provided in `Digest` header.
+/// Verify body of an inbox request against the hashes provided in `Digest` and `Content-Digest`
+/// headers.
+///
+/// At least one of the headers must be present. Every hash with a supported algorithm (SHA-256
+/// or SHA-512) must match the body, hashes with other algorithms are ignored. If no hash uses a
+/// supported algorithm, [Error::ActivityBodyDigestUnsupported] is returned.
 pub(crate) fn verify_inbox_hash(
     digest_header: Option<&HeaderValue>,
+    content_digest_header: Option<&HeaderValue>,
     body: &[u8],
 ) -> Result<(), Error> {
-    let digest = digest_header
-        .and_then(DigestPart::try_from_header)
-        .ok_or(Error::ActivityBodyDigestInvalid)?;
-    let mut hasher = Sha256::new();
-
-    for part in digest {
-        hasher.update(body);
-        if base64::encode(hasher.finalize_reset()) != part.digest {
+    let mut parts = vec![];
+    if let Some(header) = digest_header {
+        parts.extend(DigestPart::try_from_header(header).ok_or(Error::ActivityBodyDigestInvalid)?);
+    }
+    if let Some(header) = content_digest_header {
+        parts.extend(
+            DigestPart::try_from_content_digest(header).ok_or(Error::ActivityBodyDigestInvalid)?,
+        );
+    }
+    if parts.is_empty() {
+        return Err(Error::ActivityBodyDigestInvalid);
+    }
+
+    let mut verified = false;
+    for part in &parts {
+        let Some(algorithm) = DigestAlgorithm::from_name(&part.algorithm) else {
+            continue;
+        };
+        let digest = base64::decode(&part.digest).map_err(|_| Error::ActivityBodyDigestInvalid)?;
+        if algorithm.hash(body) != digest {
             return Err(Error::ActivityBodyDigestInvalid);
         }
+        verified = true;
+    }
+
+    if verified {
+        Ok(())
+    } else {
+        Err(Error::ActivityBodyDigestUnsupported(
+            parts.iter().map(|p| &p.algorithm).join(", "),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::FederationConfig,
+        traits::tests::{start_test_server, DbConnection, DbUser, DB_USER, DB_USER_KEYPAIR},
+        FEDERATION_CONTENT_TYPE,
+    };
+    use axum::{extract::Path, http::header::CONTENT_TYPE, routing::get, Router};
+    use reqwest::Client;
+    use reqwest_middleware::ClientWithMiddleware;
+    use serde_json::json;
+
+    async fn signed_request(key_id: &str) -> Request {
+        let request_builder =
+            ClientWithMiddleware::from(Client::default()).post("https://example.com/inbox");
+        sign_request(
+            request_builder,
+            key_id.to_string(),
+            "{}".to_string(),
+            DB_USER_KEYPAIR.private_key.clone(),
+            false,
+            default_crypto_backend(),
+        )
+        .await
+        .unwrap()
     }
 
-    Ok(())
+    async fn verify(request: &Request, path: &str) -> Result<(), Error> {
+        verify_with_actor(request, path, &DB_USER).await
+    }
+
+    async fn verify_with_actor(request: &Request, path: &str, actor: &DbUser) -> Result<(), Error> {
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(DbConnection)
+            .debug(true)
+            .build()
+            .unwrap();
+        let uri: Uri = path.parse().unwrap();
+        verify_signature_for_actor(
+            request.headers(),
+            request.method(),
+            &uri,
+            actor,
+            &config.to_request_data(),
+        )
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_verify_signature_key_id() {
+        // key id is listed in actor public keys
+        let request = signed_request("https://localhost/123#main-key").await;
+        assert!(verify(&request, "/inbox").await.is_ok());
+        assert_eq!(
+            verify(&request, "/wrong").await,
+            Err(ActivitySignatureInvalid)
+        );
+
+        // key id is unknown, but belongs to the actor document
+        let request = signed_request("https://localhost/123#other-key").await;
+        assert!(matches!(
+            verify(&request, "/inbox").await,
+            Err(Error::ActivitySignatureKeyUnknown(key_id)) if key_id == "https://localhost/123#other-key"
+        ));
+    }
+
+    #[cfg(feature = "integrity-proofs")]
+    #[actix_rt::test]
+    async fn test_verify_signature_multikey() {
+        use crate::{integrity_proofs::signing_key, traits::tests::DB_USER_ED25519_KEYPAIR};
+        use ed25519_dalek::Signer;
+
+        // Signed with the Ed25519 key from `assertionMethod`
+        let key_id = DB_USER.assertion_methods()[0].id.clone();
+        let signing_key = signing_key(&DB_USER_ED25519_KEYPAIR.private_key).unwrap();
+        let request = ClientWithMiddleware::from(Client::default())
+            .post("https://example.com/inbox")
+            .signature_with_digest(
+                Config::new(),
+                key_id,
+                Sha256::new(),
+                "{}".to_string(),
+                move |signing_string| {
+                    let signature = signing_key.sign(signing_string.as_bytes());
+                    Ok(base64::encode(signature.to_bytes())) as Result<_, anyhow::Error>
+                },
+            )
+            .await
+            .unwrap();
+        assert!(verify(&request, "/inbox").await.is_ok());
+        assert_eq!(
+            verify(&request, "/wrong").await,
+            Err(ActivitySignatureInvalid)
+        );
+    }
+
+    /// Starts a server with key documents which are separate from the actor, and returns its port.
+    /// `/key/alice` belongs to `/u/alice`, `/key/bob` to `/u/bob`.
+    fn start_key_server() -> u16 {
+        start_test_server(|port| {
+            Router::new().route(
+                "/key/:name",
+                get(move |Path(name): Path<String>| async move {
+                    let key = json!({
+                        "id": format!("http://localhost:{port}/key/{name}"),
+                        "owner": format!("http://localhost:{port}/u/{name}"),
+                        "publicKeyPem": DB_USER_KEYPAIR.public_key,
+                    });
+                    ([(CONTENT_TYPE, FEDERATION_CONTENT_TYPE)], key.to_string())
+                }),
+            )
+        })
+    }
+
+    #[actix_rt::test]
+    async fn test_verify_signature_key_document() {
+        let port = start_key_server();
+        // Actor with a different key in its own document
+        let mut actor = DB_USER.clone();
+        actor.federation_id = format!("http://localhost:{port}/u/alice").parse().unwrap();
+        actor.public_key = generate_actor_keypair().unwrap().public_key;
+
+        // Key is fetched from the separate document, and owned by the actor
+        let request = signed_request(&format!("http://localhost:{port}/key/alice")).await;
+        assert!(verify_with_actor(&request, "/inbox", &actor).await.is_ok());
+        assert_eq!(
+            verify_with_actor(&request, "/wrong", &actor).await,
+            Err(ActivitySignatureInvalid)
+        );
+
+        // Key is owned by another actor
+        let request = signed_request(&format!("http://localhost:{port}/key/bob")).await;
+        assert_eq!(
+            verify_with_actor(&request, "/inbox", &actor).await,
+            Err(ActivitySignatureInvalid)
+        );
+    }
+
+    #[test]
+    fn test_key_document() {
+        let actor = json!({
+            "id": "https://example.com/u/alice",
+            "type": "Person",
+            "publicKey": {
+                "id": "https://example.com/u/alice#main-key",
+                "owner": "https://example.com/u/alice",
+                "publicKeyPem": DB_USER_KEYPAIR.public_key,
+            },
+            "assertionMethod": [{
+                "id": "https://example.com/u/alice#ed25519-key",
+                "type": "Multikey",
+                "controller": "https://example.com/u/alice",
+                "publicKeyMultibase": "z6MkrJVnaZkeFzdQyMZu1cgjg7k1pZZ6pvBQ7XJPt4swbTQ2",
+            }],
+        });
+        let document: KeyDocument = serde_json::from_value(actor).unwrap();
+        let keys = document.into_keys();
+        assert_eq!(
+            keys.iter().map(VerificationKey::id).collect::<Vec<_>>(),
+            vec![
+                "https://example.com/u/alice#main-key",
+                "https://example.com/u/alice#ed25519-key"
+            ]
+        );
+        assert!(matches!(keys[1], VerificationKey::Multikey(_)));
+        assert!(keys
+            .iter()
+            .all(|k| k.owner().as_str() == "https://example.com/u/alice"));
+    }
+
+    #[test]
+    fn test_verify_inbox_hash() {
+        let body = b"{}";
+        let sha256 = base64::encode(Sha256::digest(body));
+        let sha512 = base64::encode(Sha512::digest(body));
+        let header = |value: String| Some(HeaderValue::from_str(&value).unwrap());
+        let digest = |value: String| verify_inbox_hash(header(value).as_ref(), None, body);
+
+        assert_eq!(digest(format!("SHA-256={sha256}")), Ok(()));
+        assert_eq!(digest(format!("sha-512={sha512}")), Ok(()));
+        assert_eq!(digest(format!("SHA-256={sha256},SHA-512={sha512}")), Ok(()));
+        assert_eq!(digest(format!("MD5=abc, SHA-512={sha512}")), Ok(()));
+        assert_eq!(
+            digest(format!("SHA-512={sha256}")),
+            Err(Error::ActivityBodyDigestInvalid)
+        );
+        assert_eq!(
+            digest(format!("SHA-256={sha256},SHA-512={sha256}")),
+            Err(Error::ActivityBodyDigestInvalid)
+        );
+        // `PartialEq` of `Error` only compares variants, so check the message explicitly
+        assert!(matches!(
+            digest("MD5=abc, SHA-1=def".to_string()),
+            Err(Error::ActivityBodyDigestUnsupported(algorithms)) if algorithms == "MD5, SHA-1"
+        ));
+        assert_eq!(
+            verify_inbox_hash(None, None, body),
+            Err(Error::ActivityBodyDigestInvalid)
+        );
+
+        let content_digest = |value: String| verify_inbox_hash(None, header(value).as_ref(), body);
+        assert_eq!(content_digest(format!("sha-256=:{sha256}:")), Ok(()));
+        assert_eq!(
+            content_digest(format!("sha-512=:{sha512}:, sha-256=:{sha256}:")),
+            Ok(())
+        );
+        assert_eq!(
+            content_digest(format!("sha-512=:{sha256}:")),
+            Err(Error::ActivityBodyDigestInvalid)
+        );
+        assert_eq!(
+            content_digest(format!("sha-256={sha256}")),
+            Err(Error::ActivityBodyDigestInvalid)
+        );
+        assert_eq!(
+            verify_inbox_hash(
+                header(format!("SHA-256={sha256}")).as_ref(),
+                header(format!("sha-512=:{sha256}:")).as_ref(),
+                body
+            ),
+            Err(Error::ActivityBodyDigestInvalid)
+        );
+    }
 }
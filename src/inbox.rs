@@ -0,0 +1,539 @@
+//! Framework independent handling of incoming activities
+//!
+//! [receive_activity] works with plain [http] types, so that it can be used with any web
+//! framework. The modules for [actix-web](crate::actix_web::inbox) and [axum](crate::axum::inbox)
+//! are thin wrappers around it. Other frameworks only need to convert the incoming request:
+//!
+//! ```
+//! # use activitypub_federation::config::{Data, FederationConfig};
+//! # use activitypub_federation::inbox::receive_activity;
+//! # use activitypub_federation::traits::tests::{DbConnection, DbUser, Follow};
+//! # use bytes::Bytes;
+//! async fn inbox(
+//!     request: http::Request<Bytes>,
+//!     data: &Data<DbConnection>,
+//! ) -> Result<http::StatusCode, activitypub_federation::error::Error> {
+//!     let outcome = receive_activity::<Follow, DbUser, DbConnection>(request, data).await?;
+//!     Ok(outcome.status())
+//! }
+//! ```
+
+use crate::{
+    activity_queue::RawActivity,
+    config::Data,
+    error::Error,
+    fetch::object_id::{Dereferenced, ObjectId},
+    http_signatures::{verify_activity_signature, verify_inbox_hash},
+    inbox_policy::{apply_inbox_policies, PolicyActor},
+    incoming_queue::queue_incoming_activity,
+    traits::{ActivityHandler, Actor, Object},
+};
+use anyhow::anyhow;
+use bytes::Bytes;
+use http::{header::CONTENT_TYPE, request::Parts, HeaderMap, Request, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::Value;
+use std::fmt::Display;
+use tracing::debug;
+use url::Url;
+
+/// Result of successfully handling an incoming activity
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReceiveOutcome {
+    /// Id of the activity
+    pub id: Url,
+    /// Value of the activity's `type` field
+    pub kind: String,
+    /// Actor who sent the activity, with verified signature
+    pub actor: Url,
+    /// True if an activity with the same id was received successfully before. The activity is
+    /// handled anyway, as it may be addressed to a different inbox.
+    pub duplicate: bool,
+    /// True if the activity is a `Delete` of its actor, which was already deleted on its server.
+    /// The local copy of the actor was removed with [Object::delete], and the activity was not
+    /// passed to handlers.
+    pub actor_deleted: bool,
+    /// True if the activity was added to the [incoming queue](crate::incoming_queue), instead
+    /// of being received directly
+    pub deferred: bool,
+    /// Number of outgoing HTTP requests which were made while receiving the activity, see
+    /// [Data::request_count]. Requests made by the incoming queue are not included.
+    pub request_count: u32,
+}
+
+impl ReceiveOutcome {
+    fn new(id: &Url, actor: &Url, body: &[u8]) -> Self {
+        ReceiveOutcome {
+            id: id.clone(),
+            kind: activity_kind(body),
+            actor: actor.clone(),
+            duplicate: false,
+            actor_deleted: false,
+            deferred: false,
+            request_count: 0,
+        }
+    }
+
+    /// HTTP status which should be returned to the sender, `202 Accepted` for deferred
+    /// activities and `200 OK` otherwise
+    pub fn status(&self) -> StatusCode {
+        if self.deferred {
+            StatusCode::ACCEPTED
+        } else {
+            StatusCode::OK
+        }
+    }
+}
+
+/// Handles incoming activities, verifying HTTP signatures and other checks
+///
+/// The request body size and content type are checked first, the content type needs to be
+/// `application/activity+json` or `application/ld+json` with the ActivityStreams profile. Then
+/// the body digest and signature are verified, the activity is parsed and its actor
+/// dereferenced. The activity is passed to the [inbox policies](crate::inbox_policy), and
+/// finally to the respective [trait@ActivityHandler], or added to the
+/// [incoming queue](crate::incoming_queue) if it is enabled.
+pub async fn receive_activity<Activity, ActorT, Datatype>(
+    request: Request<Bytes>,
+    data: &Data<Datatype>,
+) -> Result<ReceiveOutcome, <Activity as ActivityHandler>::Error>
+where
+    Activity: ActivityHandler<DataType = Datatype> + DeserializeOwned + Send + 'static,
+    ActorT: Object<DataType = Datatype> + Actor + Send + Sync + 'static,
+    for<'de2> <ActorT as Object>::Kind: serde::Deserialize<'de2>,
+    <Activity as ActivityHandler>::Error: From<anyhow::Error>
+        + From<Error>
+        + From<<ActorT as Object>::Error>
+        + From<serde_json::Error>,
+    <ActorT as Object>::Error: From<Error> + From<anyhow::Error>,
+    <Activity as ActivityHandler>::Error: Display + 'static,
+    Datatype: Clone + Send + Sync + 'static,
+{
+    let (parts, body) = request.into_parts();
+    verify_inbox_request(&parts.headers, &body, data.config.max_inbox_body_size)?;
+    verify_inbox_hash(
+        parts.headers.get("Digest"),
+        parts.headers.get("Content-Digest"),
+        &body,
+    )?;
+
+    let activity: Activity = serde_json::from_slice(&body).map_err(Error::ActivityParseError)?;
+    data.config.verify_url_and_domain(&activity).await?;
+    let mut outcome = ReceiveOutcome::new(activity.id(), activity.actor(), &body);
+    let actor = match ObjectId::<ActorT>::from(activity.actor().clone())
+        .dereference_or_deleted(data)
+        .await?
+    {
+        Dereferenced::Object(actor) => actor,
+        Dereferenced::Deleted(cached) => {
+            receive_from_deleted_actor(&parts, &body, &mut outcome, cached, data).await?;
+            outcome.request_count = data.request_count();
+            return Ok(outcome);
+        }
+    };
+
+    let signed = verify_activity_signature(
+        &parts.headers,
+        &parts.method,
+        &parts.uri,
+        &body,
+        &actor,
+        data,
+    )
+    .await?;
+    // Only pass on the properties which are covered by the Linked Data Signature
+    let (activity, body) = match signed {
+        Some(signed) => (
+            serde_json::from_value(signed.clone()).map_err(Error::ActivityParseError)?,
+            serde_json::to_vec(&signed)?.into(),
+        ),
+        None => (activity, body),
+    };
+
+    outcome.duplicate = data.config.received_activities.contains(&outcome.id);
+
+    // Activity which is passed to handlers, possibly rewritten by inbox policies
+    let mut activity = activity;
+    let mut raw = RawActivity::new(outcome.id.clone(), outcome.actor.clone(), body.clone());
+    let policies = &data.config.inbox_policies;
+    let policy_actor = PolicyActor::new(&actor);
+    if let Some(rewritten) = apply_inbox_policies(policies, &body, &policy_actor).await? {
+        let rewritten_activity: Activity =
+            serde_json::from_value(rewritten.clone()).map_err(Error::ActivityParseError)?;
+        if rewritten_activity.id() != activity.id()
+            || rewritten_activity.actor() != activity.actor()
+        {
+            return Err(
+                Error::other(anyhow!("Inbox policy must not change activity id or actor")).into(),
+            );
+        }
+        activity = rewritten_activity;
+        raw = raw.with_rewritten(serde_json::to_vec(&rewritten)?.into());
+    }
+
+    if data.config.incoming_queue {
+        debug!("Queueing incoming activity {}", outcome.id);
+        queue_incoming_activity::<Activity, Datatype>(raw, data)?;
+        data.config.received_activities.insert(outcome.id.clone());
+        outcome.deferred = true;
+        outcome.request_count = data.request_count();
+        return Ok(outcome);
+    }
+
+    debug!("Receiving activity {}", outcome.id);
+    let data = &data.with_received_activity(raw);
+    activity.verify(data).await?;
+    activity.receive(data).await?;
+    data.config.received_activities.insert(outcome.id.clone());
+    outcome.request_count = data.request_count();
+    Ok(outcome)
+}
+
+/// Handles an activity whose actor was deleted on its server, so that its key can't be fetched
+/// anymore. This is the case for the `Delete` activity which announces the deletion.
+///
+/// If the activity is a `Delete` of the actor itself and the actor is stored locally, the
+/// signature is verified with the cached key and [Object::delete] is called. If the actor is not
+/// stored, the activity can't be verified, and a `Delete` is only acknowledged to stop the
+/// sender from retrying. In both cases the activity is not passed to handlers, as it can't be
+/// processed without the actor. All other activities are rejected with [Error::ObjectDeleted].
+async fn receive_from_deleted_actor<ActorT, Datatype>(
+    parts: &Parts,
+    body: &[u8],
+    outcome: &mut ReceiveOutcome,
+    cached_actor: Option<ActorT>,
+    data: &Data<Datatype>,
+) -> Result<(), <ActorT as Object>::Error>
+where
+    ActorT: Object<DataType = Datatype> + Actor,
+    <ActorT as Object>::Error: From<Error>,
+    Datatype: Clone,
+{
+    match cached_actor {
+        Some(actor) if outcome.kind == "Delete" && deletes_actor(body, &outcome.actor) => {
+            verify_activity_signature(
+                &parts.headers,
+                &parts.method,
+                &parts.uri,
+                body,
+                &actor,
+                data,
+            )
+            .await?;
+            debug!("Actor {} was deleted, removing it", outcome.actor);
+            actor.delete(data).await?;
+            outcome.actor_deleted = true;
+            Ok(())
+        }
+        None if outcome.kind == "Delete" => {
+            debug!("Acknowledging delete of unknown actor {}", outcome.actor);
+            Ok(())
+        }
+        _ => Err(Error::ObjectDeleted.into()),
+    }
+}
+
+/// Checks if the `object` of an activity is `actor`, either as id or embedded object
+fn deletes_actor(body: &[u8], actor: &Url) -> bool {
+    let Ok(activity) = serde_json::from_slice::<Value>(body) else {
+        return false;
+    };
+    let object = &activity["object"];
+    let object_id = object.as_str().or_else(|| object.get("id")?.as_str());
+    object_id == Some(actor.as_str())
+}
+
+/// Returns the `type` of an activity, or an empty string if it is missing
+fn activity_kind(body: &[u8]) -> String {
+    #[derive(Deserialize)]
+    struct Kind {
+        #[serde(rename = "type")]
+        kind: Option<Value>,
+    }
+    let kind = serde_json::from_slice::<Kind>(body)
+        .ok()
+        .and_then(|k| k.kind);
+    kind_name(kind.as_ref()).to_string()
+}
+
+/// Returns the name of a `type` value, or an empty string if it is missing
+pub(crate) fn kind_name(kind: Option<&Value>) -> &str {
+    match kind {
+        Some(Value::String(kind)) => kind,
+        // Multiple types are allowed in JSON-LD, use the first one
+        Some(Value::Array(kinds)) => kinds.first().and_then(Value::as_str).unwrap_or_default(),
+        _ => "",
+    }
+}
+
+/// Rejects requests which are too large or don't contain an activity, before parsing the body
+fn verify_inbox_request(headers: &HeaderMap, body: &[u8], max_size: usize) -> Result<(), Error> {
+    if body.len() > max_size {
+        return Err(Error::ActivityBodyTooLarge);
+    }
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|c| c.to_str().ok())
+        .unwrap_or_default();
+    if !is_activity_content_type(content_type) {
+        return Err(Error::ActivityContentTypeInvalid(content_type.to_string()));
+    }
+    Ok(())
+}
+
+/// Checks for one of the content types which are allowed for activities, see
+/// <https://www.w3.org/TR/activitypub/#server-to-server-interactions>
+pub(crate) fn is_activity_content_type(content_type: &str) -> bool {
+    let mut params = content_type.split(';').map(str::trim);
+    let mime = params.next().unwrap_or_default();
+    if mime.eq_ignore_ascii_case("application/activity+json") {
+        return true;
+    }
+    mime.eq_ignore_ascii_case("application/ld+json")
+        && params.any(|param| match param.split_once('=') {
+            Some((name, value)) => {
+                name.trim().eq_ignore_ascii_case("profile")
+                    && value
+                        .trim()
+                        .trim_matches('"')
+                        .split_whitespace()
+                        .any(|p| p == "https://www.w3.org/ns/activitystreams")
+            }
+            None => false,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::FederationConfig,
+        crypto::default_crypto_backend,
+        http_signatures::{generate_actor_keypair, sign_request},
+        traits::tests::{start_test_server, Person, DB_USER_KEYPAIR},
+        FEDERATION_CONTENT_TYPE,
+    };
+    use async_trait::async_trait;
+    use chrono::NaiveDateTime;
+    use reqwest::Client;
+    use reqwest_middleware::ClientWithMiddleware;
+    use serde_json::json;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    #[derive(Default)]
+    struct DeletedUserState {
+        cached: bool,
+        deleted: AtomicBool,
+    }
+
+    /// Actor whose server responds with `410 Gone`
+    #[derive(Debug)]
+    struct DeletedUser(Url);
+
+    #[async_trait]
+    impl Object for DeletedUser {
+        type DataType = Arc<DeletedUserState>;
+        type Kind = Person;
+        type Error = anyhow::Error;
+
+        fn last_refreshed_at(&self) -> Option<NaiveDateTime> {
+            NaiveDateTime::from_timestamp_opt(0, 0)
+        }
+
+        async fn read_from_id(
+            id: Url,
+            data: &Data<Self::DataType>,
+        ) -> Result<Option<Self>, Self::Error> {
+            Ok(data.cached.then_some(DeletedUser(id)))
+        }
+
+        async fn delete(self, data: &Data<Self::DataType>) -> Result<(), Self::Error> {
+            data.deleted.store(true, Ordering::Relaxed);
+            Ok(())
+        }
+
+        async fn into_json(self, _: &Data<Self::DataType>) -> Result<Person, Self::Error> {
+            Err(anyhow!("Not implemented"))
+        }
+
+        async fn verify(_: &Person, _: &Url, _: &Data<Self::DataType>) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn from_json(_: Person, _: &Data<Self::DataType>) -> Result<Self, Self::Error> {
+            Err(anyhow!("Not implemented"))
+        }
+    }
+
+    impl Actor for DeletedUser {
+        fn id(&self) -> Url {
+            self.0.clone()
+        }
+
+        fn public_key_pem(&self) -> &str {
+            &DB_USER_KEYPAIR.public_key
+        }
+
+        fn private_key_pem(&self) -> Option<String> {
+            None
+        }
+
+        fn inbox(&self) -> Url {
+            self.0.clone()
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct AnyActivity {
+        id: Url,
+        actor: ObjectId<DeletedUser>,
+    }
+
+    #[async_trait]
+    impl ActivityHandler for AnyActivity {
+        type DataType = Arc<DeletedUserState>;
+        type Error = anyhow::Error;
+
+        fn id(&self) -> &Url {
+            &self.id
+        }
+
+        fn actor(&self) -> &Url {
+            self.actor.inner()
+        }
+
+        async fn verify(&self, _: &Data<Self::DataType>) -> Result<(), Self::Error> {
+            Err(anyhow!("Activities of deleted actors are not handled"))
+        }
+
+        async fn receive(self, _: &Data<Self::DataType>) -> Result<(), Self::Error> {
+            Err(anyhow!("Activities of deleted actors are not handled"))
+        }
+    }
+
+    /// Starts a server which responds to all requests with `410 Gone`, and returns its port
+    fn start_gone_server() -> u16 {
+        start_test_server(|_| axum::Router::new().fallback(|| async { StatusCode::GONE }))
+    }
+
+    async fn receive_from_deleted_user(
+        kind: &str,
+        object: Option<&str>,
+        private_key: &str,
+        cached: bool,
+    ) -> (Result<ReceiveOutcome, anyhow::Error>, bool) {
+        let port = start_gone_server();
+        let actor = format!("http://localhost:{port}/u/deleted");
+        let body = json!({
+            "id": format!("http://localhost:{port}/activity/1"),
+            "type": kind,
+            "actor": actor,
+            // The actor itself by default
+            "object": object.unwrap_or(&actor),
+        })
+        .to_string();
+        let request_builder = ClientWithMiddleware::from(Client::default())
+            .post("https://example.com/inbox")
+            .header("content-type", FEDERATION_CONTENT_TYPE);
+        let outgoing_request = sign_request(
+            request_builder,
+            format!("{actor}#main-key"),
+            body.clone(),
+            private_key.to_string(),
+            false,
+            default_crypto_backend(),
+        )
+        .await
+        .unwrap();
+        let mut request = Request::post("/inbox");
+        for (name, value) in outgoing_request.headers() {
+            request = request.header(name, value);
+        }
+        let request = request.body(Bytes::from(body)).unwrap();
+
+        let state = Arc::new(DeletedUserState {
+            cached,
+            ..Default::default()
+        });
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(state.clone())
+            .debug(true)
+            .build()
+            .unwrap();
+        let res =
+            receive_activity::<AnyActivity, DeletedUser, _>(request, &config.to_request_data())
+                .await;
+        (res, state.deleted.load(Ordering::Relaxed))
+    }
+
+    #[actix_rt::test]
+    async fn test_receive_from_deleted_actor() {
+        // Signature is verified with the cached key
+        let (res, deleted) =
+            receive_from_deleted_user("Delete", None, &DB_USER_KEYPAIR.private_key, true).await;
+        assert!(res.unwrap().actor_deleted);
+        assert!(deleted);
+
+        let other_key = generate_actor_keypair().unwrap();
+        let (res, deleted) =
+            receive_from_deleted_user("Delete", None, &other_key.private_key, true).await;
+        let err = res.unwrap_err();
+        let e = err.root_cause().downcast_ref::<Error>().unwrap();
+        assert_eq!(e, &Error::ActivitySignatureInvalid);
+        assert!(!deleted);
+
+        // Only a delete of the actor itself removes it
+        let note = "https://example.com/note/1";
+        for (kind, object) in [("Create", None), ("Delete", Some(note))] {
+            let (res, deleted) =
+                receive_from_deleted_user(kind, object, &DB_USER_KEYPAIR.private_key, true).await;
+            let err = res.unwrap_err();
+            let e = err.root_cause().downcast_ref::<Error>().unwrap();
+            assert_eq!(e, &Error::ObjectDeleted, "{kind}");
+            assert!(!deleted, "{kind}");
+        }
+
+        // Without cached key, only delete is acknowledged
+        let (res, _) =
+            receive_from_deleted_user("Delete", None, &other_key.private_key, false).await;
+        assert!(!res.unwrap().actor_deleted);
+        let (res, _) =
+            receive_from_deleted_user("Create", None, &other_key.private_key, false).await;
+        let err = res.unwrap_err();
+        let e = err.root_cause().downcast_ref::<Error>().unwrap();
+        assert_eq!(e, &Error::ObjectDeleted);
+    }
+
+    #[test]
+    fn test_activity_content_type() {
+        assert!(is_activity_content_type("application/activity+json"));
+        assert!(is_activity_content_type(
+            "application/activity+json; charset=utf-8"
+        ));
+        assert!(is_activity_content_type(
+            r#"application/ld+json; profile="https://www.w3.org/ns/activitystreams""#
+        ));
+        assert!(!is_activity_content_type("application/ld+json"));
+        assert!(!is_activity_content_type("application/json"));
+        assert!(!is_activity_content_type(""));
+    }
+
+    #[test]
+    fn test_verify_inbox_request() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            verify_inbox_request(&headers, b"{}", 10),
+            Err(Error::ActivityContentTypeInvalid(String::new()))
+        );
+        headers.insert(CONTENT_TYPE, "application/activity+json".parse().unwrap());
+        assert_eq!(verify_inbox_request(&headers, b"{}", 10), Ok(()));
+        assert_eq!(
+            verify_inbox_request(&headers, &[b' '; 11], 10),
+            Err(Error::ActivityBodyTooLarge)
+        );
+    }
+}
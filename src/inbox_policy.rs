@@ -0,0 +1,203 @@
+//! Moderation policies which filter incoming activities before they are handled
+//!
+//! Policies are registered with
+//! [FederationConfigBuilder::inbox_policies](crate::config::FederationConfigBuilder::inbox_policies)
+//! and called in order for each incoming activity, after its signature was verified and before
+//! [ActivityHandler::verify](crate::traits::ActivityHandler::verify). Each policy can accept the
+//! activity, reject it with a reason, or rewrite it. Rewritten activities are passed on to the
+//! next policy.
+//!
+//! Rewritten activities are passed to handlers and used by
+//! [local_recipients](crate::shared_inbox::local_recipients), but they are not
+//! [forwarded](crate::activity_queue::forward_activity).
+//!
+//! ```
+//! # use activitypub_federation::inbox_policy::{InboxPolicy, PolicyAction, PolicyActor};
+//! # use async_trait::async_trait;
+//! # use serde_json::Value;
+//! /// Removes attachments from posts which are sent by actors on the given domain
+//! #[derive(Clone)]
+//! struct DropMedia(String);
+//!
+//! #[async_trait]
+//! impl InboxPolicy for DropMedia {
+//!     async fn filter(&self, activity: &Value, actor: &PolicyActor, _kind: &str) -> PolicyAction {
+//!         if actor.id().domain() != Some(&self.0) || activity["object"].get("attachment").is_none() {
+//!             return PolicyAction::Accept;
+//!         }
+//!         let mut activity = activity.clone();
+//!         activity["object"]["attachment"] = Value::Array(vec![]);
+//!         PolicyAction::Rewrite(activity)
+//!     }
+//! }
+//! ```
+
+use crate::{error::Error, inbox::kind_name, traits::Actor};
+use async_trait::async_trait;
+use dyn_clone::{clone_trait_object, DynClone};
+use serde_json::Value;
+use std::any::Any;
+use tracing::debug;
+use url::Url;
+
+/// Decision of an [InboxPolicy] about an incoming activity
+#[derive(Clone, Debug, PartialEq)]
+pub enum PolicyAction {
+    /// Pass the activity unchanged to the next policy
+    Accept,
+    /// Reject the activity. The sender receives `403 Forbidden` with the given reason.
+    Reject(String),
+    /// Replace the activity with the given JSON. Id and actor of the activity must not be changed.
+    Rewrite(Value),
+}
+
+/// Actor who sent an incoming activity, as passed to [InboxPolicy::filter]
+pub struct PolicyActor<'a> {
+    id: Url,
+    actor: &'a (dyn Any + Send + Sync),
+}
+
+impl<'a> PolicyActor<'a> {
+    pub(crate) fn new<A: Actor + Sync>(actor: &'a A) -> Self {
+        PolicyActor {
+            id: actor.id(),
+            actor,
+        }
+    }
+
+    /// Id of the actor
+    pub fn id(&self) -> &Url {
+        &self.id
+    }
+
+    /// The actor which was dereferenced to verify the signature. `A` is the actor type which was
+    /// passed to `receive_activity`, for other types `None` is returned.
+    ///
+    /// ```
+    /// # use activitypub_federation::inbox_policy::PolicyActor;
+    /// # use activitypub_federation::traits::tests::DbUser;
+    /// fn is_local(actor: &PolicyActor) -> bool {
+    ///     actor.downcast_ref::<DbUser>().is_some_and(|user| user.local)
+    /// }
+    /// ```
+    pub fn downcast_ref<A: Actor>(&self) -> Option<&A> {
+        self.actor.downcast_ref()
+    }
+}
+
+/// Filter for incoming activities, see the [module documentation](crate::inbox_policy)
+#[async_trait]
+pub trait InboxPolicy: DynClone + Send + Sync {
+    /// Decide what happens with `activity`, which was sent by `actor` and has the given `type`.
+    /// If there are multiple types, `kind` is the first one. The actor's signature has already
+    /// been verified.
+    async fn filter(&self, activity: &Value, actor: &PolicyActor, kind: &str) -> PolicyAction;
+}
+
+clone_trait_object!(InboxPolicy);
+
+/// Runs all policies in order. Returns the rewritten activity, or `None` if it was unchanged.
+pub(crate) async fn apply_inbox_policies(
+    policies: &[Box<dyn InboxPolicy>],
+    body: &[u8],
+    actor: &PolicyActor<'_>,
+) -> Result<Option<Value>, Error> {
+    if policies.is_empty() {
+        return Ok(None);
+    }
+    let mut activity: Value = serde_json::from_slice(body).map_err(Error::ActivityParseError)?;
+    let mut rewritten = false;
+    for policy in policies {
+        let kind = kind_name(activity.get("type"));
+        match policy.filter(&activity, actor, kind).await {
+            PolicyAction::Accept => {}
+            PolicyAction::Reject(reason) => {
+                debug!(
+                    "Inbox policy rejected activity from {}: {}",
+                    actor.id(),
+                    reason
+                );
+                return Err(Error::ActivityRejected(reason));
+            }
+            PolicyAction::Rewrite(new_activity) => {
+                activity = new_activity;
+                rewritten = true;
+            }
+        }
+    }
+    Ok(rewritten.then_some(activity))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::tests::{DbUser, DB_USER};
+    use serde_json::json;
+
+    #[derive(Clone)]
+    struct RejectKind(&'static str);
+
+    #[async_trait]
+    impl InboxPolicy for RejectKind {
+        async fn filter(&self, _activity: &Value, actor: &PolicyActor, kind: &str) -> PolicyAction {
+            assert_eq!(
+                actor.downcast_ref::<DbUser>().map(|a| a.id()),
+                Some(DB_USER.id())
+            );
+            if kind == self.0 {
+                PolicyAction::Reject(format!("{} is not allowed", kind))
+            } else {
+                PolicyAction::Accept
+            }
+        }
+    }
+
+    #[derive(Clone)]
+    struct RenameType;
+
+    #[async_trait]
+    impl InboxPolicy for RenameType {
+        async fn filter(
+            &self,
+            activity: &Value,
+            _actor: &PolicyActor,
+            _kind: &str,
+        ) -> PolicyAction {
+            let mut activity = activity.clone();
+            activity["type"] = json!("Like");
+            PolicyAction::Rewrite(activity)
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_apply_inbox_policies() {
+        let actor = PolicyActor::new(&*DB_USER);
+        let body = json!({"type": "Follow"}).to_string();
+
+        assert_eq!(
+            apply_inbox_policies(&[], body.as_bytes(), &actor).await,
+            Ok(None)
+        );
+        let accept: Vec<Box<dyn InboxPolicy>> = vec![Box::new(RejectKind("Like"))];
+        assert_eq!(
+            apply_inbox_policies(&accept, body.as_bytes(), &actor).await,
+            Ok(None)
+        );
+
+        // Later policies see the rewritten activity
+        let rewrite: Vec<Box<dyn InboxPolicy>> =
+            vec![Box::new(RenameType), Box::new(RejectKind("Like"))];
+        let res = apply_inbox_policies(&rewrite, body.as_bytes(), &actor).await;
+        assert!(matches!(res, Err(Error::ActivityRejected(m)) if m == "Like is not allowed"));
+        // The first of multiple types is used
+        let body = json!({"type": ["Like", "Reaction"]}).to_string();
+        let res = apply_inbox_policies(&accept, body.as_bytes(), &actor).await;
+        assert!(matches!(res, Err(Error::ActivityRejected(m)) if m == "Like is not allowed"));
+
+        let rewrite: Vec<Box<dyn InboxPolicy>> = vec![Box::new(RenameType)];
+        assert_eq!(
+            apply_inbox_policies(&rewrite, body.as_bytes(), &actor).await,
+            Ok(Some(json!({"type": "Like"})))
+        );
+    }
+}
@@ -0,0 +1,375 @@
+//! Queue for verifying and receiving incoming activities in the background
+//!
+//! This is enabled with [FederationConfigBuilder::incoming_queue](crate::config::FederationConfigBuilder::incoming_queue).
+//! Inbox handlers then only check the HTTP signature of an incoming activity and respond with
+//! `202 Accepted`. [ActivityHandler::verify] and [ActivityHandler::receive] are called afterwards
+//! by one of the queue workers, so that slow handlers don't cause timeouts on the sending server.
+//! The workers are spawned on the async runtime of the request which queues the first activity,
+//! so that handlers can rely on runtime-local state, such as `actix_rt::spawn`. With actix-web
+//! this means that all workers run on the same server thread, so handlers should not block it.
+//!
+//! At most [FederationConfigBuilder::incoming_queue_size](crate::config::FederationConfigBuilder::incoming_queue_size)
+//! activities can wait in the queue. Further activities are rejected with
+//! `503 Service Unavailable`, so that the sending server delivers them again later.
+//!
+//! If [ActivityHandler::receive] fails, the activity is retried after 10 seconds, 100 seconds and
+//! so on, up to [FederationConfigBuilder::incoming_retry_count](crate::config::FederationConfigBuilder::incoming_retry_count)
+//! times. After that it is passed to the [IncomingFailureHandler]. Activities which can't be
+//! parsed or fail [ActivityHandler::verify] are passed to it immediately, as retrying wouldn't
+//! change the result. Verification is only retried if it failed with an [Error] which results in
+//! a `5xx` [status code](Error::status_code), such as a failed request to fetch the actor. The
+//! error may also be wrapped in an [anyhow::Error].
+//!
+//! The queue is only kept in memory. Activities which were already acknowledged with
+//! `202 Accepted` but not processed yet are lost when the server is stopped or restarted, and
+//! the sending server won't deliver them again.
+
+use crate::{activity_queue::RawActivity, config::Data, error::Error, traits::ActivityHandler};
+use async_trait::async_trait;
+use dyn_clone::{clone_trait_object, DynClone};
+use once_cell::sync::OnceCell;
+use serde::de::DeserializeOwned;
+use std::{any::Any, fmt::Display, future::Future, pin::Pin, sync::Arc, time::Duration};
+use tokio::sync::{
+    mpsc::{channel, error::TrySendError, Receiver, Sender, WeakSender},
+    Mutex,
+};
+use tracing::{debug, info, warn};
+
+/// Delay before the first retry, multiplied by 10 for each following retry
+const BACKOFF_BASE: Duration = Duration::from_secs(10);
+
+/// Handler for incoming activities which could not be processed, even after retrying.
+///
+/// The default implementation only logs the error. It can be replaced to store failed
+/// activities for later inspection:
+///
+/// ```
+/// # use async_trait::async_trait;
+/// # use activitypub_federation::activity_queue::RawActivity;
+/// # use activitypub_federation::incoming_queue::IncomingFailureHandler;
+/// #[derive(Clone)]
+/// struct FailureLog;
+///
+/// #[async_trait]
+/// impl IncomingFailureHandler for FailureLog {
+///     async fn failed(&self, activity: &RawActivity, error: String) {
+///         eprintln!("Failed to receive {}: {}", activity.id(), error);
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait IncomingFailureHandler: DynClone + Send {
+    /// Called once the last attempt to process `activity` failed with `error`
+    async fn failed(&self, activity: &RawActivity, error: String);
+}
+
+/// Default failure handler which logs failed activities
+#[derive(Clone)]
+struct DefaultIncomingFailureHandler();
+
+#[async_trait]
+impl IncomingFailureHandler for DefaultIncomingFailureHandler {
+    async fn failed(&self, activity: &RawActivity, error: String) {
+        warn!("Failed to receive activity {}: {}", activity.id(), error);
+    }
+}
+
+clone_trait_object!(IncomingFailureHandler);
+
+pub(crate) fn default_failure_handler() -> Box<dyn IncomingFailureHandler + Sync> {
+    Box::new(DefaultIncomingFailureHandler())
+}
+
+/// Error while processing an incoming activity
+struct ProcessError {
+    message: String,
+    /// Whether the error may be temporary, so that processing should be retried
+    transient: bool,
+}
+
+impl ProcessError {
+    fn permanent(error: impl Display) -> Self {
+        ProcessError {
+            message: error.to_string(),
+            transient: false,
+        }
+    }
+
+    fn transient(error: impl Display) -> Self {
+        ProcessError {
+            message: error.to_string(),
+            transient: true,
+        }
+    }
+
+    /// Errors from [ActivityHandler::verify] are permanent, unless they are caused by the server
+    /// or a remote server, for example when fetching the actor fails
+    fn verify<E: Display + 'static>(error: E) -> Self {
+        let any: &dyn Any = &error;
+        let source = any.downcast_ref::<Error>().or_else(|| {
+            any.downcast_ref::<anyhow::Error>()
+                .and_then(|e| e.downcast_ref::<Error>())
+        });
+        match source {
+            Some(e) if e.status_code().is_server_error() => ProcessError::transient(error),
+            _ => ProcessError::permanent(error),
+        }
+    }
+}
+
+type ProcessFn = Arc<
+    dyn Fn(RawActivity) -> Pin<Box<dyn Future<Output = Result<(), ProcessError>> + Send>>
+        + Send
+        + Sync,
+>;
+
+struct IncomingTask {
+    activity: RawActivity,
+    attempt: u32,
+    process: ProcessFn,
+}
+
+/// Sender for the incoming queue. Workers are started when the first activity is queued.
+#[derive(Clone, Default)]
+pub(crate) struct IncomingQueue(Arc<OnceCell<Sender<IncomingTask>>>);
+
+/// Adds an activity with verified signature to the incoming queue, to call
+/// [ActivityHandler::verify] and [ActivityHandler::receive] in the background. The activity is
+/// parsed in the form which it has after [inbox policies](crate::inbox_policy) were applied.
+///
+/// Returns [Error::IncomingQueueFull] if the queue has no space left.
+pub(crate) fn queue_incoming_activity<Activity, Datatype>(
+    activity: RawActivity,
+    data: &Data<Datatype>,
+) -> Result<(), Error>
+where
+    Activity: ActivityHandler<DataType = Datatype> + DeserializeOwned + Send + 'static,
+    <Activity as ActivityHandler>::Error: Display + 'static,
+    Datatype: Clone + Send + Sync + 'static,
+{
+    let config = data.config.clone();
+    let process: ProcessFn = Arc::new(move |raw| {
+        // Each attempt gets its own request counter
+        let data = config.to_request_data().with_received_activity(raw.clone());
+        Box::pin(async move {
+            let activity: Activity =
+                serde_json::from_slice(raw.activity_json()).map_err(ProcessError::permanent)?;
+            activity.verify(&data).await.map_err(ProcessError::verify)?;
+            activity
+                .receive(&data)
+                .await
+                .map_err(ProcessError::transient)
+        })
+    });
+
+    let config = &data.config;
+    let sender = config.incoming_activity_queue.0.get_or_init(|| {
+        start_workers(
+            config.incoming_worker_count,
+            config.incoming_retry_count,
+            config.incoming_queue_size,
+            BACKOFF_BASE,
+            config.incoming_failure_handler.clone(),
+        )
+    });
+    let task = IncomingTask {
+        activity,
+        attempt: 0,
+        process,
+    };
+    match sender.try_send(task) {
+        Ok(()) => Ok(()),
+        Err(TrySendError::Full(task)) => {
+            warn!(
+                "Incoming activity queue is full, rejecting {}",
+                task.activity.id()
+            );
+            Err(Error::IncomingQueueFull)
+        }
+        Err(TrySendError::Closed(_)) => Err(Error::other(anyhow::anyhow!(
+            "Incoming activity queue is closed"
+        ))),
+    }
+}
+
+/// Spawns `worker_count` workers on the current runtime. They stop once the returned sender and
+/// all its clones are dropped.
+fn start_workers(
+    worker_count: u64,
+    retry_count: u32,
+    queue_size: usize,
+    backoff: Duration,
+    failure_handler: Box<dyn IncomingFailureHandler + Sync>,
+) -> Sender<IncomingTask> {
+    let (sender, receiver) = channel(queue_size.max(1));
+    let receiver = Arc::new(Mutex::new(receiver));
+    let weak_sender = sender.downgrade();
+    for _ in 0..worker_count.max(1) {
+        tokio::spawn(run_worker(
+            receiver.clone(),
+            weak_sender.clone(),
+            retry_count,
+            backoff,
+            failure_handler.clone(),
+        ));
+    }
+    sender
+}
+
+async fn run_worker(
+    receiver: Arc<Mutex<Receiver<IncomingTask>>>,
+    sender: WeakSender<IncomingTask>,
+    retry_count: u32,
+    backoff: Duration,
+    failure_handler: Box<dyn IncomingFailureHandler + Sync>,
+) {
+    loop {
+        let Some(mut task) = receiver.lock().await.recv().await else {
+            return;
+        };
+        debug!("Processing incoming activity {}", task.activity.id());
+        let Err(e) = (task.process)(task.activity.clone()).await else {
+            continue;
+        };
+        if e.transient && task.attempt < retry_count {
+            let delay = backoff * 10u32.pow(task.attempt);
+            info!(
+                "Retrying incoming activity {} in {:?} after error: {}",
+                task.activity.id(),
+                delay,
+                e.message
+            );
+            task.attempt += 1;
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                // The queue was closed in the meantime
+                let Some(sender) = sender.upgrade() else {
+                    return;
+                };
+                sender.send(task).await.ok();
+            });
+        } else {
+            failure_handler.failed(&task.activity, e.message).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex as SyncMutex,
+    };
+    use url::Url;
+
+    #[derive(Clone)]
+    struct RecordFailures(Arc<SyncMutex<Vec<(Url, String)>>>);
+
+    #[async_trait]
+    impl IncomingFailureHandler for RecordFailures {
+        async fn failed(&self, activity: &RawActivity, error: String) {
+            self.0.lock().unwrap().push((activity.id().clone(), error));
+        }
+    }
+
+    fn task(id: &Url, process: ProcessFn) -> IncomingTask {
+        IncomingTask {
+            activity: RawActivity::new(id.clone(), id.clone(), "{}".into()),
+            attempt: 0,
+            process,
+        }
+    }
+
+    async fn wait_until(condition: impl Fn() -> bool) {
+        for _ in 0..100 {
+            if condition() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_report_failure() {
+        let failures = RecordFailures(Default::default());
+        let sender = start_workers(2, 3, 10, Duration::ZERO, Box::new(failures.clone()));
+        let attempts = Arc::new(AtomicU32::new(0));
+        let attempts_ = attempts.clone();
+        let id = Url::parse("https://example.com/activity/1").unwrap();
+        let process: ProcessFn = Arc::new(move |_| {
+            attempts_.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async { Err(ProcessError::transient("database unavailable")) })
+        });
+        assert!(sender.try_send(task(&id, process)).is_ok());
+
+        wait_until(|| !failures.0.lock().unwrap().is_empty()).await;
+        assert_eq!(
+            *failures.0.lock().unwrap(),
+            vec![(id, "database unavailable".to_string())]
+        );
+        assert_eq!(attempts.load(Ordering::SeqCst), 4);
+    }
+
+    #[actix_rt::test]
+    async fn test_permanent_failure() {
+        let failures = RecordFailures(Default::default());
+        let sender = start_workers(2, 3, 10, Duration::ZERO, Box::new(failures.clone()));
+        let attempts = Arc::new(AtomicU32::new(0));
+        let attempts_ = attempts.clone();
+        let id = Url::parse("https://example.com/activity/1").unwrap();
+        let process: ProcessFn = Arc::new(move |_| {
+            attempts_.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async { Err(ProcessError::permanent("invalid activity")) })
+        });
+        assert!(sender.try_send(task(&id, process)).is_ok());
+
+        wait_until(|| !failures.0.lock().unwrap().is_empty()).await;
+        assert_eq!(
+            *failures.0.lock().unwrap(),
+            vec![(id, "invalid activity".to_string())]
+        );
+        // Not retried
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_verify_error() {
+        let id = Url::parse("https://example.com/note/1").unwrap();
+        assert!(ProcessError::verify(Error::FetchedIdMismatch(id)).transient);
+        assert!(ProcessError::verify(anyhow::Error::from(Error::FetchedIdMissing)).transient);
+        assert!(
+            !ProcessError::verify(Error::UrlVerificationError("Domains do not match")).transient
+        );
+        assert!(!ProcessError::verify(anyhow::anyhow!("invalid activity")).transient);
+        assert!(!ProcessError::verify("invalid activity").transient);
+    }
+
+    #[actix_rt::test]
+    async fn test_retry_then_success() {
+        let failures = RecordFailures(Default::default());
+        let sender = start_workers(2, 3, 10, Duration::ZERO, Box::new(failures.clone()));
+        let attempts = Arc::new(AtomicU32::new(0));
+        let attempts_ = attempts.clone();
+        let id = Url::parse("https://example.com/activity/1").unwrap();
+        let process: ProcessFn = Arc::new(move |_| {
+            let attempt = attempts_.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                if attempt < 2 {
+                    Err(ProcessError::transient("database unavailable"))
+                } else {
+                    Ok(())
+                }
+            })
+        });
+        assert!(sender.try_send(task(&id, process)).is_ok());
+
+        wait_until(|| attempts.load(Ordering::SeqCst) == 3).await;
+        // Give the workers time to report a failure, in case processing was retried again
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert!(failures.0.lock().unwrap().is_empty());
+    }
+}
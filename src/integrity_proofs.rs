@@ -0,0 +1,493 @@
+//! Creating and verifying object integrity proofs
+//!
+//! Implements [FEP-8b32](https://codeberg.org/fediverse/fep/src/branch/main/fep/8b32/fep-8b32.md),
+//! using `DataIntegrityProof` with the `eddsa-jcs-2022` cryptosuite. The proof is embedded in the
+//! `proof` field of an activity or object, and lets anyone verify its authorship, even if it was
+//! relayed, forwarded or fetched from a third party:
+//!
+//! ```json
+//! "proof": {
+//!   "@context": ["https://www.w3.org/ns/activitystreams", "https://w3id.org/security/data-integrity/v1"],
+//!   "type": "DataIntegrityProof",
+//!   "cryptosuite": "eddsa-jcs-2022",
+//!   "verificationMethod": "https://example.com/users/alice#ed25519-key",
+//!   "proofPurpose": "assertionMethod",
+//!   "created": "2023-03-01T12:00:00Z",
+//!   "proofValue": "z3sXaxjKs4M3BRicwWA9peyNPJvJqxtGsDmpt1jjoHCjgeUf71TRFz56osPSfDErszyLp5Ks1EhYSgpDaNM977Rg2"
+//! }
+//! ```
+//!
+//! Proofs use Ed25519 keys, which are separate from the RSA keys of HTTP signatures. Use
+//! [generate_ed25519_keypair] to create them, and publish the public key as
+//! [Multikey] in the `assertionMethod` field of the actor
+//! (see [Actor::assertion_methods]). Outgoing activities can be signed by wrapping them in
+//! [Signed].
+//!
+//! Incoming activities with a valid proof by their actor are accepted by `receive_activity`, even
+//! if the HTTP signature was made by a different actor. Objects which are fetched with
+//! [ObjectId::dereference](crate::fetch::object_id::ObjectId::dereference) and contain a proof are
+//! rejected if the proof was made by an actor from a different domain. Proofs which can't be
+//! verified are ignored, and the object is checked with
+//! [Object::verify](crate::traits::Object::verify) as usual.
+//!
+//! Objects whose id is on a different domain than the url they were fetched from, for example
+//! from a relay, are only accepted if they have a valid proof by an actor from the domain of
+//! their id. They are then checked with [Object::verify](crate::traits::Object::verify) against
+//! their id instead of the url.
+//!
+//! This module requires the `integrity-proofs` feature, which is enabled by default.
+
+use crate::{
+    config::Data,
+    error::{Error, Error::IntegrityProofInvalid},
+    fetch::fetch_object_http,
+    protocol::{
+        helpers::deserialize_one_or_many,
+        multikey::Multikey,
+        verification::verify_domains_match,
+    },
+    traits::{ActivityHandler, Actor},
+};
+use anyhow::anyhow;
+use chrono::{SecondsFormat, Utc};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use tracing::debug;
+use url::Url;
+
+/// Proof type of FEP-8b32
+pub const DATA_INTEGRITY_PROOF: &str = "DataIntegrityProof";
+
+/// The only supported cryptosuite
+pub const EDDSA_JCS_2022: &str = "eddsa-jcs-2022";
+
+/// Multicodec prefix of Ed25519 private keys
+const ED25519_PRIV_PREFIX: [u8; 2] = [0x80, 0x26];
+
+/// Multicodec prefix of Ed25519 public keys
+const ED25519_PUB_PREFIX: [u8; 2] = [0xed, 0x01];
+
+/// An Ed25519 key pair used for object integrity proofs
+#[derive(Debug, Clone)]
+pub struct Ed25519Keypair {
+    /// Private key, encoded as multibase base58-btc
+    pub private_key: String,
+    /// Public key, encoded as multibase base58-btc. This is the value of
+    /// [Multikey::public_key_multibase].
+    pub public_key: String,
+}
+
+/// Generate a random Ed25519 keypair for object integrity proofs.
+pub fn generate_ed25519_keypair() -> Ed25519Keypair {
+    let signing_key = SigningKey::generate(&mut rand::rngs::OsRng);
+    Ed25519Keypair {
+        private_key: encode_multibase(
+            &[&ED25519_PRIV_PREFIX, signing_key.as_bytes().as_slice()].concat(),
+        ),
+        public_key: encode_public_key(&signing_key.verifying_key()),
+    }
+}
+
+/// Data Integrity proof in the `proof` field of an activity or object
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DataIntegrityProof {
+    /// Always `DataIntegrityProof`
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Algorithm which was used to create the proof
+    pub cryptosuite: String,
+    /// Id of the key which created the proof
+    pub verification_method: Url,
+    /// Always `assertionMethod`
+    pub proof_purpose: String,
+    /// Time of proof creation, in ISO 8601 format
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
+    /// The signature, encoded as multibase base58-btc
+    pub proof_value: String,
+}
+
+/// Adds an `eddsa-jcs-2022` proof by `key_id` to `document`, created with `private_key`. Existing
+/// proofs are replaced.
+pub fn create_proof(document: &Value, key_id: &Url, private_key: &str) -> Result<Value, Error> {
+    let mut document = document.as_object().cloned().ok_or(IntegrityProofInvalid)?;
+    document.remove("proof");
+    let signing_key = signing_key(private_key)?;
+
+    let mut proof = Map::new();
+    if let Some(context) = document.get("@context") {
+        proof.insert("@context".to_string(), context.clone());
+    }
+    proof.insert("type".to_string(), DATA_INTEGRITY_PROOF.into());
+    proof.insert("cryptosuite".to_string(), EDDSA_JCS_2022.into());
+    proof.insert("verificationMethod".to_string(), key_id.as_str().into());
+    proof.insert("proofPurpose".to_string(), "assertionMethod".into());
+    proof.insert(
+        "created".to_string(),
+        Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true).into(),
+    );
+
+    let signature = signing_key.sign(&hash_data(&document, &proof)?);
+    proof.insert(
+        "proofValue".to_string(),
+        encode_multibase(&signature.to_bytes()).into(),
+    );
+    document.insert("proof".to_string(), Value::Object(proof));
+    Ok(Value::Object(document))
+}
+
+/// Wrapper which adds an integrity proof to an activity or object when it is serialized
+///
+/// ```
+/// # use activitypub_federation::integrity_proofs::{generate_ed25519_keypair, Signed};
+/// # use activitypub_federation::protocol::context::WithContext;
+/// # use url::Url;
+/// #[derive(serde::Serialize)]
+/// struct Note {
+///     content: String
+/// }
+/// let keypair = generate_ed25519_keypair();
+/// let key_id = Url::parse("https://example.com/u/alice#ed25519-key")?;
+/// let note = WithContext::new_default(Note { content: "Hello world".to_string() });
+/// let note = Signed::new(note, &key_id, &keypair.private_key)?;
+/// let serialized = serde_json::to_value(&note)?;
+/// assert_eq!(serialized["proof"]["cryptosuite"], "eddsa-jcs-2022");
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct Signed<T> {
+    inner: T,
+    signed: Value,
+}
+
+impl<T: Serialize> Signed<T> {
+    /// Creates a proof for the serialized `inner` value, with the given key
+    pub fn new(inner: T, key_id: &Url, private_key: &str) -> Result<Self, Error> {
+        let document = serde_json::to_value(&inner).map_err(Error::other)?;
+        let signed = create_proof(&document, key_id, private_key)?;
+        Ok(Signed { inner, signed })
+    }
+}
+
+impl<T> Signed<T> {
+    /// Returns the wrapped value, without the proof
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T> Serialize for Signed<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.signed.serialize(serializer)
+    }
+}
+
+#[async_trait::async_trait]
+impl<T> ActivityHandler for Signed<T>
+where
+    T: ActivityHandler + Send + Sync,
+{
+    type DataType = <T as ActivityHandler>::DataType;
+    type Error = <T as ActivityHandler>::Error;
+
+    fn id(&self) -> &Url {
+        self.inner.id()
+    }
+
+    fn actor(&self) -> &Url {
+        self.inner.actor()
+    }
+
+    async fn verify(&self, data: &Data<Self::DataType>) -> Result<(), Self::Error> {
+        self.inner.verify(data).await
+    }
+
+    async fn receive(self, data: &Data<Self::DataType>) -> Result<(), Self::Error> {
+        self.inner.receive(data).await
+    }
+}
+
+/// Verifies that `document` contains a valid proof by one of the keys of `actor`.
+///
+/// The verification method is looked up in [Actor::assertion_methods]. If it is not listed there,
+/// it is fetched, and its `controller` must be the actor.
+pub async fn verify_proof<A, T>(document: &Value, actor: &A, data: &Data<T>) -> Result<(), Error>
+where
+    A: Actor,
+    T: Clone,
+{
+    let keys = actor.assertion_methods();
+    for (proof, raw_proof) in proofs(document) {
+        let key = match keys
+            .iter()
+            .find(|k| k.id == proof.verification_method.as_str())
+        {
+            Some(key) => key.clone(),
+            None => match fetch_multikey(&proof.verification_method, data).await {
+                Ok(key) if key.controller == actor.id() => key,
+                _ => continue,
+            },
+        };
+        if verify_with_key(document, &raw_proof, &proof, &key).is_ok() {
+            return Ok(());
+        }
+    }
+    Err(IntegrityProofInvalid)
+}
+
+/// Verifies the proof of a fetched object, whose author is not known in advance. Returns the
+/// controller of the key which created the first valid proof, or `None` if no supported proof
+/// could be verified.
+pub(crate) async fn verify_object_proof<T: Clone>(document: &Value, data: &Data<T>) -> Option<Url> {
+    for (proof, raw_proof) in proofs(document) {
+        let Ok(key) = fetch_multikey(&proof.verification_method, data).await else {
+            continue;
+        };
+        if verify_with_key(document, &raw_proof, &proof, &key).is_ok() {
+            return Some(key.controller);
+        }
+    }
+    None
+}
+
+/// All supported proofs of the document, in parsed and raw form.
+fn proofs(document: &Value) -> Vec<(DataIntegrityProof, Map<String, Value>)> {
+    let proofs = match document.get("proof") {
+        Some(Value::Array(proofs)) => proofs.clone(),
+        Some(proof) => vec![proof.clone()],
+        None => vec![],
+    };
+    proofs
+        .into_iter()
+        .filter_map(|raw| {
+            let proof: DataIntegrityProof = serde_json::from_value(raw.clone()).ok()?;
+            let supported = proof.kind == DATA_INTEGRITY_PROOF
+                && proof.cryptosuite == EDDSA_JCS_2022
+                && proof.proof_purpose == "assertionMethod";
+            match raw {
+                Value::Object(raw) if supported => Some((proof, raw)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn verify_with_key(
+    document: &Value,
+    raw_proof: &Map<String, Value>,
+    proof: &DataIntegrityProof,
+    key: &Multikey,
+) -> Result<(), Error> {
+    let mut document = document.as_object().cloned().ok_or(IntegrityProofInvalid)?;
+    document.remove("proof");
+    let mut options = raw_proof.clone();
+    options.remove("proofValue");
+    if let Some(proof_context) = options.get("@context") {
+        // The document context must start with the proof context
+        let document_context = context_values(document.get("@context"));
+        let proof_context = context_values(Some(proof_context));
+        if !document_context.starts_with(&proof_context) {
+            return Err(IntegrityProofInvalid);
+        }
+        document.insert("@context".to_string(), proof_context.into());
+    }
+
+    let signature = decode_multibase(&proof.proof_value)?;
+    let signature = Signature::from_slice(&signature).map_err(Error::other)?;
+    verifying_key(key)?
+        .verify_strict(&hash_data(&document, &options)?, &signature)
+        .map_err(|e| {
+            debug!("Invalid integrity proof by {}: {}", key.id, e);
+            IntegrityProofInvalid
+        })
+}
+
+fn context_values(context: Option<&Value>) -> Vec<Value> {
+    match context {
+        Some(Value::Array(values)) => values.clone(),
+        Some(value) => vec![value.clone()],
+        None => vec![],
+    }
+}
+
+/// The signed data consists of the hashes of the JCS canonicalized proof options and document,
+/// concatenated.
+fn hash_data(
+    document: &Map<String, Value>,
+    options: &Map<String, Value>,
+) -> Result<Vec<u8>, Error> {
+    let options = serde_jcs::to_vec(options).map_err(Error::other)?;
+    let document = serde_jcs::to_vec(document).map_err(Error::other)?;
+    Ok([Sha256::digest(options), Sha256::digest(document)].concat())
+}
+
+pub(crate) fn signing_key(private_key: &str) -> Result<SigningKey, Error> {
+    let bytes = decode_multibase(private_key)?;
+    let key = bytes
+        .strip_prefix(&ED25519_PRIV_PREFIX)
+        .and_then(|k| <[u8; 32]>::try_from(k).ok())
+        .ok_or_else(|| Error::other(anyhow!("Invalid Ed25519 private key")))?;
+    Ok(SigningKey::from_bytes(&key))
+}
+
+/// Verifies an Ed25519 `signature` of `message`, such as an HTTP signature
+pub(crate) fn verify_multikey_signature(
+    key: &Multikey,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), Error> {
+    let signature = Signature::from_slice(signature).map_err(Error::other)?;
+    verifying_key(key)?
+        .verify_strict(message, &signature)
+        .map_err(Error::other)
+}
+
+/// Fetches the verification method of a proof, which can be published as separate document or
+/// as part of the actor.
+async fn fetch_multikey<T: Clone>(key_id: &Url, data: &Data<T>) -> Result<Multikey, Error> {
+    let document: MultikeyDocument = fetch_object_http(key_id, data).await?;
+    let key = document
+        .into_keys()
+        .into_iter()
+        .find(|k| k.id == key_id.as_str())
+        .ok_or(IntegrityProofInvalid)?;
+    verify_domains_match(key_id, &key.controller)?;
+    Ok(key)
+}
+
+fn verifying_key(key: &Multikey) -> Result<VerifyingKey, Error> {
+    let bytes = decode_multibase(&key.public_key_multibase)?;
+    let key = bytes
+        .strip_prefix(&ED25519_PUB_PREFIX)
+        .and_then(|k| <[u8; 32]>::try_from(k).ok())
+        .ok_or_else(|| Error::other(anyhow!("Multikey is not an Ed25519 public key")))?;
+    VerifyingKey::from_bytes(&key).map_err(Error::other)
+}
+
+fn encode_public_key(key: &VerifyingKey) -> String {
+    encode_multibase(&[&ED25519_PUB_PREFIX, key.as_bytes().as_slice()].concat())
+}
+
+/// Encodes bytes as multibase base58-btc, which is the only base used by FEP-8b32
+fn encode_multibase(bytes: &[u8]) -> String {
+    format!("z{}", bs58::encode(bytes).into_string())
+}
+
+fn decode_multibase(value: &str) -> Result<Vec<u8>, Error> {
+    let value = value
+        .strip_prefix('z')
+        .ok_or_else(|| Error::other(anyhow!("Unsupported multibase encoding")))?;
+    bs58::decode(value).into_vec().map_err(Error::other)
+}
+
+/// Document which is returned when fetching a verification method. Like for
+/// [PublicKey](crate::protocol::public_key::PublicKey), this can be either the key itself or the
+/// actor which contains it.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MultikeyDocument {
+    Key(Multikey),
+    Actor {
+        #[serde(
+            rename = "assertionMethod",
+            deserialize_with = "deserialize_one_or_many"
+        )]
+        assertion_method: Vec<Multikey>,
+    },
+}
+
+impl MultikeyDocument {
+    fn into_keys(self) -> Vec<Multikey> {
+        match self {
+            MultikeyDocument::Key(k) => vec![k],
+            MultikeyDocument::Actor { assertion_method } => assertion_method,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::FederationConfig,
+        traits::tests::{DbConnection, DB_USER, DB_USER_ED25519_KEYPAIR},
+    };
+    use serde_json::json;
+
+    fn note() -> Value {
+        json!({
+            "@context": [
+                "https://www.w3.org/ns/activitystreams",
+                "https://w3id.org/security/data-integrity/v1"
+            ],
+            "id": "https://localhost/objects/1",
+            "type": "Note",
+            "attributedTo": "https://localhost/123",
+            "content": "Hello world"
+        })
+    }
+
+    #[actix_rt::test]
+    async fn test_proof_roundtrip() {
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(DbConnection)
+            .debug(true)
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+        let key_id = Url::parse(&DB_USER.assertion_methods()[0].id).unwrap();
+        let signed = create_proof(&note(), &key_id, &DB_USER_ED25519_KEYPAIR.private_key).unwrap();
+        assert_eq!(signed["proof"]["@context"], note()["@context"]);
+        verify_proof(&signed, &*DB_USER, &data).await.unwrap();
+
+        // Formatting doesn't matter
+        let reformatted: Value =
+            serde_json::from_str(&serde_json::to_string_pretty(&signed).unwrap()).unwrap();
+        verify_proof(&reformatted, &*DB_USER, &data).await.unwrap();
+
+        let mut tampered = signed.clone();
+        tampered["content"] = json!("changed");
+        assert_eq!(
+            verify_proof(&tampered, &*DB_USER, &data).await,
+            Err(IntegrityProofInvalid)
+        );
+        let mut tampered = signed.clone();
+        tampered["@context"] = json!("https://www.w3.org/ns/activitystreams");
+        assert_eq!(
+            verify_proof(&tampered, &*DB_USER, &data).await,
+            Err(IntegrityProofInvalid)
+        );
+
+        // Proof by a different key
+        let other = generate_ed25519_keypair();
+        let signed = create_proof(&note(), &key_id, &other.private_key).unwrap();
+        assert_eq!(
+            verify_proof(&signed, &*DB_USER, &data).await,
+            Err(IntegrityProofInvalid)
+        );
+        assert_eq!(
+            verify_proof(&note(), &*DB_USER, &data).await,
+            Err(IntegrityProofInvalid)
+        );
+    }
+
+    #[test]
+    fn test_multikey_encoding() {
+        // Key pair from https://www.w3.org/TR/vc-di-eddsa/#representation-eddsa-jcs-2022
+        let private_key = "z3u2en7t5LR2WtQH5PfFqMqwVHBeXouLzo6haApm8XHqvjxq";
+        let public_key = "z6MkrJVnaZkeFzdQyMZu1cgjg7k1pZZ6pvBQ7XJPt4swbTQ2";
+        let verifying_key = signing_key(private_key).unwrap().verifying_key();
+        assert_eq!(encode_public_key(&verifying_key), public_key);
+
+        let key = Multikey::new(
+            Url::parse("https://localhost/123").unwrap(),
+            public_key.to_string(),
+        );
+        assert_eq!(key.id, "https://localhost/123#ed25519-key");
+        assert_eq!(super::verifying_key(&key).unwrap(), verifying_key);
+    }
+}
@@ -0,0 +1,756 @@
+//! JSON-LD context processing and expansion, following the JSON-LD 1.0 processing algorithms
+//!
+//! <https://www.w3.org/TR/2014/REC-json-ld-api-20140116/#expansion-algorithms>
+
+use crate::{error::Error, ld_signatures::invalid_json_ld};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+/// Remote contexts which were loaded before expansion, keyed by url
+pub(crate) type LoadedContexts = HashMap<String, Value>;
+
+const KEYWORDS: [&str; 13] = [
+    "@context",
+    "@id",
+    "@value",
+    "@language",
+    "@type",
+    "@container",
+    "@list",
+    "@set",
+    "@reverse",
+    "@index",
+    "@base",
+    "@vocab",
+    "@graph",
+];
+
+pub(crate) fn is_keyword(value: &str) -> bool {
+    KEYWORDS.contains(&value)
+}
+
+#[derive(Clone, Default)]
+struct ActiveContext {
+    vocab: Option<String>,
+    language: Option<String>,
+    /// A value of `None` means that the term is explicitly mapped to null, and should be ignored
+    terms: HashMap<String, Option<TermDefinition>>,
+}
+
+#[derive(Clone)]
+struct TermDefinition {
+    iri: String,
+    reverse: bool,
+    type_mapping: Option<String>,
+    language_mapping: Option<Option<String>>,
+    container: Option<String>,
+}
+
+impl ActiveContext {
+    fn term(&self, term: &str) -> Option<&TermDefinition> {
+        self.terms.get(term).and_then(Option::as_ref)
+    }
+
+    fn container(&self, term: &str) -> Option<&str> {
+        self.term(term).and_then(|t| t.container.as_deref())
+    }
+
+    /// IRI expansion for terms, compact IRIs and vocabulary relative IRIs. Relative IRIs are
+    /// returned unchanged, because there is no document base for activities.
+    fn expand_iri(&self, value: &str, vocab: bool) -> Option<String> {
+        if is_keyword(value) {
+            return Some(value.to_string());
+        }
+        if vocab {
+            if let Some(definition) = self.terms.get(value) {
+                return definition.as_ref().map(|d| d.iri.clone());
+            }
+        }
+        if let Some((prefix, suffix)) = value.split_once(':') {
+            if prefix == "_" || suffix.starts_with("//") {
+                return Some(value.to_string());
+            }
+            if let Some(definition) = self.term(prefix) {
+                return Some(format!("{}{}", definition.iri, suffix));
+            }
+            return Some(value.to_string());
+        }
+        if vocab {
+            if let Some(vocab) = &self.vocab {
+                return Some(format!("{}{}", vocab, value));
+            }
+        }
+        Some(value.to_string())
+    }
+}
+
+/// Expands a JSON-LD document, resolving remote contexts from `contexts`.
+pub(crate) fn expand(document: &Value, contexts: &LoadedContexts) -> Result<Vec<Value>, Error> {
+    let expander = Expander { contexts };
+    let expanded = expander.expand_element(&ActiveContext::default(), None, document)?;
+    let expanded = match expanded {
+        Value::Object(mut o) if o.len() == 1 && o.contains_key("@graph") => {
+            o.remove("@graph").unwrap_or(Value::Null)
+        }
+        v => v,
+    };
+    Ok(match expanded {
+        Value::Null => vec![],
+        Value::Array(a) => a,
+        v => vec![v],
+    })
+}
+
+/// Removes all properties from `document` which are dropped during expansion or can't be
+/// converted to RDF, for example properties that are not defined in the context. These are not
+/// covered by a signature of the canonicalized document, so they can be changed freely.
+pub(crate) fn strip_unsigned(document: &Value, contexts: &LoadedContexts) -> Result<Value, Error> {
+    let expander = Expander { contexts };
+    expander.strip_element(&ActiveContext::default(), document)
+}
+
+/// Collects the urls of all remote contexts which are referenced in `value`.
+pub(crate) fn remote_contexts(value: &Value, urls: &mut Vec<String>) {
+    match value {
+        Value::Array(items) => items.iter().for_each(|i| remote_contexts(i, urls)),
+        Value::Object(object) => {
+            for (key, value) in object {
+                if key == "@context" {
+                    context_urls(value, urls);
+                }
+                remote_contexts(value, urls);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn context_urls(context: &Value, urls: &mut Vec<String>) {
+    match context {
+        Value::String(url) if !urls.contains(url) => urls.push(url.clone()),
+        Value::Array(items) => items.iter().for_each(|i| context_urls(i, urls)),
+        _ => {}
+    }
+}
+
+struct Expander<'a> {
+    contexts: &'a LoadedContexts,
+}
+
+impl<'a> Expander<'a> {
+    fn process_context(
+        &self,
+        active: &ActiveContext,
+        local: &Value,
+        remote_stack: &mut Vec<String>,
+    ) -> Result<ActiveContext, Error> {
+        let mut result = active.clone();
+        let contexts = match local {
+            Value::Array(items) => items.iter().collect(),
+            v => vec![v],
+        };
+        for context in contexts {
+            match context {
+                Value::Null => result = ActiveContext::default(),
+                Value::String(url) => {
+                    if remote_stack.contains(url) {
+                        return Err(invalid_json_ld("Recursive context inclusion"));
+                    }
+                    let remote = self
+                        .contexts
+                        .get(url)
+                        .and_then(|document| document.get("@context"))
+                        .ok_or_else(|| invalid_json_ld("Failed to load remote context"))?;
+                    remote_stack.push(url.clone());
+                    result = self.process_context(&result, remote, remote_stack)?;
+                    remote_stack.pop();
+                }
+                Value::Object(context) => {
+                    match context.get("@vocab") {
+                        None => {}
+                        Some(Value::Null) => result.vocab = None,
+                        Some(Value::String(vocab)) if vocab.contains(':') => {
+                            result.vocab = Some(vocab.clone())
+                        }
+                        Some(_) => return Err(invalid_json_ld("Invalid vocab mapping")),
+                    }
+                    match context.get("@language") {
+                        None => {}
+                        Some(Value::Null) => result.language = None,
+                        Some(Value::String(language)) => {
+                            result.language = Some(language.to_lowercase())
+                        }
+                        Some(_) => return Err(invalid_json_ld("Invalid default language")),
+                    }
+                    let mut defined = HashMap::new();
+                    for term in context.keys() {
+                        // @base is irrelevant without document base, newer keywords like
+                        // @version are ignored
+                        if term.starts_with('@') {
+                            continue;
+                        }
+                        self.create_term_definition(&mut result, context, term, &mut defined)?;
+                    }
+                }
+                _ => return Err(invalid_json_ld("Invalid local context")),
+            }
+        }
+        Ok(result)
+    }
+
+    fn create_term_definition(
+        &self,
+        active: &mut ActiveContext,
+        local: &Map<String, Value>,
+        term: &str,
+        defined: &mut HashMap<String, bool>,
+    ) -> Result<(), Error> {
+        match defined.get(term) {
+            Some(true) => return Ok(()),
+            Some(false) => return Err(invalid_json_ld("Cyclic IRI mapping")),
+            None => {}
+        }
+        defined.insert(term.to_string(), false);
+        active.terms.remove(term);
+
+        let value = match &local[term] {
+            Value::String(id) => json!({ "@id": id }),
+            v => v.clone(),
+        };
+        let value = match value {
+            Value::Object(o) if o.get("@id") != Some(&Value::Null) => o,
+            Value::Object(_) | Value::Null => {
+                active.terms.insert(term.to_string(), None);
+                defined.insert(term.to_string(), true);
+                return Ok(());
+            }
+            _ => return Err(invalid_json_ld("Invalid term definition")),
+        };
+
+        let mut definition = TermDefinition {
+            iri: String::new(),
+            reverse: false,
+            type_mapping: None,
+            language_mapping: None,
+            container: None,
+        };
+        if let Some(type_) = value.get("@type") {
+            let type_ = type_
+                .as_str()
+                .ok_or_else(|| invalid_json_ld("Invalid type mapping"))?;
+            let type_ = self.expand_iri_in_context(active, type_, local, defined)?;
+            match type_ {
+                Some(t) if t == "@id" || t == "@vocab" || is_absolute_iri(&t) => {
+                    definition.type_mapping = Some(t)
+                }
+                _ => return Err(invalid_json_ld("Invalid type mapping")),
+            }
+        }
+        if let Some(reverse) = value.get("@reverse") {
+            let reverse = reverse
+                .as_str()
+                .filter(|_| !value.contains_key("@id"))
+                .ok_or_else(|| invalid_json_ld("Invalid reverse property"))?;
+            definition.iri = self
+                .expand_iri_in_context(active, reverse, local, defined)?
+                .filter(|iri| iri.contains(':'))
+                .ok_or_else(|| invalid_json_ld("Invalid IRI mapping"))?;
+            definition.reverse = true;
+        } else if let Some(id) = value.get("@id").filter(|id| id.as_str() != Some(term)) {
+            let id = id
+                .as_str()
+                .ok_or_else(|| invalid_json_ld("Invalid IRI mapping"))?;
+            definition.iri = self
+                .expand_iri_in_context(active, id, local, defined)?
+                .filter(|iri| is_keyword(iri) || iri.contains(':'))
+                .ok_or_else(|| invalid_json_ld("Invalid IRI mapping"))?;
+        } else if let Some((prefix, suffix)) = term.split_once(':') {
+            if local.contains_key(prefix) {
+                self.create_term_definition(active, local, prefix, defined)?;
+            }
+            definition.iri = match active.term(prefix) {
+                Some(prefix) => format!("{}{}", prefix.iri, suffix),
+                None => term.to_string(),
+            };
+        } else if let Some(vocab) = &active.vocab {
+            definition.iri = format!("{}{}", vocab, term);
+        } else {
+            return Err(invalid_json_ld("Invalid IRI mapping"));
+        }
+
+        match value.get("@container") {
+            None | Some(Value::Null) => {}
+            Some(Value::String(c))
+                if ["@list", "@set", "@index", "@language"].contains(&c.as_str()) =>
+            {
+                definition.container = Some(c.clone())
+            }
+            Some(_) => return Err(invalid_json_ld("Invalid container mapping")),
+        }
+        match value.get("@language") {
+            None => {}
+            Some(Value::Null) => definition.language_mapping = Some(None),
+            Some(Value::String(l)) => definition.language_mapping = Some(Some(l.to_lowercase())),
+            Some(_) => return Err(invalid_json_ld("Invalid language mapping")),
+        }
+
+        active.terms.insert(term.to_string(), Some(definition));
+        defined.insert(term.to_string(), true);
+        Ok(())
+    }
+
+    /// IRI expansion during context processing, which first defines any terms from the local
+    /// context that `value` depends on.
+    fn expand_iri_in_context(
+        &self,
+        active: &mut ActiveContext,
+        value: &str,
+        local: &Map<String, Value>,
+        defined: &mut HashMap<String, bool>,
+    ) -> Result<Option<String>, Error> {
+        if local.contains_key(value) {
+            self.create_term_definition(active, local, value, defined)?;
+        }
+        if let Some((prefix, _)) = value.split_once(':') {
+            if local.contains_key(prefix) {
+                self.create_term_definition(active, local, prefix, defined)?;
+            }
+        }
+        Ok(active.expand_iri(value, true))
+    }
+
+    fn expand_element(
+        &self,
+        active: &ActiveContext,
+        active_property: Option<&str>,
+        element: &Value,
+    ) -> Result<Value, Error> {
+        match element {
+            Value::Null => Ok(Value::Null),
+            Value::Array(items) => {
+                let is_list = active_property
+                    .map(|p| p == "@list" || active.container(p) == Some("@list"))
+                    .unwrap_or(false);
+                let mut result = vec![];
+                for item in items {
+                    let expanded = self.expand_element(active, active_property, item)?;
+                    if is_list && (expanded.is_array() || is_list_object(&expanded)) {
+                        return Err(invalid_json_ld("List of lists"));
+                    }
+                    match expanded {
+                        Value::Array(a) => result.extend(a),
+                        Value::Null => {}
+                        v => result.push(v),
+                    }
+                }
+                Ok(Value::Array(result))
+            }
+            Value::Object(object) => self.expand_object(active, active_property, object),
+            scalar => match active_property {
+                None | Some("@graph") => Ok(Value::Null),
+                Some(property) => Ok(expand_value(active, property, scalar)),
+            },
+        }
+    }
+
+    fn expand_object(
+        &self,
+        active: &ActiveContext,
+        active_property: Option<&str>,
+        object: &Map<String, Value>,
+    ) -> Result<Value, Error> {
+        let processed;
+        let active = match object.get("@context") {
+            Some(context) => {
+                processed = self.process_context(active, context, &mut vec![])?;
+                &processed
+            }
+            None => active,
+        };
+
+        let mut result = Map::new();
+        let mut keys: Vec<&String> = object.keys().collect();
+        keys.sort();
+        for key in keys {
+            let value = &object[key];
+            if key == "@context" {
+                continue;
+            }
+            let expanded_property = match active.expand_iri(key, true) {
+                Some(p) if p.contains(':') || is_keyword(&p) => p,
+                _ => continue,
+            };
+
+            if is_keyword(&expanded_property) {
+                if active_property == Some("@reverse") {
+                    return Err(invalid_json_ld("Invalid reverse property map"));
+                }
+                if result.contains_key(&expanded_property) {
+                    return Err(invalid_json_ld("Colliding keywords"));
+                }
+                let expanded_value = match expanded_property.as_str() {
+                    "@id" => {
+                        let id = value
+                            .as_str()
+                            .ok_or_else(|| invalid_json_ld("Invalid @id value"))?;
+                        Value::String(active.expand_iri(id, false).unwrap_or_default())
+                    }
+                    "@type" => {
+                        let expand_type = |t: &Value| {
+                            t.as_str()
+                                .and_then(|t| active.expand_iri(t, true))
+                                .map(Value::String)
+                                .ok_or_else(|| invalid_json_ld("Invalid type value"))
+                        };
+                        match value {
+                            Value::Array(types) => Value::Array(
+                                types.iter().map(expand_type).collect::<Result<_, _>>()?,
+                            ),
+                            t => expand_type(t)?,
+                        }
+                    }
+                    "@graph" => self.expand_element(active, Some("@graph"), value)?,
+                    "@value" => {
+                        if value.is_object() || value.is_array() {
+                            return Err(invalid_json_ld("Invalid value object value"));
+                        }
+                        value.clone()
+                    }
+                    "@language" => Value::String(
+                        value
+                            .as_str()
+                            .ok_or_else(|| invalid_json_ld("Invalid language-tagged string"))?
+                            .to_lowercase(),
+                    ),
+                    "@index" => {
+                        if !value.is_string() {
+                            return Err(invalid_json_ld("Invalid @index value"));
+                        }
+                        value.clone()
+                    }
+                    "@list" => {
+                        if matches!(active_property, None | Some("@graph")) {
+                            continue;
+                        }
+                        let list = as_array(self.expand_element(active, active_property, value)?);
+                        if list.iter().any(is_list_object) {
+                            return Err(invalid_json_ld("List of lists"));
+                        }
+                        Value::Array(list)
+                    }
+                    "@set" => self.expand_element(active, active_property, value)?,
+                    "@reverse" => {
+                        self.expand_reverse(active, value, &mut result)?;
+                        continue;
+                    }
+                    _ => continue,
+                };
+                result.insert(expanded_property, expanded_value);
+                continue;
+            }
+
+            let term = active.term(key);
+            let container = term.and_then(|t| t.container.as_deref());
+            let mut expanded_value = match (container, value) {
+                (Some("@language"), Value::Object(map)) => expand_language_map(map)?,
+                (Some("@index"), Value::Object(map)) => self.expand_index_map(active, key, map)?,
+                _ => self.expand_element(active, Some(key), value)?,
+            };
+            if expanded_value.is_null() {
+                continue;
+            }
+            if container == Some("@list") && !is_list_object(&expanded_value) {
+                expanded_value = json!({ "@list": as_array(expanded_value) });
+            }
+            if term.map(|t| t.reverse).unwrap_or(false) {
+                let reverse_map = result
+                    .entry("@reverse")
+                    .or_insert_with(|| Value::Object(Map::new()));
+                for item in as_array(expanded_value) {
+                    if is_list_object(&item) || item.get("@value").is_some() {
+                        return Err(invalid_json_ld("Invalid reverse property value"));
+                    }
+                    if let Value::Object(reverse_map) = reverse_map {
+                        add_value(reverse_map, &expanded_property, item);
+                    }
+                }
+            } else {
+                for item in as_array(expanded_value) {
+                    add_value(&mut result, &expanded_property, item);
+                }
+                // make sure that properties with empty arrays are kept
+                result
+                    .entry(expanded_property)
+                    .or_insert_with(|| Value::Array(vec![]));
+            }
+        }
+
+        post_process(result, active_property)
+    }
+
+    fn strip_element(&self, active: &ActiveContext, element: &Value) -> Result<Value, Error> {
+        match element {
+            Value::Array(items) => Ok(Value::Array(
+                items
+                    .iter()
+                    .map(|item| self.strip_element(active, item))
+                    .collect::<Result<_, _>>()?,
+            )),
+            Value::Object(object) => self.strip_object(active, object),
+            scalar => Ok(scalar.clone()),
+        }
+    }
+
+    /// Follows the same steps as [Expander::expand_object], but keeps the compacted form
+    fn strip_object(
+        &self,
+        active: &ActiveContext,
+        object: &Map<String, Value>,
+    ) -> Result<Value, Error> {
+        let processed;
+        let active = match object.get("@context") {
+            Some(context) => {
+                processed = self.process_context(active, context, &mut vec![])?;
+                &processed
+            }
+            None => active,
+        };
+        let is_signed = |iri: &str| !iri.starts_with("_:") && is_absolute_iri(iri);
+
+        let mut result = Map::new();
+        for (key, value) in object {
+            let value = match active.expand_iri(key, true) {
+                Some(property) if is_keyword(&property) => match property.as_str() {
+                    "@type" => {
+                        let types: Vec<_> = as_array(value.clone())
+                            .into_iter()
+                            .filter(|t| {
+                                t.as_str()
+                                    .and_then(|t| active.expand_iri(t, true))
+                                    .is_some_and(|t| is_signed(&t))
+                            })
+                            .collect();
+                        match (value, types.len()) {
+                            (_, 0) => continue,
+                            (Value::Array(_), _) => Value::Array(types),
+                            _ => value.clone(),
+                        }
+                    }
+                    "@graph" | "@list" | "@set" | "@reverse" => {
+                        self.strip_element(active, value)?
+                    }
+                    _ => value.clone(),
+                },
+                Some(property) if is_signed(&property) => match (active.container(key), value) {
+                    (Some("@language"), _) => value.clone(),
+                    (Some("@index"), Value::Object(map)) => Value::Object(
+                        map.iter()
+                            .map(|(index, item)| {
+                                Ok((index.clone(), self.strip_element(active, item)?))
+                            })
+                            .collect::<Result<_, Error>>()?,
+                    ),
+                    _ => self.strip_element(active, value)?,
+                },
+                _ => continue,
+            };
+            result.insert(key.clone(), value);
+        }
+        Ok(Value::Object(result))
+    }
+
+    fn expand_reverse(
+        &self,
+        active: &ActiveContext,
+        value: &Value,
+        result: &mut Map<String, Value>,
+    ) -> Result<(), Error> {
+        let expanded = match self.expand_element(active, Some("@reverse"), value)? {
+            Value::Object(o) => o,
+            _ => return Err(invalid_json_ld("Invalid @reverse value")),
+        };
+        for (property, items) in expanded {
+            if property == "@reverse" {
+                // double reverse properties are normal properties
+                if let Value::Object(reversed) = items {
+                    for (property, items) in reversed {
+                        for item in as_array(items) {
+                            add_value(result, &property, item);
+                        }
+                    }
+                }
+                continue;
+            }
+            let reverse_map = result
+                .entry("@reverse")
+                .or_insert_with(|| Value::Object(Map::new()));
+            for item in as_array(items) {
+                if is_list_object(&item) || item.get("@value").is_some() {
+                    return Err(invalid_json_ld("Invalid reverse property value"));
+                }
+                if let Value::Object(reverse_map) = reverse_map {
+                    add_value(reverse_map, &property, item);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn expand_index_map(
+        &self,
+        active: &ActiveContext,
+        key: &str,
+        map: &Map<String, Value>,
+    ) -> Result<Value, Error> {
+        let mut indexes: Vec<&String> = map.keys().collect();
+        indexes.sort();
+        let mut result = vec![];
+        for index in indexes {
+            let expanded = self.expand_element(active, Some(key), &map[index])?;
+            for mut item in as_array(expanded) {
+                if let Value::Object(item) = &mut item {
+                    item.entry("@index")
+                        .or_insert_with(|| Value::String(index.clone()));
+                }
+                result.push(item);
+            }
+        }
+        Ok(Value::Array(result))
+    }
+}
+
+fn expand_value(active: &ActiveContext, active_property: &str, value: &Value) -> Value {
+    let term = active.term(active_property);
+    let type_mapping = term.and_then(|t| t.type_mapping.as_deref());
+    if let Value::String(id) = value {
+        match type_mapping {
+            Some("@id") => return json!({ "@id": active.expand_iri(id, false) }),
+            Some("@vocab") => return json!({ "@id": active.expand_iri(id, true) }),
+            _ => {}
+        }
+    }
+    let mut result = Map::new();
+    result.insert("@value".to_string(), value.clone());
+    match type_mapping {
+        Some(type_) if type_ != "@id" && type_ != "@vocab" => {
+            result.insert("@type".to_string(), Value::String(type_.to_string()));
+        }
+        _ if value.is_string() => {
+            let language = match term.and_then(|t| t.language_mapping.clone()) {
+                Some(language) => language,
+                None => active.language.clone(),
+            };
+            if let Some(language) = language {
+                result.insert("@language".to_string(), Value::String(language));
+            }
+        }
+        _ => {}
+    }
+    Value::Object(result)
+}
+
+fn expand_language_map(map: &Map<String, Value>) -> Result<Value, Error> {
+    let mut languages: Vec<&String> = map.keys().collect();
+    languages.sort();
+    let mut result = vec![];
+    for language in languages {
+        for item in as_array(map[language].clone()) {
+            let item = item
+                .as_str()
+                .ok_or_else(|| invalid_json_ld("Invalid language map value"))?;
+            result.push(json!({ "@value": item, "@language": language.to_lowercase() }));
+        }
+    }
+    Ok(Value::Array(result))
+}
+
+fn post_process(
+    mut result: Map<String, Value>,
+    active_property: Option<&str>,
+) -> Result<Value, Error> {
+    if let Some(value) = result.get("@value") {
+        let allowed = ["@value", "@language", "@type", "@index"];
+        if result.keys().any(|k| !allowed.contains(&k.as_str()))
+            || (result.contains_key("@language") && result.contains_key("@type"))
+        {
+            return Err(invalid_json_ld("Invalid value object"));
+        }
+        if value.is_null() {
+            return Ok(Value::Null);
+        }
+        if !value.is_string() && result.contains_key("@language") {
+            return Err(invalid_json_ld("Invalid language-tagged value"));
+        }
+        if let Some(type_) = result.get("@type") {
+            if !type_.as_str().map(is_absolute_iri).unwrap_or(false) {
+                return Err(invalid_json_ld("Invalid typed value"));
+            }
+        }
+    } else if let Some(type_) = result.get_mut("@type") {
+        if !type_.is_array() {
+            *type_ = Value::Array(vec![type_.take()]);
+        }
+    } else if result.contains_key("@set") || result.contains_key("@list") {
+        if result.len() > 2 || (result.len() == 2 && !result.contains_key("@index")) {
+            return Err(invalid_set_or_list());
+        }
+        if let Some(set) = result.remove("@set") {
+            return Ok(match set {
+                Value::Object(o) => post_process(o, active_property)?,
+                v => v,
+            });
+        }
+    }
+
+    if result.len() == 1 && result.contains_key("@language") {
+        return Ok(Value::Null);
+    }
+    if matches!(active_property, None | Some("@graph"))
+        && (result.is_empty()
+            || result.contains_key("@value")
+            || result.contains_key("@list")
+            || (result.len() == 1 && result.contains_key("@id")))
+    {
+        return Ok(Value::Null);
+    }
+    Ok(Value::Object(result))
+}
+
+fn invalid_set_or_list() -> Error {
+    invalid_json_ld("Invalid set or list object")
+}
+
+fn add_value(object: &mut Map<String, Value>, property: &str, value: Value) {
+    match object
+        .entry(property)
+        .or_insert_with(|| Value::Array(vec![]))
+    {
+        Value::Array(values) => values.push(value),
+        existing => *existing = Value::Array(vec![existing.take(), value]),
+    }
+}
+
+pub(crate) fn as_array(value: Value) -> Vec<Value> {
+    match value {
+        Value::Array(a) => a,
+        Value::Null => vec![],
+        v => vec![v],
+    }
+}
+
+pub(crate) fn is_list_object(value: &Value) -> bool {
+    value.get("@list").is_some()
+}
+
+pub(crate) fn is_absolute_iri(value: &str) -> bool {
+    value
+        .split_once(':')
+        .map(|(scheme, _)| {
+            !scheme.is_empty()
+                && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+        })
+        .unwrap_or(false)
+}
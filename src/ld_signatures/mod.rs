@@ -0,0 +1,506 @@
+//! Creating and verifying Linked Data Signatures which are embedded in activities
+//!
+//! When Mastodon forwards an activity (for example a reply or a delete) to another server, the
+//! HTTP signature is made by the forwarding actor, not by the author of the activity. To prove
+//! that the activity is authentic, the author embeds an `RsaSignature2017` signature in the
+//! `signature` field:
+//!
+//! ```json
+//! "signature": {
+//!   "type": "RsaSignature2017",
+//!   "creator": "https://mastodon.example/users/alice#main-key",
+//!   "created": "2023-03-01T12:00:00Z",
+//!   "signatureValue": "QmFzZTY0..."
+//! }
+//! ```
+//!
+//! The signature covers the JSON-LD canonicalized form of the activity (URDNA2015), so it stays
+//! valid regardless of how the json is formatted. Verification of incoming activities is
+//! disabled by default, and can be enabled with
+//! [FederationConfigBuilder::ld_signatures](crate::config::FederationConfigBuilder::ld_signatures).
+//!
+//! Canonicalization needs the JSON-LD contexts which are referenced by the activity. The
+//! ActivityStreams, `security/v1` and `identity/v1` contexts are built in, which covers
+//! activities from Mastodon. Remote contexts are never fetched, because the urls come from
+//! untrusted input. Activities which reference any other remote context are rejected, unless the
+//! context was added with
+//! [FederationConfig::add_ld_context](crate::config::FederationConfig::add_ld_context).
+//!
+//! Only properties and types which are defined in the JSON-LD context are covered by the
+//! signature, all others can be changed by the server which forwarded the activity. They are
+//! removed from activities that are verified with a Linked Data Signature, before the activity
+//! is passed to inbox policies and handlers.
+//!
+//! This module requires the `ld-signatures` feature, which is enabled by default.
+
+use crate::{
+    config::Data,
+    error::{Error, Error::ActivitySignatureInvalid},
+    http_signatures::{actor_key, verify_with_key},
+    traits::Actor,
+};
+use anyhow::anyhow;
+use chrono::{SecondsFormat, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use url::Url;
+
+mod expansion;
+mod rdf;
+mod urdna2015;
+
+/// Signature type which is used by Mastodon
+pub const RSA_SIGNATURE_2017: &str = "RsaSignature2017";
+
+/// Context which is used to canonicalize the signature options
+const IDENTITY_CONTEXT: &str = "https://w3id.org/identity/v1";
+
+/// Contexts which are included in the library, keyed by url
+static BUILTIN_CONTEXTS: Lazy<HashMap<&str, Value>> = Lazy::new(|| {
+    [
+        (
+            "https://www.w3.org/ns/activitystreams",
+            include_str!("contexts/activitystreams.jsonld"),
+        ),
+        (
+            "https://w3id.org/security/v1",
+            include_str!("contexts/security-v1.jsonld"),
+        ),
+        (
+            IDENTITY_CONTEXT,
+            include_str!("contexts/identity-v1.jsonld"),
+        ),
+    ]
+    .into_iter()
+    .map(|(url, context)| {
+        let context = serde_json::from_str(context).expect("built-in context is valid json");
+        (url, context)
+    })
+    .collect()
+});
+
+/// Linked Data Signature in the `signature` field of an activity
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct LdSignature {
+    /// Signature algorithm, only `RsaSignature2017` is supported
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Id of the key which created the signature
+    pub creator: Url,
+    /// Time of signature creation, in ISO 8601 format
+    pub created: String,
+    /// Base64 encoded signature
+    pub signature_value: String,
+}
+
+/// Adds an `RsaSignature2017` signature by `key_id` to the `document`, signed with
+/// `private_key`. An existing signature is replaced.
+///
+/// This is only needed by servers which want their activities to be forwarded by others, normal
+/// delivery uses HTTP signatures.
+pub async fn create_ld_signature<T: Clone>(
+    document: &Value,
+    key_id: &Url,
+    private_key: &str,
+    data: &Data<T>,
+) -> Result<Value, Error> {
+    let mut document = document
+        .as_object()
+        .cloned()
+        .ok_or_else(|| invalid_json_ld("Document must be an object"))?;
+    document.remove("signature");
+    let mut options = Map::new();
+    options.insert("creator".to_string(), Value::String(key_id.to_string()));
+    options.insert(
+        "created".to_string(),
+        Value::String(Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)),
+    );
+
+    let to_be_signed = signing_input(&document, &options, data)?;
+    let signature = data
+        .config
+        .crypto_backend
+        .sign(private_key, to_be_signed.as_bytes())?;
+
+    options.insert(
+        "type".to_string(),
+        Value::String(RSA_SIGNATURE_2017.to_string()),
+    );
+    options.insert(
+        "signatureValue".to_string(),
+        Value::String(base64::encode(signature)),
+    );
+    document.insert("signature".to_string(), Value::Object(options));
+    Ok(Value::Object(document))
+}
+
+/// Verifies that `document` contains a valid Linked Data Signature made by `actor`.
+///
+/// The `creator` key is resolved in the same way as the `keyId` of HTTP signatures, see
+/// [receive_activity (actix-web)](crate::actix_web::inbox::receive_activity).
+///
+/// The signature only covers properties which are defined in the JSON-LD context, any other
+/// properties can be added or changed by the server which forwarded the document. Returns the
+/// document without these unsigned properties, which must be used instead of the original.
+pub async fn verify_ld_signature<A, T>(
+    document: &Value,
+    actor: &A,
+    data: &Data<T>,
+) -> Result<Value, Error>
+where
+    A: Actor,
+    T: Clone,
+{
+    let mut document = document
+        .as_object()
+        .cloned()
+        .ok_or(ActivitySignatureInvalid)?;
+    let mut options = match document.remove("signature") {
+        Some(Value::Object(options)) => options,
+        _ => return Err(ActivitySignatureInvalid),
+    };
+    let signature_json = Value::Object(options.clone());
+    let signature: LdSignature =
+        serde_json::from_value(signature_json.clone()).map_err(Error::other)?;
+    if signature.kind != RSA_SIGNATURE_2017 {
+        return Err(Error::other(anyhow!(
+            "Unsupported signature type {}",
+            signature.kind
+        )));
+    }
+    for key in ["type", "id", "signatureValue"] {
+        options.remove(key);
+    }
+    let signature_value = base64::decode(&signature.signature_value).map_err(Error::other)?;
+
+    let to_be_verified = signing_input(&document, &options, data)?;
+    let crypto = data.config.crypto_backend.as_ref();
+    let key = actor_key(signature.creator.as_str(), actor, data).await?;
+    if !verify_with_key(&key, to_be_verified.as_bytes(), &signature_value, crypto)? {
+        return Err(ActivitySignatureInvalid);
+    }
+
+    let document = Value::Object(document);
+    let contexts = load_contexts(&document, data)?;
+    let mut signed = expansion::strip_unsigned(&document, &contexts)?;
+    if let Value::Object(signed) = &mut signed {
+        signed.insert("signature".to_string(), signature_json);
+    }
+    Ok(signed)
+}
+
+/// The signed data consists of the hex encoded hashes of the canonicalized signature options and
+/// the canonicalized document, concatenated.
+fn signing_input<T: Clone>(
+    document: &Map<String, Value>,
+    options: &Map<String, Value>,
+    data: &Data<T>,
+) -> Result<String, Error> {
+    let mut options = options.clone();
+    options.insert(
+        "@context".to_string(),
+        Value::String(IDENTITY_CONTEXT.to_string()),
+    );
+    let options_hash = canonical_hash(&Value::Object(options), data)?;
+    let document_hash = canonical_hash(&Value::Object(document.clone()), data)?;
+    Ok(format!("{}{}", options_hash, document_hash))
+}
+
+fn canonical_hash<T: Clone>(document: &Value, data: &Data<T>) -> Result<String, Error> {
+    let contexts = load_contexts(document, data)?;
+    let expanded = expansion::expand(document, &contexts)?;
+    let quads = rdf::to_rdf(&expanded)?;
+    let canonical = urdna2015::canonicalize(&quads)?;
+    Ok(urdna2015::hex(&Sha256::digest(canonical.as_bytes())))
+}
+
+/// Loads all remote contexts referenced by `document`, including contexts which are referenced
+/// by other contexts. Fails if any of them is unknown.
+fn load_contexts<T: Clone>(
+    document: &Value,
+    data: &Data<T>,
+) -> Result<expansion::LoadedContexts, Error> {
+    let mut urls = vec![];
+    expansion::remote_contexts(document, &mut urls);
+    let mut loaded = expansion::LoadedContexts::new();
+    while let Some(url) = urls.pop() {
+        if loaded.contains_key(&url) {
+            continue;
+        }
+        let context = load_context(&url, data)?;
+        expansion::remote_contexts(&context, &mut urls);
+        loaded.insert(url, context);
+    }
+    Ok(loaded)
+}
+
+/// Returns a built-in context or one which was added by the application. Other contexts are
+/// rejected instead of fetching them.
+fn load_context<T: Clone>(url: &str, data: &Data<T>) -> Result<Value, Error> {
+    if let Some(context) = BUILTIN_CONTEXTS.get(url) {
+        return Ok(context.clone());
+    }
+    data.config
+        .ld_context(url)
+        .ok_or_else(|| Error::other(anyhow!("Unsupported JSON-LD context {}", url)))
+}
+
+pub(crate) fn invalid_json_ld(message: &str) -> Error {
+    Error::other(anyhow!("Invalid JSON-LD: {}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::FederationConfig,
+        traits::tests::{DbConnection, DB_USER, DB_USER_KEYPAIR},
+    };
+    use serde_json::json;
+
+    fn config() -> FederationConfig<DbConnection> {
+        FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(DbConnection)
+            .debug(true)
+            .build()
+            .unwrap()
+    }
+
+    fn activity() -> Value {
+        json!({
+            "@context": [
+                "https://www.w3.org/ns/activitystreams",
+                { "sensitive": "as:sensitive" }
+            ],
+            "id": "https://localhost/activities/1",
+            "type": "Create",
+            "actor": "https://localhost/123",
+            "to": ["https://www.w3.org/ns/activitystreams#Public"],
+            "object": {
+                "type": "Note",
+                "content": "hello \"world\"",
+                "contentMap": { "en": "hello \"world\"" },
+                "sensitive": false,
+                "unknownProperty": 123,
+                "tag": []
+            }
+        })
+    }
+
+    #[actix_rt::test]
+    async fn test_ld_signature_roundtrip() {
+        let data = config().to_request_data();
+        let key_id = Url::parse(&DB_USER.private_key_id()).unwrap();
+        let mut activity = activity();
+        activity["object"]["type"] = json!(["Note", "UnknownType"]);
+        let signed = create_ld_signature(&activity, &key_id, &DB_USER_KEYPAIR.private_key, &data)
+            .await
+            .unwrap();
+        let verified = verify_ld_signature(&signed, &*DB_USER, &data)
+            .await
+            .unwrap();
+        assert_eq!(verified["object"]["sensitive"], json!(false));
+
+        // Formatting, properties and type names which are not defined in the context are not
+        // signed, so the latter are removed
+        let mut reformatted = signed.clone();
+        reformatted["object"]["unknownProperty"] = json!(456);
+        reformatted["object"]["type"] = json!(["Note", "OtherType"]);
+        reformatted["to"] = json!("https://www.w3.org/ns/activitystreams#Public");
+        let verified = verify_ld_signature(&reformatted, &*DB_USER, &data)
+            .await
+            .unwrap();
+        let mut expected = reformatted.clone();
+        expected["object"]
+            .as_object_mut()
+            .unwrap()
+            .remove("unknownProperty");
+        expected["object"]["type"] = json!(["Note"]);
+        assert_eq!(verified, expected);
+
+        let mut tampered = signed.clone();
+        tampered["object"]["content"] = json!("changed");
+        assert_eq!(
+            verify_ld_signature(&tampered, &*DB_USER, &data).await,
+            Err(ActivitySignatureInvalid)
+        );
+        let mut tampered = signed.clone();
+        tampered["signature"]["created"] = json!("2000-01-01T00:00:00Z");
+        assert_eq!(
+            verify_ld_signature(&tampered, &*DB_USER, &data).await,
+            Err(ActivitySignatureInvalid)
+        );
+        assert_eq!(
+            verify_ld_signature(&activity, &*DB_USER, &data).await,
+            Err(ActivitySignatureInvalid)
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_canonicalize_document() {
+        let data = config().to_request_data();
+        let contexts = load_contexts(&activity(), &data).unwrap();
+        let expanded = expansion::expand(&activity(), &contexts).unwrap();
+        let canonical = urdna2015::canonicalize(&rdf::to_rdf(&expanded).unwrap()).unwrap();
+        assert_eq!(
+            canonical,
+            r#"<https://localhost/activities/1> <http://www.w3.org/1999/02/22-rdf-syntax-ns#type> <https://www.w3.org/ns/activitystreams#Create> .
+<https://localhost/activities/1> <https://www.w3.org/ns/activitystreams#actor> <https://localhost/123> .
+<https://localhost/activities/1> <https://www.w3.org/ns/activitystreams#object> _:c14n0 .
+<https://localhost/activities/1> <https://www.w3.org/ns/activitystreams#to> <https://www.w3.org/ns/activitystreams#Public> .
+_:c14n0 <http://www.w3.org/1999/02/22-rdf-syntax-ns#type> <https://www.w3.org/ns/activitystreams#Note> .
+_:c14n0 <https://www.w3.org/ns/activitystreams#content> "hello \"world\"" .
+_:c14n0 <https://www.w3.org/ns/activitystreams#content> "hello \"world\""@en .
+_:c14n0 <https://www.w3.org/ns/activitystreams#sensitive> "false"^^<http://www.w3.org/2001/XMLSchema#boolean> .
+"#
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_unknown_context() {
+        let config = config();
+        let mut activity = activity();
+        activity["@context"] = json!([
+            "https://www.w3.org/ns/activitystreams",
+            "https://example.com/context"
+        ]);
+        let data = config.to_request_data();
+        assert!(load_contexts(&activity, &data).is_err());
+
+        config.add_ld_context("https://example.com/context", json!({ "@context": {} }));
+        assert!(load_contexts(&activity, &data).is_ok());
+    }
+
+    /// Activity in the shape sent by Mastodon, with its signature options. The expected output
+    /// was derived by hand from the URDNA2015 specification, with the hashes computed separately.
+    #[actix_rt::test]
+    async fn test_mastodon_signing_input() {
+        let activity = json!({
+            "@context": [
+                "https://www.w3.org/ns/activitystreams",
+                {
+                    "ostatus": "http://ostatus.org#",
+                    "atomUri": "ostatus:atomUri",
+                    "inReplyToAtomUri": "ostatus:inReplyToAtomUri",
+                    "conversation": "ostatus:conversation",
+                    "sensitive": "as:sensitive",
+                    "toot": "http://joinmastodon.org/ns#",
+                    "votersCount": "toot:votersCount",
+                    "Hashtag": "as:Hashtag"
+                }
+            ],
+            "id": "https://mastodon.example/users/alice/statuses/1/activity",
+            "type": "Create",
+            "actor": "https://mastodon.example/users/alice",
+            "published": "2023-03-01T12:00:00Z",
+            "to": ["https://www.w3.org/ns/activitystreams#Public"],
+            "cc": ["https://mastodon.example/users/alice/followers", "https://lemmy.example/u/bob"],
+            "object": {
+                "id": "https://mastodon.example/users/alice/statuses/1",
+                "type": "Note",
+                "summary": null,
+                "inReplyTo": "https://lemmy.example/post/1",
+                "published": "2023-03-01T12:00:00Z",
+                "url": "https://mastodon.example/@alice/1",
+                "attributedTo": "https://mastodon.example/users/alice",
+                "to": ["https://www.w3.org/ns/activitystreams#Public"],
+                "cc": [
+                    "https://mastodon.example/users/alice/followers",
+                    "https://lemmy.example/u/bob"
+                ],
+                "sensitive": false,
+                "atomUri": "https://mastodon.example/users/alice/statuses/1",
+                "inReplyToAtomUri": null,
+                "conversation": "tag:mastodon.example,2023-03-01:objectId=1:objectType=Conversation",
+                "content": "<p>Hello\tworld</p>",
+                "contentMap": { "en": "<p>Hello\tworld</p>" },
+                "attachment": [],
+                "tag": [
+                    {
+                        "type": "Mention",
+                        "href": "https://lemmy.example/u/bob",
+                        "name": "@bob@lemmy.example"
+                    },
+                    {
+                        "type": "Hashtag",
+                        "href": "https://mastodon.example/tags/rust",
+                        "name": "#rust"
+                    }
+                ],
+                "replies": {
+                    "id": "https://mastodon.example/users/alice/statuses/1/replies",
+                    "type": "Collection",
+                    "first": {
+                        "type": "CollectionPage",
+                        "next": "https://mastodon.example/users/alice/statuses/1/replies?only_other_accounts=true&page=true",
+                        "partOf": "https://mastodon.example/users/alice/statuses/1/replies",
+                        "items": []
+                    }
+                }
+            }
+        });
+        let data = config().to_request_data();
+        let contexts = load_contexts(&activity, &data).unwrap();
+        let expanded = expansion::expand(&activity, &contexts).unwrap();
+        let canonical = urdna2015::canonicalize(&rdf::to_rdf(&expanded).unwrap()).unwrap();
+        let status = "<https://mastodon.example/users/alice/statuses/1>";
+        let activity_id = "<https://mastodon.example/users/alice/statuses/1/activity>";
+        let replies = "<https://mastodon.example/users/alice/statuses/1/replies>";
+        let expected = [
+            format!("{activity_id} <http://www.w3.org/1999/02/22-rdf-syntax-ns#type> <https://www.w3.org/ns/activitystreams#Create> ."),
+            format!("{activity_id} <https://www.w3.org/ns/activitystreams#actor> <https://mastodon.example/users/alice> ."),
+            format!("{activity_id} <https://www.w3.org/ns/activitystreams#cc> <https://lemmy.example/u/bob> ."),
+            format!("{activity_id} <https://www.w3.org/ns/activitystreams#cc> <https://mastodon.example/users/alice/followers> ."),
+            format!("{activity_id} <https://www.w3.org/ns/activitystreams#object> {status} ."),
+            format!("{activity_id} <https://www.w3.org/ns/activitystreams#published> \"2023-03-01T12:00:00Z\"^^<http://www.w3.org/2001/XMLSchema#dateTime> ."),
+            format!("{activity_id} <https://www.w3.org/ns/activitystreams#to> <https://www.w3.org/ns/activitystreams#Public> ."),
+            format!("{replies} <http://www.w3.org/1999/02/22-rdf-syntax-ns#type> <https://www.w3.org/ns/activitystreams#Collection> ."),
+            format!("{replies} <https://www.w3.org/ns/activitystreams#first> _:c14n2 ."),
+            format!("{status} <http://ostatus.org#atomUri> \"https://mastodon.example/users/alice/statuses/1\" ."),
+            format!("{status} <http://ostatus.org#conversation> \"tag:mastodon.example,2023-03-01:objectId=1:objectType=Conversation\" ."),
+            format!("{status} <http://www.w3.org/1999/02/22-rdf-syntax-ns#type> <https://www.w3.org/ns/activitystreams#Note> ."),
+            format!("{status} <https://www.w3.org/ns/activitystreams#attributedTo> <https://mastodon.example/users/alice> ."),
+            format!("{status} <https://www.w3.org/ns/activitystreams#cc> <https://lemmy.example/u/bob> ."),
+            format!("{status} <https://www.w3.org/ns/activitystreams#cc> <https://mastodon.example/users/alice/followers> ."),
+            // Tab is not escaped
+            format!("{status} <https://www.w3.org/ns/activitystreams#content> \"<p>Hello\tworld</p>\" ."),
+            format!("{status} <https://www.w3.org/ns/activitystreams#content> \"<p>Hello\tworld</p>\"@en ."),
+            format!("{status} <https://www.w3.org/ns/activitystreams#inReplyTo> <https://lemmy.example/post/1> ."),
+            format!("{status} <https://www.w3.org/ns/activitystreams#published> \"2023-03-01T12:00:00Z\"^^<http://www.w3.org/2001/XMLSchema#dateTime> ."),
+            format!("{status} <https://www.w3.org/ns/activitystreams#replies> {replies} ."),
+            format!("{status} <https://www.w3.org/ns/activitystreams#sensitive> \"false\"^^<http://www.w3.org/2001/XMLSchema#boolean> ."),
+            format!("{status} <https://www.w3.org/ns/activitystreams#tag> _:c14n0 ."),
+            format!("{status} <https://www.w3.org/ns/activitystreams#tag> _:c14n1 ."),
+            format!("{status} <https://www.w3.org/ns/activitystreams#to> <https://www.w3.org/ns/activitystreams#Public> ."),
+            format!("{status} <https://www.w3.org/ns/activitystreams#url> <https://mastodon.example/@alice/1> ."),
+            "_:c14n0 <http://www.w3.org/1999/02/22-rdf-syntax-ns#type> <https://www.w3.org/ns/activitystreams#Mention> .".to_string(),
+            "_:c14n0 <https://www.w3.org/ns/activitystreams#href> <https://lemmy.example/u/bob> .".to_string(),
+            "_:c14n0 <https://www.w3.org/ns/activitystreams#name> \"@bob@lemmy.example\" .".to_string(),
+            "_:c14n1 <http://www.w3.org/1999/02/22-rdf-syntax-ns#type> <https://www.w3.org/ns/activitystreams#Hashtag> .".to_string(),
+            "_:c14n1 <https://www.w3.org/ns/activitystreams#href> <https://mastodon.example/tags/rust> .".to_string(),
+            "_:c14n1 <https://www.w3.org/ns/activitystreams#name> \"#rust\" .".to_string(),
+            "_:c14n2 <http://www.w3.org/1999/02/22-rdf-syntax-ns#type> <https://www.w3.org/ns/activitystreams#CollectionPage> .".to_string(),
+            "_:c14n2 <https://www.w3.org/ns/activitystreams#next> <https://mastodon.example/users/alice/statuses/1/replies?only_other_accounts=true&page=true> .".to_string(),
+            format!("_:c14n2 <https://www.w3.org/ns/activitystreams#partOf> {replies} ."),
+        ];
+        assert_eq!(canonical, expected.map(|line| line + "\n").concat());
+
+        let mut options = Map::new();
+        options.insert(
+            "creator".to_string(),
+            json!("https://mastodon.example/users/alice#main-key"),
+        );
+        options.insert("created".to_string(), json!("2023-03-01T12:00:00Z"));
+        let document = activity.as_object().unwrap();
+        assert_eq!(
+            signing_input(document, &options, &data).unwrap(),
+            "b9135605d60dd07d04a0f7417e0499ff7a74f2b66f6f95d33b876288a8aa9bac\
+             9d2c3dbde3f09085448f3ac145f0e2e50d8f47987425571e49f60e5856ff4351"
+        );
+    }
+}
@@ -0,0 +1,431 @@
+//! Conversion of expanded JSON-LD to an RDF dataset, following the JSON-LD 1.0 processing
+//! algorithms
+//!
+//! <https://www.w3.org/TR/2014/REC-json-ld-api-20140116/#rdf-serialization-deserialization-algorithms>
+
+use crate::{
+    error::Error,
+    ld_signatures::{
+        expansion::{as_array, is_absolute_iri, is_keyword, is_list_object},
+        invalid_json_ld,
+    },
+};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+
+const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+const RDF_FIRST: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#first";
+const RDF_REST: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#rest";
+const RDF_NIL: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#nil";
+const RDF_LANG_STRING: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#langString";
+pub(crate) const XSD_STRING: &str = "http://www.w3.org/2001/XMLSchema#string";
+const XSD_BOOLEAN: &str = "http://www.w3.org/2001/XMLSchema#boolean";
+const XSD_INTEGER: &str = "http://www.w3.org/2001/XMLSchema#integer";
+const XSD_DOUBLE: &str = "http://www.w3.org/2001/XMLSchema#double";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Term {
+    Iri(String),
+    BlankNode(String),
+    Literal {
+        value: String,
+        datatype: String,
+        language: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Quad {
+    pub(crate) subject: Term,
+    pub(crate) predicate: Term,
+    pub(crate) object: Term,
+    pub(crate) graph: Option<Term>,
+}
+
+/// Issues new blank node identifiers, remembering which identifier was issued for which existing
+/// one.
+#[derive(Clone, Debug)]
+pub(crate) struct IdentifierIssuer {
+    prefix: &'static str,
+    issued: HashMap<String, String>,
+    /// Existing identifiers in the order in which they were issued
+    order: Vec<String>,
+}
+
+impl IdentifierIssuer {
+    pub(crate) fn new(prefix: &'static str) -> Self {
+        IdentifierIssuer {
+            prefix,
+            issued: HashMap::new(),
+            order: vec![],
+        }
+    }
+
+    pub(crate) fn issue(&mut self, existing: &str) -> String {
+        if let Some(issued) = self.issued.get(existing) {
+            return issued.clone();
+        }
+        let issued = format!("{}{}", self.prefix, self.order.len());
+        self.issued.insert(existing.to_string(), issued.clone());
+        self.order.push(existing.to_string());
+        issued
+    }
+
+    pub(crate) fn get(&self, existing: &str) -> Option<&String> {
+        self.issued.get(existing)
+    }
+
+    /// Existing identifiers in the order in which they were issued.
+    pub(crate) fn issued_order(&self) -> &[String] {
+        &self.order
+    }
+
+    fn issue_new(&mut self) -> String {
+        let existing = format!("new:{}", self.order.len());
+        self.issue(&existing)
+    }
+}
+
+/// Converts an expanded JSON-LD document into RDF quads.
+pub(crate) fn to_rdf(expanded: &[Value]) -> Result<Vec<Quad>, Error> {
+    let mut generator = NodeMapGenerator {
+        graphs: BTreeMap::new(),
+        issuer: IdentifierIssuer::new("_:b"),
+    };
+    generator
+        .graphs
+        .insert("@default".to_string(), BTreeMap::new());
+    for element in expanded {
+        generator.generate(element, "@default", None, None, None)?;
+    }
+
+    let mut quads = vec![];
+    let graphs = std::mem::take(&mut generator.graphs);
+    for (graph_name, graph) in graphs {
+        let graph_term = if graph_name == "@default" {
+            None
+        } else {
+            match node_term(&graph_name) {
+                Some(term) => Some(term),
+                None => continue,
+            }
+        };
+        for (subject, node) in graph {
+            let Some(subject) = node_term(&subject) else {
+                continue;
+            };
+            let mut properties: Vec<&String> = node.keys().collect();
+            properties.sort();
+            for property in properties {
+                let values = as_array(node[property].clone());
+                if property == "@type" {
+                    for type_ in values.iter().filter_map(Value::as_str) {
+                        if let Some(object) = node_term(type_) {
+                            quads.push(Quad {
+                                subject: subject.clone(),
+                                predicate: Term::Iri(RDF_TYPE.to_string()),
+                                object,
+                                graph: graph_term.clone(),
+                            });
+                        }
+                    }
+                    continue;
+                }
+                // Blank node properties would require generalized RDF
+                if is_keyword(property) || property.starts_with("_:") || !is_absolute_iri(property)
+                {
+                    continue;
+                }
+                for item in values {
+                    let object = match item.get("@list") {
+                        Some(list) => {
+                            generator.list_to_rdf(list, graph_term.as_ref(), &mut quads)?
+                        }
+                        None => match object_to_rdf(&item) {
+                            Some(object) => object,
+                            None => continue,
+                        },
+                    };
+                    quads.push(Quad {
+                        subject: subject.clone(),
+                        predicate: Term::Iri(property.clone()),
+                        object,
+                        graph: graph_term.clone(),
+                    });
+                }
+            }
+        }
+    }
+    Ok(quads)
+}
+
+/// Converts a node identifier into an RDF term. Relative IRIs can't be represented and return
+/// `None`.
+fn node_term(id: &str) -> Option<Term> {
+    if id.starts_with("_:") {
+        Some(Term::BlankNode(id.to_string()))
+    } else if is_absolute_iri(id) {
+        Some(Term::Iri(id.to_string()))
+    } else {
+        None
+    }
+}
+
+fn object_to_rdf(item: &Value) -> Option<Term> {
+    if let Some(id) = item.get("@id") {
+        return node_term(id.as_str()?);
+    }
+    let value = item.get("@value")?;
+    let datatype = item.get("@type").and_then(Value::as_str);
+    let literal = |value: String, default_datatype: &str| Term::Literal {
+        value,
+        datatype: datatype.unwrap_or(default_datatype).to_string(),
+        language: None,
+    };
+    Some(match value {
+        Value::Bool(b) => literal(b.to_string(), XSD_BOOLEAN),
+        Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                if datatype == Some(XSD_DOUBLE) {
+                    literal(canonical_double(i as f64), XSD_DOUBLE)
+                } else {
+                    literal(i.to_string(), XSD_INTEGER)
+                }
+            } else if let Some(u) = n.as_u64() {
+                literal(u.to_string(), XSD_INTEGER)
+            } else {
+                let f = n.as_f64()?;
+                if f.fract() != 0.0 || f.abs() >= 1e21 || datatype == Some(XSD_DOUBLE) {
+                    literal(canonical_double(f), XSD_DOUBLE)
+                } else {
+                    literal(format!("{}", f as i64), XSD_INTEGER)
+                }
+            }
+        }
+        Value::String(s) => match item.get("@language").and_then(Value::as_str) {
+            Some(language) => Term::Literal {
+                value: s.clone(),
+                datatype: RDF_LANG_STRING.to_string(),
+                language: Some(language.to_string()),
+            },
+            None => literal(s.clone(), XSD_STRING),
+        },
+        _ => return None,
+    })
+}
+
+/// Formats a double in canonical XSD form, eg `5.3E0`
+fn canonical_double(value: f64) -> String {
+    let formatted = format!("{:.15e}", value);
+    let (mantissa, exponent) = formatted.split_once('e').unwrap_or((&formatted, "0"));
+    let mantissa = match mantissa.split_once('.') {
+        Some((integer, fraction)) => {
+            let fraction = fraction.trim_end_matches('0');
+            let fraction = if fraction.is_empty() { "0" } else { fraction };
+            format!("{}.{}", integer, fraction)
+        }
+        None => format!("{}.0", mantissa),
+    };
+    format!("{}E{}", mantissa, exponent)
+}
+
+type Graph = BTreeMap<String, Map<String, Value>>;
+
+struct NodeMapGenerator {
+    graphs: BTreeMap<String, Graph>,
+    issuer: IdentifierIssuer,
+}
+
+impl NodeMapGenerator {
+    /// Flattens `element` into the node map. Returns the identifier of the node if element is a
+    /// node object.
+    fn generate(
+        &mut self,
+        element: &Value,
+        graph: &str,
+        subject: Option<&str>,
+        property: Option<&str>,
+        mut list: Option<&mut Vec<Value>>,
+    ) -> Result<Option<String>, Error> {
+        let element = match element {
+            Value::Array(items) => {
+                for item in items {
+                    self.generate(item, graph, subject, property, list.as_deref_mut())?;
+                }
+                return Ok(None);
+            }
+            Value::Object(element) => element,
+            _ => return Err(invalid_json_ld("Unexpected value in expanded document")),
+        };
+        let mut element = element.clone();
+        if let Some(types) = element.get_mut("@type") {
+            for type_ in types.as_array_mut().into_iter().flatten() {
+                if let Some(t) = type_.as_str().filter(|t| t.starts_with("_:")) {
+                    *type_ = Value::String(self.issuer.issue(t));
+                }
+            }
+        }
+
+        if element.contains_key("@value") {
+            match list {
+                Some(list) => list.push(Value::Object(element)),
+                None => self.add_to_node(graph, subject, property, Value::Object(element), true),
+            }
+            return Ok(None);
+        }
+        if let Some(items) = element.get("@list") {
+            let mut result = vec![];
+            self.generate(items, graph, subject, property, Some(&mut result))?;
+            let list_object = json!({ "@list": result });
+            match list {
+                Some(list) => list.push(list_object),
+                None => self.add_to_node(graph, subject, property, list_object, false),
+            }
+            return Ok(None);
+        }
+
+        let id = match element.remove("@id") {
+            Some(Value::String(id)) if id.starts_with("_:") => self.issuer.issue(&id),
+            Some(Value::String(id)) => id,
+            _ => self.issuer.issue_new(),
+        };
+        self.node(graph, &id);
+        let reference = json!({ "@id": id });
+        match list {
+            Some(list) => list.push(reference),
+            None => self.add_to_node(graph, subject, property, reference, true),
+        }
+        if let Some(types) = element.remove("@type") {
+            for type_ in as_array(types) {
+                self.add_to_node(graph, Some(&id), Some("@type"), type_, true);
+            }
+        }
+        if let Some(index) = element.remove("@index") {
+            self.node(graph, &id).insert("@index".to_string(), index);
+        }
+        if let Some(Value::Object(reverse)) = element.remove("@reverse") {
+            for (reverse_property, values) in reverse {
+                for value in as_array(values) {
+                    if let Some(referenced) = self.generate(&value, graph, None, None, None)? {
+                        self.add_to_node(
+                            graph,
+                            Some(&referenced),
+                            Some(&reverse_property),
+                            json!({ "@id": id }),
+                            true,
+                        );
+                    }
+                }
+            }
+        }
+        if let Some(named_graph) = element.remove("@graph") {
+            self.graphs.entry(id.clone()).or_default();
+            self.generate(&named_graph, &id, None, None, None)?;
+        }
+        let mut properties: Vec<String> = element.keys().cloned().collect();
+        properties.sort();
+        for property in properties {
+            let value = &element[&property];
+            let property = if property.starts_with("_:") {
+                self.issuer.issue(&property)
+            } else {
+                property
+            };
+            self.node(graph, &id)
+                .entry(property.clone())
+                .or_insert_with(|| Value::Array(vec![]));
+            self.generate(value, graph, Some(&id), Some(&property), None)?;
+        }
+        Ok(Some(id))
+    }
+
+    fn node(&mut self, graph: &str, id: &str) -> &mut Map<String, Value> {
+        self.graphs
+            .entry(graph.to_string())
+            .or_default()
+            .entry(id.to_string())
+            .or_insert_with(|| {
+                let mut node = Map::new();
+                node.insert("@id".to_string(), Value::String(id.to_string()));
+                node
+            })
+    }
+
+    fn add_to_node(
+        &mut self,
+        graph: &str,
+        subject: Option<&str>,
+        property: Option<&str>,
+        value: Value,
+        unique: bool,
+    ) {
+        let (Some(subject), Some(property)) = (subject, property) else {
+            return;
+        };
+        let values = self
+            .node(graph, subject)
+            .entry(property.to_string())
+            .or_insert_with(|| Value::Array(vec![]));
+        if let Value::Array(values) = values {
+            if !unique || !values.contains(&value) {
+                values.push(value);
+            }
+        }
+    }
+
+    /// Adds quads for the RDF collection representing `list`, and returns its head.
+    fn list_to_rdf(
+        &mut self,
+        list: &Value,
+        graph: Option<&Term>,
+        quads: &mut Vec<Quad>,
+    ) -> Result<Term, Error> {
+        let items = list
+            .as_array()
+            .ok_or_else(|| invalid_json_ld("Invalid list object"))?;
+        let nodes: Vec<Term> = items
+            .iter()
+            .map(|_| Term::BlankNode(self.issuer.issue_new()))
+            .collect();
+        for (i, item) in items.iter().enumerate() {
+            if is_list_object(item) {
+                return Err(invalid_json_ld("List of lists"));
+            }
+            if let Some(object) = object_to_rdf(item) {
+                quads.push(Quad {
+                    subject: nodes[i].clone(),
+                    predicate: Term::Iri(RDF_FIRST.to_string()),
+                    object,
+                    graph: graph.cloned(),
+                });
+            }
+            let rest = nodes
+                .get(i + 1)
+                .cloned()
+                .unwrap_or_else(|| Term::Iri(RDF_NIL.to_string()));
+            quads.push(Quad {
+                subject: nodes[i].clone(),
+                predicate: Term::Iri(RDF_REST.to_string()),
+                object: rest,
+                graph: graph.cloned(),
+            });
+        }
+        Ok(nodes
+            .first()
+            .cloned()
+            .unwrap_or_else(|| Term::Iri(RDF_NIL.to_string())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_double() {
+        assert_eq!(canonical_double(5.3), "5.3E0");
+        assert_eq!(canonical_double(1.0), "1.0E0");
+        assert_eq!(canonical_double(-0.025), "-2.5E-2");
+        assert_eq!(canonical_double(1.5e21), "1.5E21");
+    }
+}
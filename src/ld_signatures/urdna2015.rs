@@ -0,0 +1,502 @@
+//! RDF dataset canonicalization with the URDNA2015 algorithm, serialized as canonical N-Quads
+//!
+//! <https://www.w3.org/TR/rdf-canon/>
+
+use crate::{
+    error::Error,
+    ld_signatures::{
+        invalid_json_ld,
+        rdf::{IdentifierIssuer, Quad, Term, XSD_STRING},
+    },
+};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+
+/// Limits the work for graphs with many indistinguishable blank nodes, where the algorithm has
+/// exponential complexity. Each call of the hash n-degree quads algorithm and each permutation
+/// which it tries counts as one step.
+const MAX_STEPS: usize = 4096;
+
+/// Returns the canonical N-Quads serialization of `quads`, with sorted lines.
+pub(crate) fn canonicalize(quads: &[Quad]) -> Result<String, Error> {
+    let mut state = CanonicalizationState {
+        blank_node_quads: HashMap::new(),
+        canonical_issuer: IdentifierIssuer::new("_:c14n"),
+        steps: 0,
+    };
+    for quad in quads {
+        for term in [&quad.subject, &quad.object]
+            .into_iter()
+            .chain(quad.graph.as_ref())
+        {
+            if let Term::BlankNode(id) = term {
+                let entry = state.blank_node_quads.entry(id.clone()).or_default();
+                if !entry.contains(quad) {
+                    entry.push(quad.clone());
+                }
+            }
+        }
+    }
+
+    let mut hash_to_blank_nodes: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut blank_nodes: Vec<&String> = state.blank_node_quads.keys().collect();
+    blank_nodes.sort();
+    for blank_node in blank_nodes {
+        hash_to_blank_nodes
+            .entry(state.hash_first_degree(blank_node))
+            .or_default()
+            .push(blank_node.clone());
+    }
+
+    let mut non_unique = vec![];
+    for (_, blank_nodes) in hash_to_blank_nodes {
+        match blank_nodes.as_slice() {
+            [unique] => {
+                state.canonical_issuer.issue(unique);
+            }
+            _ => non_unique.push(blank_nodes),
+        }
+    }
+    for blank_nodes in non_unique {
+        let mut hash_paths = vec![];
+        for blank_node in blank_nodes {
+            if state.canonical_issuer.get(&blank_node).is_some() {
+                continue;
+            }
+            let mut issuer = IdentifierIssuer::new("_:b");
+            issuer.issue(&blank_node);
+            hash_paths.push(state.hash_n_degree(&blank_node, issuer)?);
+        }
+        hash_paths.sort_by(|a, b| a.0.cmp(&b.0));
+        for (_, issuer) in hash_paths {
+            for existing in issuer.issued_order() {
+                state.canonical_issuer.issue(existing);
+            }
+        }
+    }
+
+    let relabel = |term: &Term| match term {
+        Term::BlankNode(id) => Term::BlankNode(
+            state
+                .canonical_issuer
+                .get(id)
+                .cloned()
+                .unwrap_or_else(|| id.clone()),
+        ),
+        t => t.clone(),
+    };
+    let mut lines: Vec<String> = quads
+        .iter()
+        .map(|quad| {
+            serialize_quad(&Quad {
+                subject: relabel(&quad.subject),
+                predicate: quad.predicate.clone(),
+                object: relabel(&quad.object),
+                graph: quad.graph.as_ref().map(relabel),
+            })
+        })
+        .collect();
+    lines.sort();
+    lines.dedup();
+    Ok(lines.concat())
+}
+
+struct CanonicalizationState {
+    blank_node_quads: HashMap<String, Vec<Quad>>,
+    canonical_issuer: IdentifierIssuer,
+    steps: usize,
+}
+
+impl CanonicalizationState {
+    /// Counts `steps` towards [MAX_STEPS], and fails if the budget is exceeded
+    fn spend(&mut self, steps: usize) -> Result<(), Error> {
+        self.steps = self.steps.saturating_add(steps);
+        if self.steps > MAX_STEPS {
+            return Err(invalid_json_ld("Document is too complex to canonicalize"));
+        }
+        Ok(())
+    }
+
+    fn hash_first_degree(&self, reference: &str) -> String {
+        let replace = |term: &Term| match term {
+            Term::BlankNode(id) if id == reference => Term::BlankNode("_:a".to_string()),
+            Term::BlankNode(_) => Term::BlankNode("_:z".to_string()),
+            t => t.clone(),
+        };
+        let mut nquads: Vec<String> = self.blank_node_quads[reference]
+            .iter()
+            .map(|quad| {
+                serialize_quad(&Quad {
+                    subject: replace(&quad.subject),
+                    predicate: quad.predicate.clone(),
+                    object: replace(&quad.object),
+                    graph: quad.graph.as_ref().map(replace),
+                })
+            })
+            .collect();
+        nquads.sort();
+        sha256_hex(&nquads.concat())
+    }
+
+    fn hash_related_blank_node(
+        &self,
+        related: &str,
+        quad: &Quad,
+        issuer: &IdentifierIssuer,
+        position: &str,
+    ) -> String {
+        let identifier = self
+            .canonical_issuer
+            .get(related)
+            .or_else(|| issuer.get(related))
+            .cloned()
+            .unwrap_or_else(|| self.hash_first_degree(related));
+        let mut input = position.to_string();
+        if position != "g" {
+            input.push_str(&serialize_term(&quad.predicate));
+        }
+        input.push_str(&identifier);
+        sha256_hex(&input)
+    }
+
+    fn hash_n_degree(
+        &mut self,
+        identifier: &str,
+        mut issuer: IdentifierIssuer,
+    ) -> Result<(String, IdentifierIssuer), Error> {
+        self.spend(1)?;
+
+        let mut hash_to_related: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for quad in &self.blank_node_quads[identifier] {
+            let components = [(&quad.subject, "s"), (&quad.object, "o")]
+                .into_iter()
+                .chain(quad.graph.as_ref().map(|g| (g, "g")));
+            for (term, position) in components {
+                if let Term::BlankNode(related) = term {
+                    if related != identifier {
+                        let hash = self.hash_related_blank_node(related, quad, &issuer, position);
+                        hash_to_related
+                            .entry(hash)
+                            .or_default()
+                            .push(related.clone());
+                    }
+                }
+            }
+        }
+
+        let mut data_to_hash = String::new();
+        for (related_hash, blank_nodes) in hash_to_related {
+            data_to_hash.push_str(&related_hash);
+            let mut chosen_path = String::new();
+            let mut chosen_issuer = None;
+            let permutations = Permutations::new(&blank_nodes);
+            // Fail before trying any permutation if there are too many
+            self.spend(permutations.count_remaining())?;
+            'permutations: for permutation in permutations {
+                let mut issuer_copy = issuer.clone();
+                let mut path = String::new();
+                let mut recursion_list = vec![];
+                for related in permutation {
+                    if let Some(canonical) = self.canonical_issuer.get(related) {
+                        path.push_str(canonical);
+                    } else {
+                        if issuer_copy.get(related).is_none() {
+                            recursion_list.push(related);
+                        }
+                        path.push_str(&issuer_copy.issue(related));
+                    }
+                    if !chosen_path.is_empty()
+                        && path.len() >= chosen_path.len()
+                        && path > chosen_path
+                    {
+                        continue 'permutations;
+                    }
+                }
+                for related in recursion_list {
+                    let (hash, result_issuer) = self.hash_n_degree(related, issuer_copy.clone())?;
+                    path.push_str(&issuer_copy.issue(related));
+                    path.push('<');
+                    path.push_str(&hash);
+                    path.push('>');
+                    issuer_copy = result_issuer;
+                    if !chosen_path.is_empty()
+                        && path.len() >= chosen_path.len()
+                        && path > chosen_path
+                    {
+                        continue 'permutations;
+                    }
+                }
+                if chosen_path.is_empty() || path < chosen_path {
+                    chosen_path = path;
+                    chosen_issuer = Some(issuer_copy);
+                }
+            }
+            data_to_hash.push_str(&chosen_path);
+            if let Some(chosen_issuer) = chosen_issuer {
+                issuer = chosen_issuer;
+            }
+        }
+        Ok((sha256_hex(&data_to_hash), issuer))
+    }
+}
+
+/// Iterator over all orderings of a list, generated one at a time with Heap's algorithm.
+/// Duplicate entries are removed first, they can't result in a different path.
+struct Permutations<'a> {
+    items: Vec<&'a String>,
+    /// Loop counters of the algorithm, one per position
+    counters: Vec<usize>,
+    /// Position which is swapped next, `None` before the first permutation is returned
+    position: Option<usize>,
+}
+
+impl<'a> Permutations<'a> {
+    fn new(items: &'a [String]) -> Self {
+        let mut unique: Vec<&String> = vec![];
+        for item in items {
+            if !unique.contains(&item) {
+                unique.push(item);
+            }
+        }
+        Permutations {
+            counters: vec![0; unique.len()],
+            items: unique,
+            position: None,
+        }
+    }
+
+    /// Number of permutations, saturating at `usize::MAX`. Only valid before iteration starts.
+    fn count_remaining(&self) -> usize {
+        (1..=self.items.len()).fold(1usize, |count, n| count.saturating_mul(n))
+    }
+}
+
+impl<'a> Iterator for Permutations<'a> {
+    type Item = Vec<&'a String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Some(mut i) = self.position else {
+            self.position = Some(1);
+            return Some(self.items.clone());
+        };
+        while i < self.items.len() {
+            if self.counters[i] < i {
+                let swap = if i % 2 == 0 { 0 } else { self.counters[i] };
+                self.items.swap(swap, i);
+                self.counters[i] += 1;
+                self.position = Some(1);
+                return Some(self.items.clone());
+            }
+            self.counters[i] = 0;
+            i += 1;
+        }
+        self.position = Some(i);
+        None
+    }
+}
+
+fn sha256_hex(input: &str) -> String {
+    hex(&Sha256::digest(input.as_bytes()))
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn serialize_quad(quad: &Quad) -> String {
+    let mut line = format!(
+        "{} {} {} ",
+        serialize_term(&quad.subject),
+        serialize_term(&quad.predicate),
+        serialize_term(&quad.object)
+    );
+    if let Some(graph) = &quad.graph {
+        line.push_str(&serialize_term(graph));
+        line.push(' ');
+    }
+    line.push_str(".\n");
+    line
+}
+
+fn serialize_term(term: &Term) -> String {
+    match term {
+        Term::Iri(iri) => format!("<{}>", iri),
+        Term::BlankNode(id) => id.clone(),
+        Term::Literal {
+            value,
+            datatype,
+            language,
+        } => {
+            let mut literal = format!("\"{}\"", escape_literal(value));
+            if let Some(language) = language {
+                literal.push('@');
+                literal.push_str(language);
+            } else if datatype != XSD_STRING {
+                literal.push_str(&format!("^^<{}>", datatype));
+            }
+            literal
+        }
+    }
+}
+
+/// Escapes literal values like the N-Quads serialization of URDNA2015, which only escapes
+/// backslash, double quote, line feed and carriage return. Other characters are kept as they
+/// are, escaping them would change the hash.
+fn escape_literal(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn iri(i: &str) -> Term {
+        Term::Iri(format!("http://example.com/{}", i))
+    }
+
+    fn blank(b: &str) -> Term {
+        Term::BlankNode(format!("_:{}", b))
+    }
+
+    fn quad(subject: Term, predicate: &str, object: Term) -> Quad {
+        Quad {
+            subject,
+            predicate: iri(predicate),
+            object,
+            graph: None,
+        }
+    }
+
+    #[test]
+    fn test_canonicalize_without_blank_nodes() {
+        let quads = vec![
+            quad(
+                iri("b"),
+                "name",
+                Term::Literal {
+                    value: "line\n\"quoted\"".to_string(),
+                    datatype: XSD_STRING.to_string(),
+                    language: None,
+                },
+            ),
+            quad(iri("a"), "knows", iri("b")),
+        ];
+        assert_eq!(
+            canonicalize(&quads).unwrap(),
+            "<http://example.com/a> <http://example.com/knows> <http://example.com/b> .\n\
+             <http://example.com/b> <http://example.com/name> \"line\\n\\\"quoted\\\"\" .\n"
+        );
+    }
+
+    #[test]
+    fn test_canonicalize_independent_of_labels() {
+        // Two blank nodes which can only be distinguished by hashing their neighbours
+        let dataset = |x: &str, y: &str, z: &str| {
+            vec![
+                quad(blank(x), "p", blank(y)),
+                quad(blank(y), "p", blank(z)),
+                quad(blank(z), "p", blank(x)),
+                quad(iri("s"), "q", blank(y)),
+            ]
+        };
+        let canonical = canonicalize(&dataset("x", "y", "z")).unwrap();
+        let mut relabeled = dataset("b1", "b0", "foo");
+        relabeled.reverse();
+        assert_eq!(canonical, canonicalize(&relabeled).unwrap());
+        assert!(canonical.contains("_:c14n0"));
+        assert!(!canonical.contains("_:x"));
+    }
+
+    #[test]
+    fn test_canonicalize_spec_examples() {
+        // Examples for unique and shared hashes from the W3C RDF Dataset Canonicalization
+        // specification, https://www.w3.org/TR/rdf-canon/
+        let unique = vec![
+            quad(iri("#p"), "#q", blank("e0")),
+            quad(iri("#p"), "#r", blank("e1")),
+            quad(blank("e0"), "#s", iri("#u")),
+            quad(blank("e1"), "#t", iri("#u")),
+        ];
+        assert_eq!(
+            canonicalize(&unique).unwrap(),
+            "<http://example.com/#p> <http://example.com/#q> _:c14n0 .\n\
+             <http://example.com/#p> <http://example.com/#r> _:c14n1 .\n\
+             _:c14n0 <http://example.com/#s> <http://example.com/#u> .\n\
+             _:c14n1 <http://example.com/#t> <http://example.com/#u> .\n"
+        );
+
+        let shared = vec![
+            quad(iri("#p"), "#q", blank("e0")),
+            quad(iri("#p"), "#q", blank("e1")),
+            quad(blank("e0"), "#p", blank("e2")),
+            quad(blank("e1"), "#p", blank("e3")),
+            quad(blank("e2"), "#r", blank("e3")),
+        ];
+        assert_eq!(
+            canonicalize(&shared).unwrap(),
+            "<http://example.com/#p> <http://example.com/#q> _:c14n2 .\n\
+             <http://example.com/#p> <http://example.com/#q> _:c14n3 .\n\
+             _:c14n0 <http://example.com/#r> _:c14n1 .\n\
+             _:c14n2 <http://example.com/#p> _:c14n1 .\n\
+             _:c14n3 <http://example.com/#p> _:c14n0 .\n"
+        );
+    }
+
+    #[test]
+    fn test_canonicalize_too_complex() {
+        // Two blank nodes which are both linked to the same indistinguishable blank nodes
+        let quads: Vec<_> = ["x", "y"]
+            .iter()
+            .flat_map(|hub| (0..8).map(move |i| quad(blank(hub), "p", blank(&i.to_string()))))
+            .collect();
+        assert!(canonicalize(&quads).is_err());
+    }
+
+    #[test]
+    fn test_canonicalize_too_many_permutations() {
+        // Two hubs with 12 indistinguishable children each, like `"tag": [{}, {}, ...]`. This
+        // must fail without trying all 12! orderings.
+        let quads: Vec<_> = ["x", "y"]
+            .iter()
+            .flat_map(|hub| {
+                (0..12).map(move |i| quad(blank(hub), "tag", blank(&format!("{hub}{i}"))))
+            })
+            .collect();
+        let start = std::time::Instant::now();
+        assert!(canonicalize(&quads).is_err());
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_permutations() {
+        let items: Vec<String> = ["a", "b", "c", "d", "a"].map(String::from).to_vec();
+        let permutations = Permutations::new(&items);
+        assert_eq!(permutations.count_remaining(), 24);
+        let mut all: Vec<String> = permutations
+            .map(|p| p.into_iter().map(String::as_str).collect())
+            .collect();
+        assert_eq!(all.len(), 24);
+        all.sort();
+        all.dedup();
+        assert_eq!(all.len(), 24);
+        assert_eq!(Permutations::new(&[]).count(), 1);
+    }
+
+    #[test]
+    fn test_escape_literal() {
+        assert_eq!(
+            escape_literal("a\\b\"c\nd\re\tf\u{8}g\u{c}h\u{1}"),
+            "a\\\\b\\\"c\\nd\\re\tf\u{8}g\u{c}h\u{1}"
+        );
+    }
+}
@@ -16,11 +16,21 @@ pub mod actix_web;
 #[cfg(feature = "axum")]
 pub mod axum;
 pub mod config;
+pub mod crypto;
 pub mod error;
 pub mod fetch;
 pub mod http_signatures;
+pub mod inbox;
+pub mod inbox_policy;
+pub mod incoming_queue;
+#[cfg(feature = "integrity-proofs")]
+pub mod integrity_proofs;
+#[cfg(feature = "ld-signatures")]
+pub mod ld_signatures;
+pub mod object_enum;
 pub mod protocol;
 pub(crate) mod reqwest_shim;
+pub mod shared_inbox;
 pub mod traits;
 
 pub use activitystreams_kinds as kinds;
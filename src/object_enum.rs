@@ -0,0 +1,396 @@
+//! Enums which combine multiple object or actor types, see [object_enum](crate::object_enum!)
+//! and [actor_enum](crate::actor_enum!)
+
+use serde_json::Value;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use async_trait::async_trait;
+    pub use chrono::NaiveDateTime;
+    pub use serde;
+    pub use serde_json;
+    pub use url::Url;
+}
+
+/// Returns true if the `type` of `json` is one of `names`. The type may also be an array, as is
+/// allowed by JSON-LD.
+#[doc(hidden)]
+pub fn has_type(json: &Value, names: &[&str]) -> bool {
+    match json.get("type") {
+        Some(Value::String(kind)) => names.contains(&kind.as_str()),
+        Some(Value::Array(kinds)) => kinds
+            .iter()
+            .filter_map(Value::as_str)
+            .any(|kind| names.contains(&kind)),
+        _ => false,
+    }
+}
+
+/// Defines an enum of multiple [Object](crate::traits::Object) types, and implements `Object`
+/// for it.
+///
+/// This is useful to fetch an url whose type is not known beforehand, for example from a search
+/// field. Each variant is annotated with the values of the JSON `type` field which it accepts.
+/// Fetched JSON is parsed with the variant that matches its `type`, instead of the first variant
+/// which happens to parse without errors. All wrapped types must have the same `DataType`, and
+/// their errors must be convertible into the error of the first type.
+///
+/// The macro also defines an enum with the given name for the JSON representation, which is used
+/// as [Object::Kind](crate::traits::Object::Kind).
+///
+/// ```
+/// # use activitypub_federation::traits::tests::{DbPost, DbUser};
+/// activitypub_federation::object_enum! {
+///     /// Objects which can be found through the search field
+///     pub enum SearchableDbObjects: SearchableObjects {
+///         User(DbUser) = "Person",
+///         Post(DbPost) = "Note" | "Article",
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! object_enum {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident: $kind:ident {
+            $(#[$first_meta:meta])*
+            $first_variant:ident($first_ty:ty) = $($first_type_name:literal)|+
+            $(,
+                $(#[$variant_meta:meta])*
+                $variant:ident($ty:ty) = $($type_name:literal)|+
+            )* $(,)?
+        }
+    ) => {
+        $crate::object_enum!(@impl $first_ty;
+            $(#[$meta])*
+            $vis enum $name: $kind {
+                $(#[$first_meta])* $first_variant($first_ty) = $($first_type_name)|+,
+                $($(#[$variant_meta])* $variant($ty) = $($type_name)|+,)*
+            }
+        );
+    };
+    (@impl $first_ty:ty;
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident: $kind:ident {
+            $($(#[$variant_meta:meta])* $variant:ident($ty:ty) = $($type_name:literal)|+,)+
+        }
+    ) => {
+        $(#[$meta])*
+        $vis enum $name {
+            $($(#[$variant_meta])* $variant($ty),)+
+        }
+
+        #[doc = concat!("JSON representation of [", stringify!($name), "]")]
+        #[allow(missing_docs, clippy::large_enum_variant)]
+        $vis enum $kind {
+            $($variant(<$ty as $crate::traits::Object>::Kind),)+
+        }
+
+        impl $crate::object_enum::__private::serde::Serialize for $kind {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: $crate::object_enum::__private::serde::Serializer,
+            {
+                match self {
+                    $($kind::$variant(json) => $crate::object_enum::__private::serde::Serialize::serialize(json, serializer),)+
+                }
+            }
+        }
+
+        impl<'de> $crate::object_enum::__private::serde::Deserialize<'de> for $kind {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: $crate::object_enum::__private::serde::Deserializer<'de>,
+            {
+                use $crate::object_enum::__private::{serde::de::Error as _, serde_json};
+                let json = serde_json::Value::deserialize(deserializer)?;
+                $(
+                    if $crate::object_enum::has_type(&json, &[$($type_name),+]) {
+                        return serde_json::from_value(json)
+                            .map($kind::$variant)
+                            .map_err(D::Error::custom);
+                    }
+                )+
+                Err(D::Error::custom(format!(
+                    "Unexpected type {} for {}",
+                    json.get("type").unwrap_or(&serde_json::Value::Null),
+                    stringify!($name)
+                )))
+            }
+        }
+
+        #[$crate::object_enum::__private::async_trait]
+        impl $crate::traits::Object for $name {
+            type DataType = <$first_ty as $crate::traits::Object>::DataType;
+            type Kind = $kind;
+            type Error = <$first_ty as $crate::traits::Object>::Error;
+
+            fn last_refreshed_at(&self) -> Option<$crate::object_enum::__private::NaiveDateTime> {
+                match self {
+                    $($name::$variant(object) => $crate::traits::Object::last_refreshed_at(object),)+
+                }
+            }
+
+            fn refetch_interval(&self) -> Option<std::time::Duration> {
+                match self {
+                    $($name::$variant(object) => $crate::traits::Object::refetch_interval(object),)+
+                }
+            }
+
+            fn cache_validators(&self) -> Option<$crate::fetch::CacheValidators> {
+                match self {
+                    $($name::$variant(object) => $crate::traits::Object::cache_validators(object),)+
+                }
+            }
+
+            async fn set_cache_validators(
+                self,
+                validators: $crate::fetch::CacheValidators,
+                data: &$crate::config::Data<Self::DataType>,
+            ) -> Result<Self, Self::Error> {
+                match self {
+                    $($name::$variant(object) => Ok($name::$variant(
+                        $crate::traits::Object::set_cache_validators(object, validators, data).await?,
+                    )),)+
+                }
+            }
+
+            async fn not_modified(
+                self,
+                data: &$crate::config::Data<Self::DataType>,
+            ) -> Result<Self, Self::Error> {
+                match self {
+                    $($name::$variant(object) => Ok($name::$variant(
+                        $crate::traits::Object::not_modified(object, data).await?,
+                    )),)+
+                }
+            }
+
+            fn cache_copy(&self) -> Option<Self> {
+                match self {
+                    $($name::$variant(object) => {
+                        $crate::traits::Object::cache_copy(object).map($name::$variant)
+                    })+
+                }
+            }
+
+            async fn read_from_id(
+                object_id: $crate::object_enum::__private::Url,
+                data: &$crate::config::Data<Self::DataType>,
+            ) -> Result<Option<Self>, Self::Error> {
+                $(
+                    if let Some(object) =
+                        <$ty as $crate::traits::Object>::read_from_id(object_id.clone(), data).await?
+                    {
+                        return Ok(Some($name::$variant(object)));
+                    }
+                )+
+                Ok(None)
+            }
+
+            async fn delete(self, data: &$crate::config::Data<Self::DataType>) -> Result<(), Self::Error> {
+                match self {
+                    $($name::$variant(object) => Ok($crate::traits::Object::delete(object, data).await?),)+
+                }
+            }
+
+            async fn into_json(
+                self,
+                data: &$crate::config::Data<Self::DataType>,
+            ) -> Result<Self::Kind, Self::Error> {
+                match self {
+                    $($name::$variant(object) => Ok($kind::$variant($crate::traits::Object::into_json(object, data).await?)),)+
+                }
+            }
+
+            async fn verify(
+                json: &Self::Kind,
+                expected_domain: &$crate::object_enum::__private::Url,
+                data: &$crate::config::Data<Self::DataType>,
+            ) -> Result<(), Self::Error> {
+                match json {
+                    $($kind::$variant(json) => {
+                        Ok(<$ty as $crate::traits::Object>::verify(json, expected_domain, data).await?)
+                    })+
+                }
+            }
+
+            async fn from_json(
+                json: Self::Kind,
+                data: &$crate::config::Data<Self::DataType>,
+            ) -> Result<Self, Self::Error> {
+                match json {
+                    $($kind::$variant(json) => Ok($name::$variant(
+                        <$ty as $crate::traits::Object>::from_json(json, data).await?,
+                    )),)+
+                }
+            }
+        }
+    };
+}
+
+/// Defines an enum of multiple [Actor](crate::traits::Actor) types, and implements `Object` and
+/// `Actor` for it.
+///
+/// This works like [object_enum](crate::object_enum!), and additionally delegates all `Actor`
+/// methods to the wrapped actor. The enum can be used as actor type for
+/// [receive_activity](crate::inbox::receive_activity), so that activities from users, groups
+/// and bots are accepted in the same inbox. The remote actor is then parsed according to its
+/// `type`, and the activity signature is verified with the keys of that actor.
+///
+/// ```
+/// # use activitypub_federation::traits::tests::DbUser;
+/// # type DbGroup = DbUser;
+/// # type DbBot = DbUser;
+/// activitypub_federation::actor_enum! {
+///     /// All actors which can send activities to the inbox
+///     pub enum AnyActor: AnyActorJson {
+///         User(DbUser) = "Person",
+///         Group(DbGroup) = "Group",
+///         Bot(DbBot) = "Service" | "Application",
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! actor_enum {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident: $kind:ident {
+            $(
+                $(#[$variant_meta:meta])*
+                $variant:ident($ty:ty) = $($type_name:literal)|+
+            ),+ $(,)?
+        }
+    ) => {
+        $crate::object_enum! {
+            $(#[$meta])*
+            $vis enum $name: $kind {
+                $($(#[$variant_meta])* $variant($ty) = $($type_name)|+,)+
+            }
+        }
+
+        impl $crate::traits::Actor for $name {
+            fn id(&self) -> $crate::object_enum::__private::Url {
+                match self {
+                    $($name::$variant(actor) => $crate::traits::Actor::id(actor),)+
+                }
+            }
+
+            fn public_key_pem(&self) -> &str {
+                match self {
+                    $($name::$variant(actor) => $crate::traits::Actor::public_key_pem(actor),)+
+                }
+            }
+
+            fn private_key_pem(&self) -> Option<String> {
+                match self {
+                    $($name::$variant(actor) => $crate::traits::Actor::private_key_pem(actor),)+
+                }
+            }
+
+            fn private_key_id(&self) -> String {
+                match self {
+                    $($name::$variant(actor) => $crate::traits::Actor::private_key_id(actor),)+
+                }
+            }
+
+            fn inbox(&self) -> $crate::object_enum::__private::Url {
+                match self {
+                    $($name::$variant(actor) => $crate::traits::Actor::inbox(actor),)+
+                }
+            }
+
+            fn public_key(&self) -> $crate::protocol::public_key::PublicKey {
+                match self {
+                    $($name::$variant(actor) => $crate::traits::Actor::public_key(actor),)+
+                }
+            }
+
+            fn public_keys(&self) -> Vec<$crate::protocol::public_key::PublicKey> {
+                match self {
+                    $($name::$variant(actor) => $crate::traits::Actor::public_keys(actor),)+
+                }
+            }
+
+            fn assertion_methods(&self) -> Vec<$crate::protocol::multikey::Multikey> {
+                match self {
+                    $($name::$variant(actor) => $crate::traits::Actor::assertion_methods(actor),)+
+                }
+            }
+
+            fn shared_inbox(&self) -> Option<$crate::object_enum::__private::Url> {
+                match self {
+                    $($name::$variant(actor) => $crate::traits::Actor::shared_inbox(actor),)+
+                }
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::FederationConfig,
+        traits::{
+            tests::{DbConnection, DbPost, DbUser, Note, DB_USER},
+            Actor,
+            Object,
+        },
+    };
+    use serde_json::json;
+
+    // `Note` parses from any JSON object, so it would also match actors if the enum was
+    // untagged
+    crate::object_enum! {
+        #[allow(clippy::large_enum_variant)]
+        enum SearchableDbObjects: SearchableObjects {
+            Post(DbPost) = "Note",
+            User(DbUser) = "Person",
+        }
+    }
+
+    crate::actor_enum! {
+        enum AnyActor: AnyActorJson {
+            User(DbUser) = "Person" | "Service",
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_object_enum() {
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(DbConnection)
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+        let person = serde_json::to_value(DB_USER.clone().into_json(&data).await.unwrap()).unwrap();
+
+        let parsed: SearchableObjects = serde_json::from_value(person.clone()).unwrap();
+        assert!(matches!(parsed, SearchableObjects::User(_)));
+        let object = SearchableDbObjects::from_json(parsed, &data).await.unwrap();
+        assert!(matches!(object, SearchableDbObjects::User(_)));
+
+        let parsed: SearchableObjects = serde_json::from_value(json!({"type": "Note"})).unwrap();
+        assert!(matches!(parsed, SearchableObjects::Post(Note {})));
+        let parsed = serde_json::from_value::<SearchableObjects>(json!({"type": ["Note"]}));
+        assert!(matches!(parsed, Ok(SearchableObjects::Post(_))));
+        let parsed = serde_json::from_value::<SearchableObjects>(json!({"type": "Group"}));
+        assert!(parsed.is_err());
+
+        // Only variants whose type can be cached are copied
+        let post = SearchableDbObjects::Post(DbPost {});
+        assert!(matches!(
+            post.cache_copy(),
+            Some(SearchableDbObjects::Post(_))
+        ));
+        assert!(object.cache_copy().is_none());
+
+        let actor = AnyActor::User(DB_USER.clone());
+        assert_eq!(actor.id(), DB_USER.id());
+        assert_eq!(actor.public_key_pem(), DB_USER.public_key_pem());
+        assert_eq!(actor.assertion_methods(), DB_USER.assertion_methods());
+        // Serializes the same way as the wrapped type
+        let json = serde_json::to_value(actor.into_json(&data).await.unwrap()).unwrap();
+        assert_eq!(json, person);
+    }
+}
@@ -2,6 +2,7 @@
 
 pub mod context;
 pub mod helpers;
+pub mod multikey;
 pub mod public_key;
 pub mod values;
 pub mod verification;
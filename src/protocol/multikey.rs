@@ -0,0 +1,46 @@
+//! Struct which is used to federate actor keys for object integrity proofs
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// Ed25519 public key of an actor which is used for object integrity proofs
+/// ([FEP-8b32](https://codeberg.org/fediverse/fep/src/branch/main/fep/8b32/fep-8b32.md)).
+///
+/// This needs to be federated in the `assertionMethod` field of actors:
+///
+/// ```
+/// # use activitypub_federation::protocol::{helpers::deserialize_one_or_many, multikey::Multikey};
+/// #[derive(serde::Deserialize)]
+/// #[serde(rename_all = "camelCase")]
+/// struct Person {
+///     #[serde(deserialize_with = "deserialize_one_or_many", default)]
+///     assertion_method: Vec<Multikey>,
+/// }
+/// ```
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Multikey {
+    /// Id of this key, usually `{actor_id}#ed25519-key`
+    pub id: String,
+    /// Always `Multikey`
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// ID of the actor that this key belongs to
+    pub controller: Url,
+    /// The public key, encoded as multibase base58-btc
+    pub public_key_multibase: String,
+}
+
+impl Multikey {
+    /// Create a new [Multikey] struct for the `controller`, with key id
+    /// `{controller}#ed25519-key`. The key can be generated with
+    /// [generate_ed25519_keypair](crate::integrity_proofs::generate_ed25519_keypair).
+    pub fn new(controller: Url, public_key_multibase: String) -> Self {
+        Multikey {
+            id: format!("{}#ed25519-key", &controller),
+            kind: "Multikey".to_string(),
+            controller,
+            public_key_multibase,
+        }
+    }
+}
@@ -1,11 +1,24 @@
 //! Struct which is used to federate actor key for HTTP signatures
 
+use crate::protocol::{helpers::deserialize_one_or_many, multikey::Multikey};
 use serde::{Deserialize, Serialize};
 use url::Url;
 
 /// Public key of actors which is used for HTTP signatures.
 ///
-/// This needs to be federated in the `public_key` field of all actors.
+/// This needs to be federated in the `public_key` field of all actors. Actors with multiple keys
+/// can federate an array instead, which is received with
+/// [deserialize_one_or_many]:
+///
+/// ```
+/// # use activitypub_federation::protocol::{helpers::deserialize_one_or_many, public_key::PublicKey};
+/// #[derive(serde::Deserialize)]
+/// #[serde(rename_all = "camelCase")]
+/// struct Person {
+///     #[serde(deserialize_with = "deserialize_one_or_many")]
+///     public_key: Vec<PublicKey>,
+/// }
+/// ```
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PublicKey {
@@ -34,3 +47,61 @@ impl PublicKey {
 pub(crate) fn main_key_id(owner: &Url) -> String {
     format!("{}#main-key", &owner)
 }
+
+/// Key which verifies signatures of an actor: an RSA key from `publicKey`, or an Ed25519 key from
+/// `assertionMethod`.
+#[derive(Clone, Debug)]
+pub(crate) enum VerificationKey {
+    Pem(PublicKey),
+    Multikey(Multikey),
+}
+
+impl VerificationKey {
+    pub(crate) fn id(&self) -> &str {
+        match self {
+            VerificationKey::Pem(key) => &key.id,
+            VerificationKey::Multikey(key) => &key.id,
+        }
+    }
+
+    /// The actor which the key belongs to
+    pub(crate) fn owner(&self) -> &Url {
+        match self {
+            VerificationKey::Pem(key) => &key.owner,
+            VerificationKey::Multikey(key) => &key.controller,
+        }
+    }
+}
+
+/// Document which is returned when fetching a key id. Some platforms publish keys as separate
+/// documents, others return the actor which contains the key.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(crate) enum KeyDocument {
+    Key(PublicKey),
+    Multikey(Multikey),
+    #[serde(rename_all = "camelCase")]
+    Actor {
+        #[serde(deserialize_with = "deserialize_one_or_many", default)]
+        public_key: Vec<PublicKey>,
+        #[serde(deserialize_with = "deserialize_one_or_many", default)]
+        assertion_method: Vec<Multikey>,
+    },
+}
+
+impl KeyDocument {
+    pub(crate) fn into_keys(self) -> Vec<VerificationKey> {
+        match self {
+            KeyDocument::Key(key) => vec![VerificationKey::Pem(key)],
+            KeyDocument::Multikey(key) => vec![VerificationKey::Multikey(key)],
+            KeyDocument::Actor {
+                public_key,
+                assertion_method,
+            } => public_key
+                .into_iter()
+                .map(VerificationKey::Pem)
+                .chain(assertion_method.into_iter().map(VerificationKey::Multikey))
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! Helpers for dispatching activities which were received in a shared inbox
+//!
+//! A shared inbox receives only one copy of each activity, even if it is addressed to many local
+//! actors. [local_recipients] reads the addressing fields of the activity which is currently
+//! being received, and resolves all local actors and collections to their actors with the
+//! [Recipient] trait.
+//!
+//! ```
+//! # use activitypub_federation::config::Data;
+//! # use activitypub_federation::shared_inbox::{local_recipients, Recipient};
+//! # use activitypub_federation::traits::tests::DbConnection;
+//! // Called from ActivityHandler::receive, with the user type which implements `Recipient`
+//! async fn deliver<User>(data: &Data<DbConnection>) -> Result<(), anyhow::Error>
+//! where
+//!     User: Recipient<DataType = DbConnection, Error = anyhow::Error>,
+//! {
+//!     for user in local_recipients::<User>(data).await? {
+//!         // Add activity to the inbox of user
+//!     }
+//!     Ok(())
+//! }
+//! ```
+
+use crate::{config::Data, error::Error, traits::Actor};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use serde_json::Value;
+use std::collections::HashSet;
+use url::Url;
+
+/// Fields which are used to address an activity
+const ADDRESSING_FIELDS: [&str; 5] = ["to", "cc", "bto", "bcc", "audience"];
+
+/// Local actor which can be the recipient of an activity in the shared inbox. This is usually an
+/// enum of all local actor types, or the user type.
+#[async_trait]
+pub trait Recipient: Actor {
+    /// Read the local actor with the given id. Should return `Ok(None)` if not found.
+    ///
+    /// Defaults to [Object::read_from_id](crate::traits::Object::read_from_id).
+    async fn read_local_actor(
+        id: Url,
+        data: &Data<Self::DataType>,
+    ) -> Result<Option<Self>, Self::Error> {
+        Self::read_from_id(id, data).await
+    }
+
+    /// Read the local members of the local collection with the given id, for example the local
+    /// followers of a group. Should return `Ok(None)` if `collection` is not a collection.
+    async fn read_collection_members(
+        collection: Url,
+        data: &Data<Self::DataType>,
+    ) -> Result<Option<Vec<Self>>, Self::Error>;
+}
+
+/// Returns all local recipients of the activity which is currently being received.
+///
+/// Each local url in `to`, `cc`, `bto`, `bcc` and `audience` is resolved with
+/// [Recipient::read_collection_members] and, if it is not a collection, with
+/// [Recipient::read_local_actor]. Remote urls are ignored. Every actor is returned only once,
+/// even if it is addressed multiple times.
+///
+/// If the activity was rewritten by an [inbox policy](crate::inbox_policy), the addressing of
+/// the rewritten activity is used. This can only be called while handling an incoming activity,
+/// see [Data::received_activity].
+pub async fn local_recipients<R>(data: &Data<R::DataType>) -> Result<Vec<R>, R::Error>
+where
+    R: Recipient,
+    R::Error: From<Error>,
+{
+    let received = data
+        .received_activity()
+        .ok_or_else(|| Error::other(anyhow!("No activity is being received")))?;
+    let activity: Value = serde_json::from_slice(received.activity_json()).map_err(Error::other)?;
+
+    let mut recipients: Vec<R> = vec![];
+    let mut recipient_ids = HashSet::new();
+    for url in addressed_urls(&activity) {
+        if !data.config.is_local_url(&url) {
+            continue;
+        }
+        let actors = match R::read_collection_members(url.clone(), data).await? {
+            Some(members) => members,
+            None => R::read_local_actor(url, data).await?.into_iter().collect(),
+        };
+        for actor in actors {
+            if recipient_ids.insert(actor.id()) {
+                recipients.push(actor);
+            }
+        }
+    }
+    Ok(recipients)
+}
+
+/// Returns all urls in the addressing fields of `activity`, without duplicates
+fn addressed_urls(activity: &Value) -> Vec<Url> {
+    let mut urls = vec![];
+    let mut seen = HashSet::new();
+    for field in ADDRESSING_FIELDS {
+        let values = match activity.get(field) {
+            Some(Value::Array(values)) => values.iter().collect(),
+            Some(value) => vec![value],
+            None => vec![],
+        };
+        for value in values {
+            // Recipients may also be embedded objects
+            let url = value.as_str().or_else(|| value.get("id")?.as_str());
+            if let Some(url) = url.and_then(|u| Url::parse(u).ok()) {
+                if seen.insert(url.clone()) {
+                    urls.push(url);
+                }
+            }
+        }
+    }
+    urls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        activity_queue::RawActivity,
+        config::FederationConfig,
+        traits::tests::{DbConnection, DbUser, DB_USER},
+    };
+    use serde_json::json;
+
+    #[async_trait]
+    impl Recipient for DbUser {
+        async fn read_collection_members(
+            collection: Url,
+            _data: &Data<Self::DataType>,
+        ) -> Result<Option<Vec<Self>>, Self::Error> {
+            if collection.path().ends_with("/followers") {
+                let mut follower = DB_USER.clone();
+                follower.federation_id = "http://localhost:8002/u/bob".parse()?;
+                Ok(Some(vec![follower, DB_USER.clone()]))
+            } else {
+                Ok(None)
+            }
+        }
+    }
+
+    #[test]
+    fn test_addressed_urls() {
+        let activity = json!({
+            "to": "https://example.com/u/alice",
+            "cc": ["https://example.com/u/alice", {"id": "https://example.com/u/bob"}],
+            "audience": "https://example.com/c/main",
+        });
+        let urls: Vec<String> = addressed_urls(&activity)
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            urls,
+            vec![
+                "https://example.com/u/alice",
+                "https://example.com/u/bob",
+                "https://example.com/c/main"
+            ]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_local_recipients() {
+        let config = FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(DbConnection)
+            .debug(true)
+            .build()
+            .unwrap();
+        let activity = json!({
+            "id": "https://example.com/activity/1",
+            "to": ["https://www.w3.org/ns/activitystreams#Public", "http://localhost:8002/u/alice"],
+            "cc": ["http://localhost:8002/u/alice/followers", "https://example.com/u/carol"],
+        });
+        let raw = RawActivity::new(
+            "https://example.com/activity/1".parse().unwrap(),
+            "https://example.com/u/carol".parse().unwrap(),
+            activity.to_string().into(),
+        );
+        let data = config.to_request_data();
+        assert!(local_recipients::<DbUser>(&data).await.is_err());
+
+        let data = data.with_received_activity(raw.clone());
+        let recipients: Vec<Url> = local_recipients::<DbUser>(&data)
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| r.id())
+            .collect();
+        // Local user is returned only once, even though it is also in the followers collection
+        assert_eq!(
+            recipients,
+            vec![DB_USER.id(), "http://localhost:8002/u/bob".parse().unwrap()]
+        );
+
+        // Addressing of a rewritten activity is used
+        let rewritten = json!({
+            "id": "https://example.com/activity/1",
+            "to": ["https://www.w3.org/ns/activitystreams#Public"],
+        });
+        let data = data.with_received_activity(raw.with_rewritten(rewritten.to_string().into()));
+        assert!(local_recipients::<DbUser>(&data).await.unwrap().is_empty());
+    }
+}
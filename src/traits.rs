@@ -1,10 +1,17 @@
 //! Traits which need to be implemented for federated data types
 
-use crate::{config::Data, protocol::public_key::PublicKey};
+use crate::{
+    config::Data,
+    fetch::CacheValidators,
+    protocol::{
+        multikey::Multikey,
+        public_key::{main_key_id, PublicKey},
+    },
+};
 use async_trait::async_trait;
 use chrono::NaiveDateTime;
 use serde::Deserialize;
-use std::{fmt::Debug, ops::Deref};
+use std::{fmt::Debug, ops::Deref, time::Duration};
 use url::Url;
 
 /// Helper for converting between database structs and federated protocol structs.
@@ -109,12 +116,69 @@ pub trait Object: Sized {
     /// update mechanism prescribed. It is possible to send `Update/Person` activities for profile
     /// changes, but not all implementations do this, so `last_refreshed_at` is still necessary.
     ///
-    /// The object is refetched if `last_refreshed_at` value is more than 24 hours ago. In debug
-    /// mode this is reduced to 20 seconds.
+    /// The object is refetched if `last_refreshed_at` value is longer ago than
+    /// [Object::refetch_interval].
     fn last_refreshed_at(&self) -> Option<NaiveDateTime> {
         None
     }
 
+    /// Time after which the object is refetched from its original instance, see
+    /// [Object::last_refreshed_at].
+    ///
+    /// Returning `None` uses the interval from
+    /// [FederationConfigBuilder::refetch_interval](crate::config::FederationConfigBuilder::refetch_interval),
+    /// which is 24 hours by default. Override this to refetch some types of objects more or less
+    /// often, for example posts which are rarely edited.
+    fn refetch_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Validators from the HTTP response when this object was last fetched, as passed to
+    /// [Object::set_cache_validators].
+    ///
+    /// If this returns `Some`, refetches are sent as conditional requests. When the remote server
+    /// responds with `304 Not Modified`, [Object::not_modified] is called instead of parsing the
+    /// object again. Implement [Object::not_modified] together with this method, otherwise
+    /// [Object::last_refreshed_at] is never updated and a conditional request is sent on every
+    /// dereference once the refetch interval has passed.
+    fn cache_validators(&self) -> Option<CacheValidators> {
+        None
+    }
+
+    /// Called after the object was fetched and converted with [Object::from_json], with the `ETag`
+    /// and `Last-Modified` headers of the response. Store the validators together with the object
+    /// in the local database, and return them from [Object::cache_validators].
+    ///
+    /// The validators are empty if the response contained neither header. In that case previously
+    /// stored validators must be removed, as they no longer match the object.
+    async fn set_cache_validators(
+        self,
+        _validators: CacheValidators,
+        _data: &Data<Self::DataType>,
+    ) -> Result<Self, Self::Error> {
+        Ok(self)
+    }
+
+    /// Called when a refetch of the object returned `304 Not Modified`. This should update
+    /// [Object::last_refreshed_at] in the local database, so that the object is not refetched
+    /// again before the refetch interval has passed.
+    ///
+    /// The default implementation returns the object unchanged, and doesn't update
+    /// [Object::last_refreshed_at].
+    async fn not_modified(self, _data: &Data<Self::DataType>) -> Result<Self, Self::Error> {
+        Ok(self)
+    }
+
+    /// Copy of the object which is kept in the [ObjectCache](crate::fetch::cache::ObjectCache),
+    /// if one is configured.
+    ///
+    /// Objects are only cached if this returns `Some`, usually `Some(self.clone())`. Cached
+    /// objects are returned by [ObjectId::dereference](crate::fetch::object_id::ObjectId::dereference)
+    /// without reading them from the database.
+    fn cache_copy(&self) -> Option<Self> {
+        None
+    }
+
     /// Try to read the object with given `id` from local database.
     ///
     /// Should return `Ok(None)` if not found.
@@ -245,6 +309,15 @@ pub trait Actor: Object + Send + 'static {
     /// actor keypair.
     fn private_key_pem(&self) -> Option<String>;
 
+    /// Id of the key returned by [Actor::private_key_pem], which is sent as `keyId` in HTTP
+    /// signatures.
+    ///
+    /// Defaults to `{actor_id}#main-key`. Override this together with `private_key_pem` to sign
+    /// with a different key, if the actor has multiple keys.
+    fn private_key_id(&self) -> String {
+        main_key_id(&self.id())
+    }
+
     /// The inbox where activities for this user should be sent to
     fn inbox(&self) -> Url;
 
@@ -253,6 +326,26 @@ pub trait Actor: Object + Send + 'static {
         PublicKey::new(self.id(), self.public_key_pem().to_string())
     }
 
+    /// All public keys of the actor, which are used to verify signatures of incoming activities.
+    ///
+    /// The key whose `id` matches the `keyId` of the signature is used for verification. Defaults
+    /// to the single key from [Actor::public_key]. Override this if the actor publishes multiple
+    /// keys, or if the key ids of remote actors are stored in the database. Signatures whose
+    /// `keyId` is not listed here or in [Actor::assertion_methods] are rejected, unless the key is
+    /// published in a separate document.
+    fn public_keys(&self) -> Vec<PublicKey> {
+        vec![self.public_key()]
+    }
+
+    /// Ed25519 keys of the actor, which are used to verify object integrity proofs and HTTP
+    /// signatures. These should be federated in the `assertionMethod` field, see
+    /// [crate::integrity_proofs].
+    ///
+    /// Defaults to no keys, in which case the keys are fetched when verifying a proof.
+    fn assertion_methods(&self) -> Vec<Multikey> {
+        vec![]
+    }
+
     /// The actor's shared inbox, if any
     fn shared_inbox(&self) -> Option<Url> {
         None
@@ -390,6 +483,10 @@ pub mod tests {
 
     pub static DB_USER_KEYPAIR: Lazy<Keypair> = Lazy::new(|| generate_actor_keypair().unwrap());
 
+    #[cfg(feature = "integrity-proofs")]
+    pub static DB_USER_ED25519_KEYPAIR: Lazy<crate::integrity_proofs::Ed25519Keypair> =
+        Lazy::new(crate::integrity_proofs::generate_ed25519_keypair);
+
     pub static DB_USER: Lazy<DbUser> = Lazy::new(|| DbUser {
         name: String::new(),
         federation_id: "https://localhost/123".parse().unwrap(),
@@ -464,6 +561,14 @@ pub mod tests {
         fn inbox(&self) -> Url {
             self.inbox.clone()
         }
+
+        #[cfg(feature = "integrity-proofs")]
+        fn assertion_methods(&self) -> Vec<Multikey> {
+            vec![Multikey::new(
+                self.id(),
+                DB_USER_ED25519_KEYPAIR.public_key.clone(),
+            )]
+        }
     }
 
     #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -510,6 +615,10 @@ pub mod tests {
         type Kind = Note;
         type Error = Error;
 
+        fn cache_copy(&self) -> Option<Self> {
+            Some(self.clone())
+        }
+
         async fn read_from_id(
             _: Url,
             _: &Data<Self::DataType>,
@@ -533,4 +642,17 @@ pub mod tests {
             todo!()
         }
     }
+
+    /// Starts a local HTTP server with the router returned by `app`, and returns its port. The
+    /// port is passed to `app`, so that responses can contain urls of the server.
+    #[cfg(test)]
+    pub fn start_test_server(app: impl FnOnce(u16) -> axum::Router) -> u16 {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app(port).into_make_service());
+        tokio::spawn(server);
+        port
+    }
 }